@@ -1,5 +1,8 @@
 use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
-use txs_eng::{Amount, ClientId, Engine, Transaction, TxId};
+use txs_eng::engine::ShardedEngine;
+use txs_eng::{Amount, Asset, ClientId, Engine, EngineConfig, Metadata, Transaction, TxId};
+
+const USD: Asset = Asset::new("USD").unwrap();
 
 /// Generates valid transaction sequences for benchmarking.
 ///
@@ -30,7 +33,7 @@ impl TxGenerator {
 
     /// Total number of transactions this generator will produce
     pub fn total_transactions(&self) -> u64 {
-        self.num_clients as u64 * self.txs_per_client as u64
+        self.num_clients * self.txs_per_client as u64
     }
 }
 
@@ -50,17 +53,26 @@ impl Iterator for TxGenerator {
             0 => Transaction::Deposit {
                 client: self.current_client,
                 tx: tx_id,
+                asset: USD,
                 amount: Amount::from_scaled(1_000_000), // 100.0
+                timestamp: None,
+                metadata: Metadata::default(),
             },
             1 => Transaction::Deposit {
                 client: self.current_client,
                 tx: tx_id,
+                asset: USD,
                 amount: Amount::from_scaled(500_000), // 50.0
+                timestamp: None,
+                metadata: Metadata::default(),
             },
             _ => Transaction::Withdrawal {
                 client: self.current_client,
                 tx: tx_id,
+                asset: USD,
                 amount: Amount::from_scaled(300_000), // 30.0
+                timestamp: None,
+                metadata: Metadata::default(),
             },
         };
 
@@ -77,7 +89,7 @@ impl Iterator for TxGenerator {
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         let total = self.total_transactions() as usize;
-        let done = (self.current_client.saturating_sub(1) as u64 * self.txs_per_client as u64
+        let done = (self.current_client.saturating_sub(1) * self.txs_per_client as u64
             + self.current_step as u64) as usize;
         let remaining = total.saturating_sub(done);
         (remaining, Some(remaining))
@@ -136,8 +148,13 @@ impl Iterator for TxGeneratorWithDisputes {
                 if self.recent_deposits.len() > 1 {
                     let idx = self.recent_deposits.len() / 2;
                     let (client, tx_id) = self.recent_deposits.remove(idx);
-                    self.pending_disputes
-                        .push(Transaction::Dispute { client, tx: tx_id });
+                    self.pending_disputes.push(Transaction::Dispute {
+                        client,
+                        tx: tx_id,
+                        amount: None,
+                        timestamp: None,
+                        metadata: Metadata::default(),
+                    });
                 }
             }
         }
@@ -240,6 +257,42 @@ fn bench_large_scale(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_sharded(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sharded");
+    group.sample_size(10);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    // 1M transactions of 1000 clients, streamed as in `Engine::run`
+    group.bench_function("1M_single", |b| {
+        b.iter(|| {
+            let mut engine = Engine::new();
+            let generator = TxGenerator::new(1000, 1_000);
+            runtime
+                .block_on(engine.run(tokio_stream::iter(generator)))
+                .unwrap();
+            engine
+        });
+    });
+
+    for shards in [2, 4, 8] {
+        let label = format!("1M_{shards}_shards");
+        group.bench_with_input(
+            BenchmarkId::from_parameter(&label),
+            &shards,
+            |b, &shards| {
+                b.iter(|| {
+                    let mut engine = ShardedEngine::new(EngineConfig::default(), shards);
+                    let generator = TxGenerator::new(1000, 1_000);
+                    runtime.block_on(engine.run(tokio_stream::iter(generator)));
+                    engine
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
 fn bench_stress_test(c: &mut Criterion) {
     let mut group = c.benchmark_group("stress_test");
     group.sample_size(10);
@@ -266,6 +319,7 @@ criterion_group!(
     bench_mixed_transactions,
     bench_with_disputes,
     bench_large_scale,
+    bench_sharded,
 );
 
 criterion_group!(
@@ -1,71 +1,556 @@
 //! Fixed-point decimal type for balance calculation.
 
+use std::cmp::Ordering;
 use std::fmt;
+use std::str::FromStr;
 
-/// Fixed-point decimal with 4 decimal places, stored as a scaled integer.
+use serde::de::{self, DeserializeSeed, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Fixed-point decimal stored as a scaled integer.
 ///
-/// Uses i64 internally to avoid any floating-point precision issues.
-/// The value is scaled by 10,000, so `1.0` is stored as `10000`.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
-pub struct Amount(i64);
+/// Uses i64 internally to avoid any floating-point precision issues, along with
+/// the number of decimal places of the [`Precision`] the amount was parsed with
+/// (4 by default, so `1.0` is stored as `10000`). Amounts compare by value across
+/// scales, and are formatted at their own scale.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Amount {
+    value: i64,
+    scale: u8,
+}
 
-impl Amount {
-    const SCALE: i64 = 10_000;
+/// Rounding policy applied when a value has more decimal places than the target scale.
+///
+/// Deserialized from its snake case name (e.g. `"half_even"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Reject values that can't be represented exactly.
+    #[default]
+    Exact,
+    /// Round to nearest, ties away from zero (`2.5 -> 3`, `-2.5 -> -3`).
+    HalfAwayFromZero,
+    /// Round to nearest, ties to even, a.k.a. banker's rounding (`2.5 -> 2`, `3.5 -> 4`).
+    HalfEven,
+    /// Truncate the extra decimal places (`2.9 -> 2`, `-2.9 -> -2`).
+    TowardZero,
+}
+
+impl RoundingMode {
+    /// Decide whether a truncated value must be moved one unit away from zero.
+    ///
+    /// `discarded` compares the (non-zero) discarded part to half a unit.
+    /// Returns `None` if rounding is not allowed.
+    fn round_away(self, truncated_is_odd: bool, discarded: Ordering) -> Option<bool> {
+        match self {
+            RoundingMode::Exact => None,
+            RoundingMode::HalfAwayFromZero => Some(discarded.is_ge()),
+            RoundingMode::HalfEven => {
+                Some(discarded.is_gt() || (discarded.is_eq() && truncated_is_odd))
+            }
+            RoundingMode::TowardZero => Some(false),
+        }
+    }
+}
+
+/// Divide `n` by a positive `d`, rounding the quotient according to `mode`.
+///
+/// Returns `None` if the division is inexact and `mode` is [`RoundingMode::Exact`].
+pub(crate) fn div_round(n: i128, d: i128, mode: RoundingMode) -> Option<i128> {
+    let (quotient, remainder) = (n / d, n % d);
+    if remainder == 0 {
+        return Some(quotient);
+    }
+    let discarded = (2 * remainder.abs()).cmp(&d);
+    let away = mode.round_away(quotient % 2 != 0, discarded)?;
+    Some(if away {
+        quotient + n.signum()
+    } else {
+        quotient
+    })
+}
+
+/// Number of decimal places and rounding policy used to parse, convert and format amounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    scale: u32,
+    rounding: RoundingMode,
+}
 
+impl Precision {
+    /// Maximum number of decimal places (the scale factor must fit in an i64).
+    pub const MAX_SCALE: u32 = 18;
+
+    /// 4 decimal places, rejecting anything more precise.
+    pub const DEFAULT: Precision = Precision {
+        scale: 4,
+        rounding: RoundingMode::Exact,
+    };
+
+    /// Create a precision, returns `None` if `scale` exceeds [`Precision::MAX_SCALE`].
+    pub const fn new(scale: u32, rounding: RoundingMode) -> Option<Self> {
+        if scale > Self::MAX_SCALE {
+            return None;
+        }
+        Some(Self { scale, rounding })
+    }
+
+    /// Returns the number of decimal places.
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Returns the rounding policy.
+    pub fn rounding(&self) -> RoundingMode {
+        self.rounding
+    }
+
+    /// Returns the same scale with another rounding policy.
+    pub fn with_rounding(self, rounding: RoundingMode) -> Self {
+        Self { rounding, ..self }
+    }
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Deserialized from a `scale` and an optional `rounding` (exact by default).
+impl<'de> Deserialize<'de> for Precision {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Fields {
+            scale: u32,
+            #[serde(default)]
+            rounding: RoundingMode,
+        }
+
+        let Fields { scale, rounding } = Fields::deserialize(deserializer)?;
+        Precision::new(scale, rounding).ok_or_else(|| {
+            de::Error::custom(format!(
+                "scale {scale} exceeds the maximum of {}",
+                Precision::MAX_SCALE
+            ))
+        })
+    }
+}
+
+impl Amount {
     /// Create an Amount from a floating-point value.
     ///
-    /// The value is rounded to 4 decimal places.
-    pub fn from_float(value: f64) -> Self {
-        Amount((value * Self::SCALE as f64).round() as i64)
+    /// The value is rounded half away from zero to 4 decimal places. Returns an
+    /// error if the value is not finite or out of range.
+    pub fn from_float(value: f64) -> Result<Self, ParseAmountError> {
+        let precision = Precision::DEFAULT.with_rounding(RoundingMode::HalfAwayFromZero);
+        Self::from_float_with(value, precision)
+    }
+
+    /// Create an Amount from a floating-point value at the given precision.
+    ///
+    /// The float is converted from its shortest decimal representation (`0.1` is
+    /// read as `"0.1"`, not as its binary approximation), then parsed with
+    /// [`Amount::parse`].
+    pub fn from_float_with(value: f64, precision: Precision) -> Result<Self, ParseAmountError> {
+        Self::parse(&value.to_string(), precision)
     }
 
-    /// Create an Amount from a pre-scaled integer value.
+    /// Create an Amount from a pre-scaled integer value at the default precision
+    /// (4 decimal places).
     pub fn from_scaled(value: i64) -> Self {
-        Amount(value)
+        Self::from_scaled_at(value, Precision::DEFAULT)
+    }
+
+    /// Create an Amount from an integer value pre-scaled at the given precision.
+    pub fn from_scaled_at(value: i64, precision: Precision) -> Self {
+        Amount {
+            value,
+            scale: precision.scale as u8,
+        }
+    }
+
+    /// Returns the pre-scaled integer value, at [`Amount::scale`].
+    pub fn scaled(self) -> i64 {
+        self.value
+    }
+
+    /// Returns the number of decimal places of the amount.
+    pub fn scale(self) -> u32 {
+        u32::from(self.scale)
+    }
+
+    /// Returns an amount at the same scale with another scaled value.
+    pub(crate) fn with_scaled(self, value: i64) -> Self {
+        Amount { value, ..self }
+    }
+
+    /// Scaled value at a scale no smaller than the amount's, always fits in an i128.
+    fn widen(self, scale: u8) -> i128 {
+        i128::from(self.value) * 10i128.pow(u32::from(scale - self.scale))
+    }
+
+    /// Apply `op` to both amounts at the larger of their scales.
+    fn checked_op(self, rhs: Self, op: fn(i128, i128) -> i128) -> Option<Self> {
+        let scale = self.scale.max(rhs.scale);
+        let value = op(self.widen(scale), rhs.widen(scale));
+        i64::try_from(value)
+            .ok()
+            .map(|value| Amount { value, scale })
+    }
+
+    /// Parse a decimal string such as `"-12.3456"` exactly, digit by digit.
+    ///
+    /// Accepts an optional sign, and an optional fractional part. Decimal places
+    /// beyond `precision.scale()` are rounded with `precision.rounding()`
+    /// (trailing zeros are always accepted as they don't change the value).
+    /// No floating-point conversion is involved.
+    pub fn parse(s: &str, precision: Precision) -> Result<Self, ParseAmountError> {
+        let out_of_range = || ParseAmountError::OutOfRange(s.to_string());
+        let (negative, whole, frac) =
+            split_decimal(s).ok_or_else(|| ParseAmountError::Invalid(s.to_string()))?;
+
+        let scale = precision.scale as usize;
+        let frac = frac.trim_end_matches('0');
+        let (kept, discarded) = frac.split_at(frac.len().min(scale));
+
+        // Accumulate the scaled magnitude: whole digits, then fractional digits
+        // right-padded to the number of decimal places.
+        let padding = std::iter::repeat_n(b'0', scale - kept.len());
+        let mut magnitude: u64 = 0;
+        for b in whole.bytes().chain(kept.bytes()).chain(padding) {
+            magnitude = magnitude
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(u64::from(b - b'0')))
+                .ok_or_else(out_of_range)?;
+        }
+
+        // Round on the discarded digits (no trailing zeros, so it is never zero)
+        if !discarded.is_empty() {
+            let half = match discarded.as_bytes()[0].cmp(&b'5') {
+                Ordering::Equal if discarded.len() > 1 => Ordering::Greater,
+                ordering => ordering,
+            };
+            let away = precision
+                .rounding
+                .round_away(!magnitude.is_multiple_of(2), half)
+                .ok_or_else(|| ParseAmountError::TooManyDecimals(s.to_string(), precision.scale))?;
+            if away {
+                magnitude = magnitude.checked_add(1).ok_or_else(out_of_range)?;
+            }
+        }
+
+        let value = if negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        };
+        value
+            .map(|value| Self::from_scaled_at(value, precision))
+            .ok_or_else(out_of_range)
+    }
+
+    /// Convert the amount to the scale of `to`, rounding with `to.rounding()` when
+    /// decimal places are dropped.
+    ///
+    /// Returns `None` on overflow, or if rounding is needed but not allowed.
+    pub fn rescale(self, to: Precision) -> Option<Self> {
+        let (from, value) = (self.scale(), i128::from(self.value));
+        let rescaled = match from.cmp(&to.scale) {
+            Ordering::Equal => value,
+            Ordering::Less => value * 10i128.pow(to.scale - from),
+            Ordering::Greater => div_round(value, 10i128.pow(from - to.scale), to.rounding)?,
+        };
+        i64::try_from(rescaled)
+            .ok()
+            .map(|value| Self::from_scaled_at(value, to))
+    }
+
+    /// Returns an object that formats the amount at the given precision.
+    pub fn display(self, precision: Precision) -> AmountDisplay {
+        AmountDisplay {
+            amount: self,
+            precision,
+        }
+    }
+
+    /// Checked addition at the larger scale of both amounts, returns `None` on overflow.
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, |a, b| a + b)
+    }
+
+    /// Checked subtraction at the larger scale of both amounts, returns `None` on
+    /// overflow.
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.checked_op(rhs, |a, b| a - b)
+    }
+}
+
+impl PartialEq for Amount {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Amount {}
+
+impl PartialOrd for Amount {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compares values, `1.5` at 2 decimal places equals `1.5` at 8.
+impl Ord for Amount {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.widen(scale).cmp(&other.widen(scale))
+    }
+}
+
+/// Split a decimal string into its sign, whole and fractional digits, returns `None`
+/// if it is not a decimal.
+fn split_decimal(s: &str) -> Option<(bool, &str, &str)> {
+    let (negative, digits) = match s.as_bytes().first() {
+        Some(b'-') => (true, &s[1..]),
+        Some(b'+') => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && frac.is_empty() {
+        return None;
+    }
+    whole
+        .bytes()
+        .chain(frac.bytes())
+        .all(|b| b.is_ascii_digit())
+        .then_some((negative, whole, frac))
+}
+
+/// Decimal number not yet scaled to a [`Precision`], such as the amount of a partial
+/// dispute, which is in the asset of the disputed transaction.
+///
+/// Kept as its decimal text, converted exactly with [`Decimal::to_amount`] once the
+/// precision is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decimal(String);
+
+impl Decimal {
+    /// Convert to an amount at the given precision, see [`Amount::parse`].
+    pub fn to_amount(&self, precision: Precision) -> Result<Amount, ParseAmountError> {
+        Amount::parse(&self.0, precision)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseAmountError;
+
+    /// Check the syntax of a decimal string, which is scaled later.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match split_decimal(s) {
+            Some(_) => Ok(Decimal(s.to_string())),
+            None => Err(ParseAmountError::Invalid(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// An amount at a known precision, as its exact decimal text.
+impl From<AmountDisplay> for Decimal {
+    fn from(amount: AmountDisplay) -> Self {
+        Decimal(amount.to_string())
+    }
+}
+
+/// Error returned when parsing an [`Amount`] from its decimal representation.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseAmountError {
+    #[error("invalid amount '{0}'")]
+    Invalid(String),
+    #[error("amount '{0}' is out of range")]
+    OutOfRange(String),
+    #[error("amount '{0}' has more than {1} decimal places")]
+    TooManyDecimals(String, u32),
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    /// Parse a decimal string exactly, at the scale of its fractional digits
+    /// (trailing zeros aside), so that it round-trips through [`fmt::Display`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let decimals = split_decimal(s).map_or(0, |(_, _, frac)| frac.trim_end_matches('0').len());
+        let precision = u32::try_from(decimals)
+            .ok()
+            .and_then(|scale| Precision::new(scale, RoundingMode::Exact))
+            .ok_or_else(|| {
+                ParseAmountError::TooManyDecimals(s.to_string(), Precision::MAX_SCALE)
+            })?;
+        Self::parse(s, precision)
     }
 }
 
+/// Formats the amount at its scale, omitting trailing zeros of the fractional part.
 impl fmt::Display for Amount {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let sign = if self.0 < 0 { "-" } else { "" };
-        let abs = self.0.abs();
-        let whole = abs / Self::SCALE;
-        let frac = abs % Self::SCALE;
+        let factor = 10u64.pow(self.scale());
+        let sign = if self.value < 0 { "-" } else { "" };
+        let abs = self.value.unsigned_abs();
+        let whole = abs / factor;
+        let frac = abs % factor;
         if frac == 0 {
             write!(f, "{sign}{whole}")
         } else {
-            let frac_str = format!("{frac:04}");
+            let width = self.scale as usize;
+            let frac_str = format!("{frac:0width$}");
             let trimmed = frac_str.trim_end_matches('0');
             write!(f, "{sign}{whole}.{trimmed}")
         }
     }
 }
 
-impl std::ops::Add for Amount {
-    type Output = Self;
+/// Helper to format an [`Amount`] at a given [`Precision`], see [`Amount::display`].
+///
+/// The amount is rescaled with the rounding of the precision, or formatted at its own
+/// scale if it can't be. Trailing zeros of the fractional part are omitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountDisplay {
+    amount: Amount,
+    precision: Precision,
+}
+
+impl AmountDisplay {
+    /// Returns the formatted amount.
+    pub fn amount(self) -> Amount {
+        self.amount
+    }
+
+    /// Returns the precision the amount is formatted at.
+    pub fn precision(self) -> Precision {
+        self.precision
+    }
+}
 
-    fn add(self, rhs: Self) -> Self::Output {
-        Amount(self.0 + rhs.0)
+impl fmt::Display for AmountDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let amount = self.amount.rescale(self.precision).unwrap_or(self.amount);
+        fmt::Display::fmt(&amount, f)
     }
 }
 
-impl std::ops::AddAssign for Amount {
-    fn add_assign(&mut self, rhs: Self) {
-        self.0 += rhs.0;
+/// Serialized as its canonical decimal string at its scale (matching
+/// [`fmt::Display`]), which round-trips exactly. Use [`Amount::display`] to serialize it
+/// at another precision, and [`scaled`] for an integer representation.
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
-impl std::ops::Sub for Amount {
-    type Output = Self;
+/// Deserialized from a decimal string at the scale of its digits, see [`FromStr`].
+/// Deserialize with a [`Precision`] to parse it at a given precision instead.
+///
+/// Numbers are rejected, as they may already have gone through floating point.
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
 
-    fn sub(self, rhs: Self) -> Self::Output {
-        Amount(self.0 - rhs.0)
+        impl Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(AmountVisitor)
     }
 }
 
-impl std::ops::SubAssign for Amount {
-    fn sub_assign(&mut self, rhs: Self) {
-        self.0 -= rhs.0;
+/// Serialized as the decimal string formatted at its precision.
+impl Serialize for AmountDisplay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes an [`Amount`] from a decimal string at this precision, see
+/// [`Amount::parse`]. The counterpart of serializing an [`AmountDisplay`].
+///
+/// Numbers are rejected, as they may already have gone through floating point.
+///
+/// ```
+/// # use txs_eng::{Amount, Precision, RoundingMode};
+/// use serde::de::DeserializeSeed;
+///
+/// let sats = Precision::new(8, RoundingMode::Exact).unwrap();
+/// let mut json = serde_json::Deserializer::from_str(r#""1.5""#);
+/// let amount = sats.deserialize(&mut json).unwrap();
+/// assert_eq!(amount, Amount::from_scaled_at(150_000_000, sats));
+/// assert_eq!(serde_json::to_string(&amount).unwrap(), r#""1.5""#);
+/// ```
+impl<'de> DeserializeSeed<'de> for Precision {
+    type Value = Amount;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Amount, D::Error> {
+        struct AmountVisitor(Precision);
+
+        impl Visitor<'_> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a decimal string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                Amount::parse(v, self.0).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(AmountVisitor(self))
+    }
+}
+
+/// Opt-in serde representation of an [`Amount`] as its scaled integer and scale, for
+/// compact binary formats.
+///
+/// ```
+/// # use txs_eng::Amount;
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Balance {
+///     #[serde(with = "txs_eng::amount::scaled")]
+///     available: Amount,
+/// }
+/// ```
+pub mod scaled {
+    use super::{Amount, Precision};
+    use serde::de::{self, Deserialize, Deserializer};
+    use serde::{Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &Amount, serializer: S) -> Result<S::Ok, S::Error> {
+        (amount.value, amount.scale).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        let (value, scale) = <(i64, u8)>::deserialize(deserializer)?;
+        if u32::from(scale) > Precision::MAX_SCALE {
+            return Err(de::Error::custom(format!(
+                "scale {scale} exceeds the maximum of {}",
+                Precision::MAX_SCALE
+            )));
+        }
+        Ok(Amount { value, scale })
     }
 }
 
@@ -76,72 +561,438 @@ mod tests {
     #[test]
     fn from_scaled_preserves_value() {
         let amount = Amount::from_scaled(123456);
-        assert_eq!(amount, Amount(123456));
+        assert_eq!(amount.scaled(), 123456);
+        assert_eq!(amount.scale(), 4);
     }
 
     #[test]
     fn from_float_converts_correctly() {
-        assert_eq!(Amount::from_float(100.0), Amount::from_scaled(1_000_000));
-        assert_eq!(Amount::from_float(1.5), Amount::from_scaled(15_000));
-        assert_eq!(Amount::from_float(0.0001), Amount::from_scaled(1));
+        assert_eq!(
+            Amount::from_float(100.0),
+            Ok(Amount::from_scaled(1_000_000))
+        );
+        assert_eq!(Amount::from_float(1.5), Ok(Amount::from_scaled(15_000)));
+        assert_eq!(Amount::from_float(0.0001), Ok(Amount::from_scaled(1)));
     }
 
     #[test]
     fn from_float_rounds_correctly() {
-        assert_eq!(Amount::from_float(1.23456), Amount::from_scaled(12346));
-        assert_eq!(Amount::from_float(1.23454), Amount::from_scaled(12345));
+        assert_eq!(Amount::from_float(1.23456), Ok(Amount::from_scaled(12346)));
+        assert_eq!(Amount::from_float(1.23454), Ok(Amount::from_scaled(12345)));
     }
 
     #[test]
     fn from_float_handles_negative() {
-        assert_eq!(Amount::from_float(-50.25), Amount::from_scaled(-502_500));
+        assert_eq!(
+            Amount::from_float(-50.25),
+            Ok(Amount::from_scaled(-502_500))
+        );
     }
 
     #[test]
-    fn display_formats_positive() {
-        assert_eq!(Amount::from_scaled(1_000_000).to_string(), "100");
-        assert_eq!(Amount::from_scaled(15_000).to_string(), "1.5");
-        assert_eq!(Amount::from_scaled(1).to_string(), "0.0001");
-        assert_eq!(Amount::from_scaled(0).to_string(), "0");
+    fn from_float_rejects_non_finite_and_out_of_range() {
+        assert!(matches!(
+            Amount::from_float(f64::NAN),
+            Err(ParseAmountError::Invalid(_))
+        ));
+        assert!(Amount::from_float(f64::NEG_INFINITY).is_err());
+        assert!(matches!(
+            Amount::from_float(1e300),
+            Err(ParseAmountError::OutOfRange(_))
+        ));
     }
 
     #[test]
-    fn display_formats_negative() {
-        assert_eq!(Amount::from_scaled(-502_500).to_string(), "-50.25");
-        assert_eq!(Amount::from_scaled(-1).to_string(), "-0.0001");
+    fn parse_exact_decimals() {
+        assert_eq!("100".parse(), Ok(Amount::from_scaled(1_000_000)));
+        assert_eq!("1.5".parse(), Ok(Amount::from_scaled(15_000)));
+        assert_eq!("0.0001".parse(), Ok(Amount::from_scaled(1)));
+        assert_eq!("-50.25".parse(), Ok(Amount::from_scaled(-502_500)));
+        assert_eq!("+2.".parse(), Ok(Amount::from_scaled(20_000)));
+        assert_eq!(".5".parse(), Ok(Amount::from_scaled(5_000)));
+        assert_eq!("1.50000".parse(), Ok(Amount::from_scaled(15_000)));
     }
 
     #[test]
-    fn default_is_zero() {
-        assert_eq!(Amount::default(), Amount::from_scaled(0));
+    fn parse_does_not_lose_precision() {
+        // Not representable exactly as f64
+        assert_eq!(
+            "922337203685477.5807".parse(),
+            Ok(Amount::from_scaled(i64::MAX))
+        );
+        assert_eq!(
+            "-922337203685477.5808".parse(),
+            Ok(Amount::from_scaled(i64::MIN))
+        );
     }
 
     #[test]
-    fn add() {
-        let a = Amount::from_scaled(100);
-        let b = Amount::from_scaled(50);
-        assert_eq!(a + b, Amount::from_scaled(150));
+    fn parse_rejects_malformed() {
+        for input in [
+            "", "-", ".", "abc", "1.2.3", "1,5", " 1", "1e5", "--1", "1.-5",
+        ] {
+            assert_eq!(
+                input.parse::<Amount>(),
+                Err(ParseAmountError::Invalid(input.to_string())),
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range() {
+        for input in [
+            "922337203685477.5808",
+            "-922337203685477.5809",
+            "99999999999999999999999",
+        ] {
+            assert_eq!(
+                input.parse::<Amount>(),
+                Err(ParseAmountError::OutOfRange(input.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_at_the_scale_of_the_digits() {
+        let amount = "1.23456".parse::<Amount>().unwrap();
+        assert_eq!((amount.scaled(), amount.scale()), (123_456, 5));
+        let amount = "12.500".parse::<Amount>().unwrap();
+        assert_eq!((amount.scaled(), amount.scale()), (125, 1));
+    }
+
+    #[test]
+    fn parse_rejects_too_many_decimals() {
+        let input = "0.0000000000000000001";
+        assert_eq!(
+            input.parse::<Amount>(),
+            Err(ParseAmountError::TooManyDecimals(input.to_string(), 18))
+        );
+        assert_eq!(
+            Amount::parse("1.23456", Precision::DEFAULT),
+            Err(ParseAmountError::TooManyDecimals("1.23456".to_string(), 4))
+        );
+    }
+
+    #[test]
+    fn parse_display_round_trip() {
+        for scaled in [0, 1, -1, 15_000, -502_500, i64::MAX, i64::MIN] {
+            let amount = Amount::from_scaled(scaled);
+            assert_eq!(amount.to_string().parse(), Ok(amount));
+            let amount = Amount::from_scaled_at(scaled, precision(8, RoundingMode::Exact));
+            assert_eq!(amount.to_string().parse(), Ok(amount));
+        }
+    }
+
+    fn precision(scale: u32, rounding: RoundingMode) -> Precision {
+        Precision::new(scale, rounding).unwrap()
+    }
+
+    fn parse_all(inputs: &[&str], precision: Precision) -> Vec<i64> {
+        inputs
+            .iter()
+            .map(|s| Amount::parse(s, precision).unwrap().scaled())
+            .collect()
+    }
+
+    #[test]
+    fn precision_rejects_oversized_scale() {
+        assert!(Precision::new(18, RoundingMode::Exact).is_some());
+        assert!(Precision::new(19, RoundingMode::Exact).is_none());
+    }
+
+    #[test]
+    fn parse_at_configured_scale() {
+        let cents = precision(2, RoundingMode::Exact);
+        assert_eq!(Amount::parse("1.5", cents).unwrap().scaled(), 150);
+        assert_eq!(Amount::parse("12", cents).unwrap().scaled(), 1_200);
+        assert_eq!(
+            Amount::parse("1.005", cents),
+            Err(ParseAmountError::TooManyDecimals("1.005".to_string(), 2))
+        );
+
+        let sats = precision(8, RoundingMode::Exact);
+        assert_eq!(
+            Amount::parse("0.00000001", sats),
+            Ok(Amount::from_scaled_at(1, sats))
+        );
+        assert_eq!(
+            Amount::parse("92233720368.54775807", sats),
+            Ok(Amount::from_scaled_at(i64::MAX, sats))
+        );
+        assert!(matches!(
+            Amount::parse("92233720368.54775808", sats),
+            Err(ParseAmountError::OutOfRange(_))
+        ));
+
+        let whole = precision(0, RoundingMode::Exact);
+        assert_eq!(Amount::parse("42", whole).unwrap().scaled(), 42);
+        assert_eq!(Amount::parse("42", whole).unwrap().scale(), 0);
+    }
+
+    #[test]
+    fn parse_rounding_modes() {
+        let inputs = ["2.5", "3.5", "-2.5", "2.51", "2.49", "-2.9", "0.05"];
+        let at = |rounding| parse_all(&inputs, precision(0, rounding));
+
+        assert_eq!(at(RoundingMode::HalfAwayFromZero), [3, 4, -3, 3, 2, -3, 0]);
+        assert_eq!(at(RoundingMode::HalfEven), [2, 4, -2, 3, 2, -3, 0]);
+        assert_eq!(at(RoundingMode::TowardZero), [2, 3, -2, 2, 2, -2, 0]);
+        assert!(Amount::parse("2.5", precision(0, RoundingMode::Exact)).is_err());
+    }
+
+    #[test]
+    fn parse_rounding_long_fraction() {
+        let cents = precision(2, RoundingMode::HalfEven);
+        assert_eq!(
+            parse_all(&["1.125", "1.135", "1.1250000001", "-1.125"], cents),
+            [112, 114, 113, -112]
+        );
+    }
+
+    #[test]
+    fn parse_rounding_can_overflow() {
+        let whole = precision(0, RoundingMode::HalfAwayFromZero);
+        assert!(matches!(
+            Amount::parse("9223372036854775807.5", whole),
+            Err(ParseAmountError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn from_float_with_uses_decimal_representation() {
+        let cents = precision(2, RoundingMode::HalfEven);
+        assert_eq!(
+            Amount::from_float_with(0.125, cents),
+            Ok(Amount::from_scaled_at(12, cents))
+        );
+        assert_eq!(
+            Amount::from_float_with(1.1, precision(2, RoundingMode::Exact)),
+            Ok(Amount::from_scaled_at(110, cents))
+        );
+        assert!(Amount::from_float_with(f64::NAN, cents).is_err());
+        assert!(Amount::from_float_with(f64::INFINITY, cents).is_err());
     }
 
     #[test]
-    fn add_assign() {
-        let mut a = Amount::from_scaled(100);
-        a += Amount::from_scaled(50);
-        assert_eq!(a, Amount::from_scaled(150));
+    fn decimal_is_scaled_later() {
+        let decimal: Decimal = "0.12345".parse().unwrap();
+        let sats = precision(8, RoundingMode::Exact);
+        assert_eq!(
+            decimal.to_amount(sats),
+            Ok(Amount::from_scaled_at(12_345_000, sats))
+        );
+        assert_eq!(
+            decimal.to_amount(Precision::DEFAULT),
+            Err(ParseAmountError::TooManyDecimals("0.12345".to_string(), 4))
+        );
+        assert_eq!(
+            "1.2.3".parse::<Decimal>(),
+            Err(ParseAmountError::Invalid("1.2.3".to_string()))
+        );
+
+        let cents = precision(2, RoundingMode::Exact);
+        let amount = Amount::from_scaled_at(-150, cents).display(cents);
+        assert_eq!(Decimal::from(amount).to_string(), "-1.5");
+    }
+
+    #[test]
+    fn rescale_between_precisions() {
+        let four = Precision::DEFAULT;
+        let cents = precision(2, RoundingMode::HalfEven);
+        let sats = precision(8, RoundingMode::Exact);
+
+        let amount = Amount::from_scaled(12_350); // 1.235
+        let rescaled = amount.rescale(cents).unwrap();
+        assert_eq!((rescaled.scaled(), rescaled.scale()), (124, 2));
+        assert_eq!(
+            amount
+                .rescale(cents.with_rounding(RoundingMode::TowardZero))
+                .map(Amount::scaled),
+            Some(123)
+        );
+        assert_eq!(amount.rescale(four), Some(amount));
+        assert_eq!(amount.rescale(precision(2, RoundingMode::Exact)), None);
+        let rescaled = amount.rescale(sats).unwrap();
+        assert_eq!((rescaled.scaled(), rescaled.scale()), (123_500_000, 8));
+        assert_eq!(rescaled, amount);
+        assert_eq!(Amount::from_scaled(i64::MAX).rescale(sats), None);
+    }
+
+    #[test]
+    fn div_round_modes() {
+        assert_eq!(div_round(7, 2, RoundingMode::HalfEven), Some(4));
+        assert_eq!(div_round(5, 2, RoundingMode::HalfEven), Some(2));
+        assert_eq!(div_round(-5, 2, RoundingMode::HalfAwayFromZero), Some(-3));
+        assert_eq!(div_round(-1, 3, RoundingMode::HalfAwayFromZero), Some(0));
+        assert_eq!(div_round(-2, 3, RoundingMode::HalfAwayFromZero), Some(-1));
+        assert_eq!(div_round(-5, 3, RoundingMode::TowardZero), Some(-1));
+        assert_eq!(div_round(6, 3, RoundingMode::Exact), Some(2));
+        assert_eq!(div_round(5, 3, RoundingMode::Exact), None);
+    }
+
+    #[test]
+    fn display_at_configured_scale() {
+        let cents = precision(2, RoundingMode::Exact);
+        let sats = precision(8, RoundingMode::Exact);
+        let whole = precision(0, RoundingMode::Exact);
+        assert_eq!(Amount::from_scaled_at(150, cents).to_string(), "1.5");
+        assert_eq!(Amount::from_scaled_at(-1, cents).to_string(), "-0.01");
+        assert_eq!(Amount::from_scaled_at(1, sats).to_string(), "0.00000001");
+        assert_eq!(Amount::from_scaled_at(150_000_000, sats).to_string(), "1.5");
+        assert_eq!(Amount::from_scaled_at(42, whole).to_string(), "42");
+    }
+
+    #[test]
+    fn display_at_another_precision() {
+        let cents = precision(2, RoundingMode::HalfEven);
+        let amount = Amount::from_scaled(12_350); // 1.235
+        assert_eq!(amount.display(cents).to_string(), "1.24");
+        assert_eq!(
+            Amount::from_scaled_at(150, cents)
+                .display(precision(8, RoundingMode::Exact))
+                .to_string(),
+            "1.5"
+        );
+        // Formatted exactly when rounding is not allowed
+        assert_eq!(
+            amount
+                .display(cents.with_rounding(RoundingMode::Exact))
+                .to_string(),
+            "1.235"
+        );
+    }
+
+    #[test]
+    fn serde_decimal_string_round_trip() {
+        for scaled in [0, 1, -1, 15_000, -502_500, i64::MAX, i64::MIN] {
+            let amount = Amount::from_scaled(scaled);
+            let json = serde_json::to_string(&amount).unwrap();
+            assert_eq!(json, format!("\"{}\"", amount.display(Precision::DEFAULT)));
+            assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+        }
+        assert_eq!(
+            serde_json::to_string(&Amount::from_scaled(-502_500)).unwrap(),
+            "\"-50.25\""
+        );
+    }
+
+    #[test]
+    fn serde_rejects_numbers_and_invalid_strings() {
+        assert!(serde_json::from_str::<Amount>("1.5").is_err());
+        assert!(serde_json::from_str::<Amount>("\"0.0000000000000000001\"").is_err());
+        assert!(serde_json::from_str::<Amount>("\"abc\"").is_err());
+    }
+
+    #[test]
+    fn serde_scaled_integer() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Payload {
+            #[serde(with = "scaled")]
+            amount: Amount,
+        }
+
+        let sats = precision(8, RoundingMode::Exact);
+        for scaled in [0, -502_500, i64::MAX, i64::MIN] {
+            let payload = Payload {
+                amount: Amount::from_scaled_at(scaled, sats),
+            };
+            let json = serde_json::to_string(&payload).unwrap();
+            assert_eq!(json, format!(r#"{{"amount":[{scaled},8]}}"#));
+            let amount = serde_json::from_str::<Payload>(&json).unwrap().amount;
+            assert_eq!((amount.scaled(), amount.scale()), (scaled, 8));
+        }
+        assert!(serde_json::from_str::<Payload>(r#"{"amount":[1,19]}"#).is_err());
+    }
+
+    #[test]
+    fn serde_decimal_string_at_precision() {
+        let sats = precision(8, RoundingMode::Exact);
+        for scaled in [0, 1, -1, 100_000_000, -502_500, i64::MAX, i64::MIN] {
+            let amount = Amount::from_scaled_at(scaled, sats);
+            let json = serde_json::to_string(&amount.display(sats)).unwrap();
+            assert_eq!(json, format!("\"{amount}\""));
+            let mut deserializer = serde_json::Deserializer::from_str(&json);
+            assert_eq!(sats.deserialize(&mut deserializer).unwrap(), amount);
+        }
+        let cents = precision(2, RoundingMode::Exact);
+        let json = serde_json::to_string(&Amount::from_scaled(15_000).display(cents)).unwrap();
+        assert_eq!(json, "\"1.5\"");
     }
 
     #[test]
-    fn sub() {
+    fn serde_decimal_string_at_non_default_scale() {
+        let sats = precision(8, RoundingMode::Exact);
+        let amount = Amount::from_scaled_at(150_000_000, sats);
+        assert_eq!(serde_json::to_string(&amount).unwrap(), "\"1.5\"");
+
+        let amount = Amount::from_scaled_at(123_456_789, sats);
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"1.23456789\"");
+        let parsed = serde_json::from_str::<Amount>(&json).unwrap();
+        assert_eq!((parsed.scaled(), parsed.scale()), (123_456_789, 8));
+    }
+
+    #[test]
+    fn serde_decimal_string_rejects_numbers_and_invalid_strings() {
+        let cents = precision(2, RoundingMode::Exact);
+        for json in ["1.5", "\"1.234\"", "\"abc\""] {
+            let mut deserializer = serde_json::Deserializer::from_str(json);
+            assert!(cents.deserialize(&mut deserializer).is_err(), "{json}");
+        }
+    }
+
+    #[test]
+    fn serde_precision() {
+        let json = r#"{"scale": 2, "rounding": "half_even"}"#;
+        assert_eq!(
+            serde_json::from_str::<Precision>(json).unwrap(),
+            precision(2, RoundingMode::HalfEven)
+        );
+        assert_eq!(
+            serde_json::from_str::<Precision>(r#"{"scale": 8}"#).unwrap(),
+            precision(8, RoundingMode::Exact)
+        );
+        assert!(serde_json::from_str::<Precision>(r#"{"scale": 19}"#).is_err());
+        assert!(serde_json::from_str::<Precision>(r#"{"scale": 2, "rounding": "up"}"#).is_err());
+    }
+
+    /// Format at the default precision.
+    fn fmt(scaled: i64) -> String {
+        Amount::from_scaled(scaled).to_string()
+    }
+
+    #[test]
+    fn display_formats_positive() {
+        assert_eq!(fmt(1_000_000), "100");
+        assert_eq!(fmt(15_000), "1.5");
+        assert_eq!(fmt(1), "0.0001");
+        assert_eq!(fmt(0), "0");
+    }
+
+    #[test]
+    fn display_formats_negative() {
+        assert_eq!(fmt(-502_500), "-50.25");
+        assert_eq!(fmt(-1), "-0.0001");
+    }
+
+    #[test]
+    fn default_is_zero() {
+        assert_eq!(Amount::default(), Amount::from_scaled(0));
+    }
+
+    #[test]
+    fn checked_add() {
         let a = Amount::from_scaled(100);
-        let b = Amount::from_scaled(30);
-        assert_eq!(a - b, Amount::from_scaled(70));
+        assert_eq!(a.checked_add(a), Some(Amount::from_scaled(200)));
+        assert_eq!(Amount::from_scaled(i64::MAX).checked_add(a), None);
     }
 
     #[test]
-    fn sub_assign() {
-        let mut a = Amount::from_scaled(100);
-        a -= Amount::from_scaled(30);
-        assert_eq!(a, Amount::from_scaled(70));
+    fn checked_sub() {
+        let a = Amount::from_scaled(100);
+        assert_eq!(a.checked_sub(a), Some(Amount::from_scaled(0)));
+        assert_eq!(Amount::from_scaled(i64::MIN).checked_sub(a), None);
     }
 
     #[test]
@@ -152,6 +1003,23 @@ mod tests {
         assert!(large > small);
     }
 
+    #[test]
+    fn compares_values_across_scales() {
+        let cents = precision(2, RoundingMode::Exact);
+        let sats = precision(8, RoundingMode::Exact);
+        assert_eq!(
+            Amount::from_scaled_at(150, cents),
+            Amount::from_scaled_at(150_000_000, sats)
+        );
+        assert!(Amount::from_scaled_at(1, cents) > Amount::from_scaled_at(999_999, sats));
+        assert_eq!(Amount::default(), Amount::from_scaled_at(0, sats));
+
+        let sum = Amount::from_scaled_at(150, cents)
+            .checked_add(Amount::from_scaled_at(1, sats))
+            .unwrap();
+        assert_eq!((sum.scaled(), sum.scale()), (150_000_001, 8));
+    }
+
     #[test]
     fn negative_ordering() {
         let negative = Amount::from_scaled(-100);
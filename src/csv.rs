@@ -1,12 +1,20 @@
 //! CSV parsing and export for transactions and account state.
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::fs::File;
 use std::io;
+use std::iter::Enumerate;
+use std::marker::PhantomData;
+use std::mem;
 use std::path::Path;
 use thiserror::Error;
 
-use crate::engine::ClientAccount;
-use crate::{Amount, ClientId, Transaction, TxId};
+use crate::amount::{AmountDisplay, Decimal, ParseAmountError};
+use crate::engine::{Balance, ClientAccount, EngineConfig};
+use crate::intern::{ExternalIds, IdInterner};
+use crate::model::{AdminAction, Asset, Metadata, OperatorId, ParseAssetError, Timestamp};
+use crate::{Amount, Transaction};
 
 /// Errors that can occur when parsing CSV rows.
 #[derive(Debug, Error)]
@@ -19,105 +27,488 @@ pub enum CsvError {
 
     #[error("line {line}: {tx_type} missing amount")]
     MissingAmount { line: usize, tx_type: String },
+
+    #[error("line {line}: transfer missing to_client")]
+    MissingReceiver { line: usize },
+
+    #[error("line {line}: admin {tx_type} missing operator")]
+    MissingOperator { line: usize, tx_type: String },
+
+    #[error("line {line}: admin adjustment missing reason")]
+    MissingReason { line: usize },
+
+    #[error("line {line}: {source}")]
+    InvalidAmount {
+        line: usize,
+        source: ParseAmountError,
+    },
+
+    #[error("line {line}: {source}")]
+    InvalidAsset {
+        line: usize,
+        source: ParseAssetError,
+    },
 }
 
+/// A row that could not be read as a transaction.
+#[derive(Debug, Error)]
+#[error("{error}")]
+pub struct RowError {
+    pub error: CsvError,
+    /// The metadata columns of the row, read even if the rest of it is invalid.
+    pub metadata: Metadata,
+}
+
+/// Input row, client and transaction IDs are numeric unless read as external IDs.
 #[derive(Debug, Deserialize)]
-struct InputRow {
+struct InputRow<Id = u64> {
     r#type: String,
-    client: ClientId,
-    tx: TxId,
-    amount: Option<f64>,
+    client: Id,
+    tx: Id,
+    amount: Option<String>,
+    /// Optional column, rows without an asset use the configured default asset.
+    #[serde(default)]
+    asset: Option<String>,
+    /// Optional column, required by transfers to name the receiving client.
+    #[serde(default)]
+    to_client: Option<Id>,
+    /// Optional column, in seconds since the Unix epoch.
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+    /// Optional column, required by admin transactions.
+    #[serde(default)]
+    operator: Option<OperatorId>,
+    /// Optional column, required by admin adjustments.
+    #[serde(default)]
+    reason: Option<String>,
+    /// Any other column, passed through the engine.
+    #[serde(skip)]
+    metadata: Metadata,
+}
+
+/// Columns read by [`InputRow`], every other column is metadata.
+const COLUMNS: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "asset",
+    "to_client",
+    "timestamp",
+    "operator",
+    "reason",
+];
+
+impl<Id> InputRow<Id> {
+    /// Parse the asset and the required amount column of a money-moving row.
+    ///
+    /// The amount is parsed exactly (no floating-point conversion), at the
+    /// precision configured for the asset.
+    fn money(&self, line: usize, config: &EngineConfig) -> Result<(Asset, Amount), CsvError> {
+        let asset = self.asset(line, config)?;
+        let amount =
+            self.optional_amount(line, config, asset)?
+                .ok_or_else(|| CsvError::MissingAmount {
+                    line,
+                    tx_type: self.r#type.clone(),
+                })?;
+        Ok((asset, amount))
+    }
+
+    /// Parse the optional amount column of a dispute, resolve, chargeback or represent row.
+    ///
+    /// The amount is in the asset of the referenced transaction, unknown here: it is
+    /// only checked to be a decimal, and scaled by the engine at the precision of that
+    /// asset.
+    fn partial_amount(&self, line: usize) -> Result<Option<Decimal>, CsvError> {
+        self.amount
+            .as_deref()
+            .map(str::parse)
+            .transpose()
+            .map_err(|source| CsvError::InvalidAmount { line, source })
+    }
+
+    /// Parse the asset column, or return the configured default asset.
+    fn asset(&self, line: usize, config: &EngineConfig) -> Result<Asset, CsvError> {
+        match self.asset.as_deref() {
+            Some(code) => code
+                .parse()
+                .map_err(|source| CsvError::InvalidAsset { line, source }),
+            None => Ok(config.default_asset),
+        }
+    }
+
+    /// Parse the amount column if present, at the precision configured for `asset`.
+    fn optional_amount(
+        &self,
+        line: usize,
+        config: &EngineConfig,
+        asset: Asset,
+    ) -> Result<Option<Amount>, CsvError> {
+        self.amount
+            .as_deref()
+            .map(|raw| Amount::parse(raw, config.precision_for(asset)))
+            .transpose()
+            .map_err(|source| CsvError::InvalidAmount { line, source })
+    }
+}
+
+impl InputRow {
+    /// Convert a row into the transaction it describes, keeping its metadata on errors.
+    fn into_transaction(
+        mut self,
+        line: usize,
+        config: &EngineConfig,
+    ) -> Result<Transaction, RowError> {
+        self.transaction(line, config).map_err(|error| RowError {
+            error,
+            metadata: self.metadata,
+        })
+    }
+
+    /// Read the transaction a row describes, taking its metadata once valid.
+    fn transaction(&mut self, line: usize, config: &EngineConfig) -> Result<Transaction, CsvError> {
+        match self.r#type.as_str() {
+            "deposit" => {
+                let (asset, amount) = self.money(line, config)?;
+                Ok(Transaction::Deposit {
+                    client: self.client,
+                    tx: self.tx,
+                    asset,
+                    amount,
+                    timestamp: self.timestamp,
+                    metadata: mem::take(&mut self.metadata),
+                })
+            }
+            "withdrawal" => {
+                let (asset, amount) = self.money(line, config)?;
+                Ok(Transaction::Withdrawal {
+                    client: self.client,
+                    tx: self.tx,
+                    asset,
+                    amount,
+                    timestamp: self.timestamp,
+                    metadata: mem::take(&mut self.metadata),
+                })
+            }
+            "authorize" => {
+                let (asset, amount) = self.money(line, config)?;
+                Ok(Transaction::Authorize {
+                    client: self.client,
+                    tx: self.tx,
+                    asset,
+                    amount,
+                    timestamp: self.timestamp,
+                    metadata: mem::take(&mut self.metadata),
+                })
+            }
+            "capture" => Ok(Transaction::Capture {
+                client: self.client,
+                tx: self.tx,
+                timestamp: self.timestamp,
+                metadata: mem::take(&mut self.metadata),
+            }),
+            "void" => Ok(Transaction::Void {
+                client: self.client,
+                tx: self.tx,
+                timestamp: self.timestamp,
+                metadata: mem::take(&mut self.metadata),
+            }),
+            "transfer" => {
+                let (asset, amount) = self.money(line, config)?;
+                Ok(Transaction::Transfer {
+                    from: self.client,
+                    to: self.to_client.ok_or(CsvError::MissingReceiver { line })?,
+                    tx: self.tx,
+                    asset,
+                    amount,
+                    timestamp: self.timestamp,
+                    metadata: mem::take(&mut self.metadata),
+                })
+            }
+            "lock" => self.admin(line, AdminAction::Lock),
+            "unlock" => self.admin(line, AdminAction::Unlock),
+            "adjustment" => {
+                let (asset, amount) = self.money(line, config)?;
+                let reason = self.reason.take().ok_or(CsvError::MissingReason { line })?;
+                self.admin(
+                    line,
+                    AdminAction::Adjustment {
+                        asset,
+                        amount,
+                        reason,
+                    },
+                )
+            }
+            "dispute" => Ok(Transaction::Dispute {
+                amount: self.partial_amount(line)?,
+                client: self.client,
+                tx: self.tx,
+                timestamp: self.timestamp,
+                metadata: mem::take(&mut self.metadata),
+            }),
+            "resolve" => Ok(Transaction::Resolve {
+                amount: self.partial_amount(line)?,
+                client: self.client,
+                tx: self.tx,
+                timestamp: self.timestamp,
+                metadata: mem::take(&mut self.metadata),
+            }),
+            "chargeback" => Ok(Transaction::Chargeback {
+                amount: self.partial_amount(line)?,
+                client: self.client,
+                tx: self.tx,
+                timestamp: self.timestamp,
+                metadata: mem::take(&mut self.metadata),
+            }),
+            "represent" | "reverse_chargeback" => Ok(Transaction::Represent {
+                amount: self.partial_amount(line)?,
+                client: self.client,
+                tx: self.tx,
+                timestamp: self.timestamp,
+                metadata: mem::take(&mut self.metadata),
+            }),
+            other => Err(CsvError::UnrecognizedType {
+                line,
+                tx_type: other.to_string(),
+            }),
+        }
+    }
+}
+
+impl InputRow {
+    /// Read an admin row as a transaction, it requires an operator.
+    fn admin(&mut self, line: usize, action: AdminAction) -> Result<Transaction, CsvError> {
+        let operator = self
+            .operator
+            .take()
+            .ok_or_else(|| CsvError::MissingOperator {
+                line,
+                tx_type: action.name().to_string(),
+            })?;
+        Ok(Transaction::Admin {
+            client: self.client,
+            tx: self.tx,
+            operator,
+            action,
+            timestamp: self.timestamp,
+            metadata: mem::take(&mut self.metadata),
+        })
+    }
+}
+
+impl InputRow<String> {
+    /// Replace external string IDs with their interned numeric IDs.
+    fn intern(self, ids: &mut ExternalIds) -> InputRow {
+        InputRow {
+            client: ids.clients.intern(&self.client),
+            tx: ids.txs.intern(&self.tx),
+            to_client: self.to_client.map(|to| ids.clients.intern(&to)),
+            timestamp: self.timestamp,
+            operator: self.operator,
+            reason: self.reason,
+            metadata: self.metadata,
+            r#type: self.r#type,
+            amount: self.amount,
+            asset: self.asset,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct OutputRow {
-    client: ClientId,
-    available: String,
-    held: String,
-    total: String,
+    /// Numeric or external client ID
+    client: String,
+    asset: Asset,
+    available: AmountDisplay,
+    held: AmountDisplay,
+    reserved: AmountDisplay,
+    total: AmountDisplay,
     locked: bool,
 }
 
 /// Read transactions from a CSV file.
 ///
+/// Columns: type, client, tx, amount, an optional asset (defaults to
+/// `config.default_asset`), an optional to_client (required by transfers), an
+/// optional timestamp (seconds since the Unix epoch), and optional operator and reason
+/// (required by admin transactions). Any other column is passed through as the
+/// transaction metadata. Amounts are parsed at the precision configured for their asset.
 /// Returns an iterator that yields each transaction or an error if parsing fails.
-/// Invalid rows are returned as errors; valid rows continue to be processed.
+/// Invalid rows are returned as errors, with their metadata; valid rows continue to be
+/// processed.
 pub fn read_transactions(
     path: impl AsRef<Path>,
-) -> Result<impl Iterator<Item = Result<Transaction, CsvError>>, io::Error> {
-    let reader = csv::ReaderBuilder::new()
+    config: &EngineConfig,
+) -> Result<impl Iterator<Item = Result<Transaction, RowError>>, io::Error> {
+    let config = config.clone();
+    Ok(Rows::open(path)?.map(move |(line, row)| row?.into_transaction(line, &config)))
+}
+
+/// Read transactions from a CSV file whose client and tx columns are opaque
+/// strings (e.g. UUIDs), interning them into numeric IDs.
+///
+/// Same format as [`read_transactions`]. The interned IDs are available from the
+/// returned iterator, to map results back to external IDs.
+pub fn read_transactions_interned(
+    path: impl AsRef<Path>,
+    config: &EngineConfig,
+) -> Result<InternedTransactions, io::Error> {
+    Ok(InternedTransactions {
+        rows: Rows::open(path)?,
+        config: config.clone(),
+        ids: ExternalIds::default(),
+    })
+}
+
+/// Iterator over transactions with external IDs, see [`read_transactions_interned`].
+pub struct InternedTransactions {
+    rows: Rows<String>,
+    config: EngineConfig,
+    ids: ExternalIds,
+}
+
+impl InternedTransactions {
+    /// Returns the IDs interned so far.
+    pub fn ids(&self) -> &ExternalIds {
+        &self.ids
+    }
+
+    /// Consume the iterator, returning the interned IDs.
+    pub fn into_ids(self) -> ExternalIds {
+        self.ids
+    }
+}
+
+impl Iterator for InternedTransactions {
+    type Item = Result<Transaction, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, row) = self.rows.next()?;
+        Some(row.and_then(|row| {
+            row.intern(&mut self.ids)
+                .into_transaction(line, &self.config)
+        }))
+    }
+}
+
+/// Iterator over the input rows of a CSV file and their line number, with the
+/// columns not read by [`InputRow`] collected as metadata.
+struct Rows<Id> {
+    records: Enumerate<csv::StringRecordsIntoIter<File>>,
+    headers: csv::StringRecord,
+    /// Index and name of metadata columns
+    metadata: Vec<(usize, String)>,
+    ids: PhantomData<Id>,
+}
+
+impl<Id> Rows<Id> {
+    fn open(path: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let mut reader = open(path)?;
+        let headers = reader.headers().map_err(io::Error::other)?.clone();
+        let metadata = headers
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| !COLUMNS.contains(name))
+            .map(|(idx, name)| (idx, name.to_string()))
+            .collect();
+        Ok(Self {
+            records: reader.into_records().enumerate(),
+            headers,
+            metadata,
+            ids: PhantomData,
+        })
+    }
+}
+
+impl<Id: DeserializeOwned + Default> Iterator for Rows<Id> {
+    type Item = (usize, Result<InputRow<Id>, RowError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (idx, result) = self.records.next()?;
+        let line = idx + 2; // 1-indexed, skip header
+        let record = match result {
+            Ok(record) => record,
+            Err(source) => {
+                let error = CsvError::Parse { line, source };
+                let metadata = Metadata::new();
+                return Some((line, Err(RowError { error, metadata })));
+            }
+        };
+        // Read before the other columns, to be kept if they are invalid. Empty
+        // metadata cells are skipped
+        let metadata = self
+            .metadata
+            .iter()
+            .filter_map(|(idx, name)| {
+                let value = record.get(*idx).filter(|value| !value.is_empty())?;
+                Some((name.clone(), value.to_string()))
+            })
+            .collect();
+        let row = match record.deserialize::<InputRow<Id>>(Some(&self.headers)) {
+            Ok(row) => Ok(InputRow { metadata, ..row }),
+            Err(source) => Err(RowError {
+                error: CsvError::Parse { line, source },
+                metadata,
+            }),
+        };
+        Some((line, row))
+    }
+}
+
+/// Open a CSV file with the input format options.
+fn open(path: impl AsRef<Path>) -> Result<csv::Reader<File>, io::Error> {
+    csv::ReaderBuilder::new()
         .trim(csv::Trim::All)
         .from_path(path)
         .map_err(|e| match e.into_kind() {
             csv::ErrorKind::Io(io_err) => io_err,
             _ => io::Error::other("csv error"),
-        })?;
-
-    Ok(reader
-        .into_deserialize::<InputRow>()
-        .enumerate()
-        .map(|(idx, result)| {
-            let line = idx + 2; // 1-indexed, skip header
-            let row = result.map_err(|source| CsvError::Parse { line, source })?;
-            match row.r#type.as_str() {
-                "deposit" => {
-                    let amount = row.amount.ok_or_else(|| CsvError::MissingAmount {
-                        line,
-                        tx_type: "deposit".to_string(),
-                    })?;
-                    Ok(Transaction::Deposit {
-                        client: row.client,
-                        tx: row.tx,
-                        amount: Amount::from_float(amount),
-                    })
-                }
-                "withdrawal" => {
-                    let amount = row.amount.ok_or_else(|| CsvError::MissingAmount {
-                        line,
-                        tx_type: "withdrawal".to_string(),
-                    })?;
-                    Ok(Transaction::Withdrawal {
-                        client: row.client,
-                        tx: row.tx,
-                        amount: Amount::from_float(amount),
-                    })
-                }
-                "dispute" => Ok(Transaction::Dispute {
-                    client: row.client,
-                    tx: row.tx,
-                }),
-                "resolve" => Ok(Transaction::Resolve {
-                    client: row.client,
-                    tx: row.tx,
-                }),
-                "chargeback" => Ok(Transaction::Chargeback {
-                    client: row.client,
-                    tx: row.tx,
-                }),
-                other => Err(CsvError::UnrecognizedType {
-                    line,
-                    tx_type: other.to_string(),
-                }),
-            }
-        }))
+        })
 }
 
 /// Write client accounts to stdout in CSV format.
 ///
-/// Output columns: client, available, held, total, locked
-pub fn write_accounts<'a>(accounts: impl IntoIterator<Item = &'a ClientAccount>) {
+/// Output columns: client, asset, available, held, reserved, total, locked
+/// One row is written per client and asset, amounts are formatted at the precision
+/// configured for the asset. Clients without any balance get a zero row in
+/// `config.default_asset`. If `clients` is given, client IDs are written as the
+/// external IDs they were interned from.
+pub fn write_accounts<'a>(
+    accounts: impl IntoIterator<Item = &'a ClientAccount>,
+    config: &EngineConfig,
+    clients: Option<&IdInterner>,
+) {
     let stdout = io::stdout();
     let mut writer = csv::Writer::from_writer(stdout.lock());
 
     for account in accounts {
-        let row = OutputRow {
-            client: account.id(),
-            available: account.available().to_string(),
-            held: account.held().to_string(),
-            total: account.total().to_string(),
-            locked: account.is_frozen(),
+        let mut balances: Vec<_> = account
+            .balances()
+            .map(|(asset, balance)| (asset, *balance))
+            .collect();
+        if balances.is_empty() {
+            balances.push((config.default_asset, Balance::default()));
+        }
+
+        let client = match clients.and_then(|ids| ids.resolve(account.id())) {
+            Some(external) => external.to_string(),
+            None => account.id().to_string(),
         };
-        writer.serialize(&row).expect("failed to write csv row");
+
+        for (asset, balance) in balances {
+            let precision = config.precision_for(asset);
+            let row = OutputRow {
+                client: client.clone(),
+                asset,
+                available: balance.available().display(precision),
+                held: balance.held().display(precision),
+                reserved: balance.reserved().display(precision),
+                total: balance.total().display(precision),
+                locked: account.is_frozen(),
+            };
+            writer.serialize(&row).expect("failed to write csv row");
+        }
     }
 
     writer.flush().expect("failed to flush csv writer");
@@ -126,6 +517,7 @@ pub fn write_accounts<'a>(accounts: impl IntoIterator<Item = &'a ClientAccount>)
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::amount::{Precision, RoundingMode};
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -138,15 +530,27 @@ mod tests {
     #[test]
     fn read_deposit() {
         let file = write_csv("type,client,tx,amount\ndeposit,1,1,10.5\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
 
         let tx = results.into_iter().next().unwrap().unwrap();
         match tx {
-            Transaction::Deposit { client, tx, amount } => {
+            Transaction::Deposit {
+                client,
+                tx,
+                asset,
+                amount,
+                timestamp,
+                metadata,
+            } => {
+                assert_eq!(timestamp, None);
+                assert!(metadata.is_empty());
                 assert_eq!(client, 1);
                 assert_eq!(tx, 1);
-                assert_eq!(amount, Amount::from_float(10.5));
+                assert_eq!(asset, EngineConfig::default().default_asset);
+                assert_eq!(amount, Amount::from_float(10.5).unwrap());
             }
             _ => panic!("expected deposit"),
         }
@@ -155,15 +559,19 @@ mod tests {
     #[test]
     fn read_withdrawal() {
         let file = write_csv("type,client,tx,amount\nwithdrawal,2,3,5.25\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
 
         let tx = results.into_iter().next().unwrap().unwrap();
         match tx {
-            Transaction::Withdrawal { client, tx, amount } => {
+            Transaction::Withdrawal {
+                client, tx, amount, ..
+            } => {
                 assert_eq!(client, 2);
                 assert_eq!(tx, 3);
-                assert_eq!(amount, Amount::from_float(5.25));
+                assert_eq!(amount, Amount::from_float(5.25).unwrap());
             }
             _ => panic!("expected withdrawal"),
         }
@@ -172,7 +580,9 @@ mod tests {
     #[test]
     fn read_with_whitespace() {
         let file = write_csv("type, client, tx, amount\ndeposit, 1, 1, 10.0\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
         assert!(results[0].is_ok());
     }
@@ -180,32 +590,215 @@ mod tests {
     #[test]
     fn read_returns_error_for_unknown_type() {
         let file = write_csv("type,client,tx,amount\nunknown,1,1,10.0\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
-        let err = results[0].as_ref().unwrap_err();
+        let err = &results[0].as_ref().unwrap_err().error;
         assert!(matches!(err, CsvError::UnrecognizedType { line: 2, .. }));
     }
 
     #[test]
     fn read_returns_error_for_missing_amount() {
         let file = write_csv("type,client,tx,amount\ndeposit,1,1,\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
-        let err = results[0].as_ref().unwrap_err();
+        let err = &results[0].as_ref().unwrap_err().error;
         assert!(matches!(err, CsvError::MissingAmount { line: 2, .. }));
     }
 
+    #[test]
+    fn read_amount_without_precision_loss() {
+        let file = write_csv("type,client,tx,amount\ndeposit,1,1,922337203685477.5807\n");
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+
+        match results.into_iter().next().unwrap().unwrap() {
+            Transaction::Deposit { amount, .. } => {
+                assert_eq!(amount, Amount::from_scaled(i64::MAX));
+            }
+            _ => panic!("expected deposit"),
+        }
+    }
+
+    #[test]
+    fn read_returns_error_for_invalid_amount() {
+        let file = write_csv(
+            "type,client,tx,amount\n\
+             deposit,1,1,abc\n\
+             deposit,1,2,1.23456\n\
+             withdrawal,1,3,1000000000000000\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(
+            &results[0].as_ref().unwrap_err().error,
+            CsvError::InvalidAmount {
+                line: 2,
+                source: ParseAmountError::Invalid(_)
+            }
+        ));
+        assert!(matches!(
+            &results[1].as_ref().unwrap_err().error,
+            CsvError::InvalidAmount {
+                line: 3,
+                source: ParseAmountError::TooManyDecimals(_, 4)
+            }
+        ));
+        assert!(matches!(
+            &results[2].as_ref().unwrap_err().error,
+            CsvError::InvalidAmount {
+                line: 4,
+                source: ParseAmountError::OutOfRange(_)
+            }
+        ));
+    }
+
+    #[test]
+    fn read_amount_at_configured_precision() {
+        let file = write_csv("type,client,tx,amount\ndeposit,1,1,2.345\ndeposit,1,2,2.355\n");
+        let config = EngineConfig {
+            precision: Precision::new(2, RoundingMode::HalfEven).unwrap(),
+            ..EngineConfig::default()
+        };
+        let amounts: Vec<_> = read_transactions(file.path(), &config)
+            .unwrap()
+            .map(|result| match result.unwrap() {
+                Transaction::Deposit { amount, .. } => amount,
+                _ => panic!("expected deposit"),
+            })
+            .collect();
+        let cents = config.precision;
+        assert_eq!(
+            amounts,
+            [
+                Amount::from_scaled_at(234, cents),
+                Amount::from_scaled_at(236, cents)
+            ]
+        );
+    }
+
+    #[test]
+    fn read_asset_column() {
+        let file = write_csv(
+            "type,client,tx,amount,asset\n\
+             deposit,1,1,1.5,EUR\n\
+             withdrawal,1,2,0.12345678,BTC\n\
+             deposit,1,3,2,\n\
+             deposit,1,4,2,not an asset\n",
+        );
+        let config = EngineConfig {
+            asset_precision: [(
+                Asset::new("BTC").unwrap(),
+                Precision::new(8, RoundingMode::Exact).unwrap(),
+            )]
+            .into(),
+            ..EngineConfig::default()
+        };
+        let results: Vec<_> = read_transactions(file.path(), &config).unwrap().collect();
+        assert_eq!(results.len(), 4);
+
+        match results[0].as_ref().unwrap() {
+            Transaction::Deposit { asset, amount, .. } => {
+                assert_eq!(asset.as_str(), "EUR");
+                assert_eq!(*amount, Amount::from_scaled(15_000));
+            }
+            _ => panic!("expected deposit"),
+        }
+        match results[1].as_ref().unwrap() {
+            Transaction::Withdrawal { asset, amount, .. } => {
+                assert_eq!(asset.as_str(), "BTC");
+                let sats = Precision::new(8, RoundingMode::Exact).unwrap();
+                assert_eq!(*amount, Amount::from_scaled_at(12_345_678, sats));
+                assert_eq!(amount.to_string(), "0.12345678");
+            }
+            _ => panic!("expected withdrawal"),
+        }
+        match results[2].as_ref().unwrap() {
+            Transaction::Deposit { asset, .. } => assert_eq!(*asset, config.default_asset),
+            _ => panic!("expected deposit"),
+        }
+        assert!(matches!(
+            &results[3].as_ref().unwrap_err().error,
+            CsvError::InvalidAsset { line: 5, .. }
+        ));
+    }
+
+    #[test]
+    fn read_interned_ids() {
+        let file = write_csv(
+            "type,client,tx,amount\n\
+             deposit,alice,7f3a-01,10\n\
+             deposit,bob,7f3a-02,5\n\
+             dispute,alice,7f3a-01,\n",
+        );
+        let mut reader = read_transactions_interned(file.path(), &EngineConfig::default()).unwrap();
+        let results: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+
+        assert!(matches!(
+            results[0],
+            Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            results[1],
+            Transaction::Deposit {
+                client: 2,
+                tx: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            results[2],
+            Transaction::Dispute {
+                client: 1,
+                tx: 1,
+                amount: None,
+                ..
+            }
+        ));
+
+        let ids = reader.into_ids();
+        assert_eq!(ids.clients.resolve(2), Some("bob"));
+        assert_eq!(ids.txs.get("7f3a-01"), Some(1));
+    }
+
+    #[test]
+    fn read_rejects_non_numeric_ids() {
+        let file = write_csv("type,client,tx,amount\ndeposit,alice,1,10\n");
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert!(matches!(
+            &results[0].as_ref().unwrap_err().error,
+            CsvError::Parse { line: 2, .. }
+        ));
+    }
+
     #[test]
     fn read_dispute() {
         let file = write_csv("type,client,tx,amount\ndispute,1,5,\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
 
         let tx = results.into_iter().next().unwrap().unwrap();
         match tx {
-            Transaction::Dispute { client, tx } => {
+            Transaction::Dispute {
+                client, tx, amount, ..
+            } => {
                 assert_eq!(client, 1);
                 assert_eq!(tx, 5);
+                assert_eq!(amount, None);
             }
             _ => panic!("expected dispute"),
         }
@@ -214,14 +807,19 @@ mod tests {
     #[test]
     fn read_resolve() {
         let file = write_csv("type,client,tx,amount\nresolve,2,10,\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
 
         let tx = results.into_iter().next().unwrap().unwrap();
         match tx {
-            Transaction::Resolve { client, tx } => {
+            Transaction::Resolve {
+                client, tx, amount, ..
+            } => {
                 assert_eq!(client, 2);
                 assert_eq!(tx, 10);
+                assert_eq!(amount, None);
             }
             _ => panic!("expected resolve"),
         }
@@ -230,16 +828,285 @@ mod tests {
     #[test]
     fn read_chargeback() {
         let file = write_csv("type,client,tx,amount\nchargeback,3,15,\n");
-        let results: Vec<_> = read_transactions(file.path()).unwrap().collect();
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
         assert_eq!(results.len(), 1);
 
         let tx = results.into_iter().next().unwrap().unwrap();
         match tx {
-            Transaction::Chargeback { client, tx } => {
+            Transaction::Chargeback {
+                client, tx, amount, ..
+            } => {
                 assert_eq!(client, 3);
                 assert_eq!(tx, 15);
+                assert_eq!(amount, None);
             }
             _ => panic!("expected chargeback"),
         }
     }
+
+    #[test]
+    fn read_partial_dispute_amounts() {
+        let file = write_csv(
+            "type,client,tx,amount\n\
+             dispute,1,5,2.5\n\
+             resolve,1,5,1\n\
+             chargeback,1,5,0.12345\n\
+             represent,1,5,abc\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 4);
+
+        match results[0].as_ref().unwrap() {
+            Transaction::Dispute { amount, .. } => {
+                assert_eq!(*amount, Some("2.5".parse().unwrap()))
+            }
+            _ => panic!("expected dispute"),
+        }
+        match results[1].as_ref().unwrap() {
+            Transaction::Resolve { amount, .. } => {
+                assert_eq!(*amount, Some("1".parse().unwrap()))
+            }
+            _ => panic!("expected resolve"),
+        }
+        // Scaled by the engine, at the precision of the disputed deposit
+        match results[2].as_ref().unwrap() {
+            Transaction::Chargeback { amount, .. } => {
+                assert_eq!(*amount, Some("0.12345".parse().unwrap()))
+            }
+            _ => panic!("expected chargeback"),
+        }
+        assert!(matches!(
+            &results[3].as_ref().unwrap_err().error,
+            CsvError::InvalidAmount { line: 5, .. }
+        ));
+    }
+
+    #[test]
+    fn read_transfer() {
+        let file = write_csv(
+            "type,client,tx,amount,to_client\n\
+             transfer,1,7,2.5,2\n\
+             transfer,1,8,2.5,\n\
+             deposit,1,9,1,\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 3);
+
+        match results[0].as_ref().unwrap() {
+            Transaction::Transfer {
+                from,
+                to,
+                tx,
+                amount,
+                ..
+            } => {
+                assert_eq!((*from, *to, *tx), (1, 2, 7));
+                assert_eq!(*amount, Amount::from_scaled(25_000));
+            }
+            _ => panic!("expected transfer"),
+        }
+        assert!(matches!(
+            &results[1].as_ref().unwrap_err().error,
+            CsvError::MissingReceiver { line: 3 }
+        ));
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn read_timestamp_column() {
+        let file = write_csv(
+            "type,client,tx,amount,timestamp\n\
+             deposit,1,1,10,1700000000\n\
+             dispute,1,1,,\n\
+             deposit,1,2,10,yesterday\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap().timestamp(),
+            Some(1_700_000_000)
+        );
+        assert_eq!(results[1].as_ref().unwrap().timestamp(), None);
+        assert!(matches!(
+            &results[2].as_ref().unwrap_err().error,
+            CsvError::Parse { line: 4, .. }
+        ));
+    }
+
+    #[test]
+    fn read_metadata_columns() {
+        let file = write_csv(
+            "type,client,tx,amount,merchant,memo\n\
+             deposit,1,1,10,acme,first order\n\
+             dispute,1,1,,acme,\n\
+             deposit,1,2,5,,\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 3);
+
+        let metadata = results[0].as_ref().unwrap().metadata();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["merchant"], "acme");
+        assert_eq!(metadata["memo"], "first order");
+        // Empty cells are not captured
+        let metadata = results[1].as_ref().unwrap().metadata();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata["merchant"], "acme");
+        assert!(results[2].as_ref().unwrap().metadata().is_empty());
+    }
+
+    #[test]
+    fn invalid_rows_keep_their_metadata() {
+        let file = write_csv(
+            "type,client,tx,amount,merchant\n\
+             deposit,1,1,abc,acme\n\
+             deposit,x,2,10,globex\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+
+        let err = results[0].as_ref().unwrap_err();
+        assert!(matches!(err.error, CsvError::InvalidAmount { line: 2, .. }));
+        assert_eq!(err.metadata["merchant"], "acme");
+        // Also when the other columns can't be read
+        let err = results[1].as_ref().unwrap_err();
+        assert!(matches!(err.error, CsvError::Parse { line: 3, .. }));
+        assert_eq!(err.metadata["merchant"], "globex");
+    }
+
+    #[test]
+    fn read_interned_metadata() {
+        let file = write_csv(
+            "type,client,tx,amount,reference\n\
+             deposit,alice,dep-1,10,INV-42\n",
+        );
+        let tx = read_transactions_interned(file.path(), &EngineConfig::default())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.metadata()["reference"], "INV-42");
+    }
+
+    #[test]
+    fn read_admin_transactions() {
+        let file = write_csv(
+            "type,client,tx,amount,operator,reason\n\
+             unlock,1,7,,ops-jane,\n\
+             adjustment,1,8,-2.5,ops-jane,FEE_REFUND\n\
+             lock,1,9,,,\n\
+             adjustment,1,10,1,ops-jane,\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 4);
+
+        match results[0].as_ref().unwrap() {
+            Transaction::Admin {
+                client,
+                operator,
+                action,
+                ..
+            } => {
+                assert_eq!(*client, 1);
+                assert_eq!(operator, "ops-jane");
+                assert_eq!(*action, AdminAction::Unlock);
+            }
+            _ => panic!("expected admin"),
+        }
+        match results[1].as_ref().unwrap() {
+            Transaction::Admin {
+                action: AdminAction::Adjustment { amount, reason, .. },
+                ..
+            } => {
+                assert_eq!(*amount, Amount::from_scaled(-25_000));
+                assert_eq!(reason, "FEE_REFUND");
+            }
+            _ => panic!("expected adjustment"),
+        }
+        assert!(matches!(
+            &results[2].as_ref().unwrap_err().error,
+            CsvError::MissingOperator { line: 4, .. }
+        ));
+        assert!(matches!(
+            &results[3].as_ref().unwrap_err().error,
+            CsvError::MissingReason { line: 5 }
+        ));
+    }
+
+    #[test]
+    fn read_two_phase_withdrawal() {
+        let file = write_csv(
+            "type,client,tx,amount\n\
+             authorize,1,7,2.5\n\
+             capture,1,7,\n\
+             void,1,7,\n",
+        );
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 3);
+
+        match results[0].as_ref().unwrap() {
+            Transaction::Authorize { tx, amount, .. } => {
+                assert_eq!(*tx, 7);
+                assert_eq!(*amount, Amount::from_scaled(25_000));
+            }
+            _ => panic!("expected authorize"),
+        }
+        assert!(matches!(
+            results[1].as_ref().unwrap(),
+            Transaction::Capture {
+                client: 1,
+                tx: 7,
+                ..
+            }
+        ));
+        assert!(matches!(
+            results[2].as_ref().unwrap(),
+            Transaction::Void {
+                client: 1,
+                tx: 7,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn read_represent() {
+        let file = write_csv("type,client,tx,amount\nrepresent,3,15,\nreverse_chargeback,3,16,2\n");
+        let results: Vec<_> = read_transactions(file.path(), &EngineConfig::default())
+            .unwrap()
+            .collect();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[0].as_ref().unwrap(),
+            Transaction::Represent {
+                client: 3,
+                tx: 15,
+                amount: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            results[1].as_ref().unwrap(),
+            Transaction::Represent {
+                tx: 16,
+                amount: Some(_),
+                ..
+            }
+        ));
+    }
 }
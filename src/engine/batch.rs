@@ -0,0 +1,387 @@
+//! Atomic batches of transactions.
+//!
+//! [`Engine::apply_batch`] applies transactions in order, saving the state each one
+//! can change before applying it: the accounts of its client (both clients of a
+//! transfer), the record, authorization or ID stored under its transaction ID, the
+//! metadata and dispute count kept for that ID, and the engine clock. If a
+//! transaction is rejected, or the events of the batch can't be journaled, the saved
+//! state is restored, undoing the transactions applied before.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use tracing::info;
+
+use super::{AccountStore, BatchError, ClientAccount, Engine, EngineError, StoreError, TxStore};
+use crate::model::{
+    AuthorizationRecord, ClientId, DepositRecord, Metadata, Timestamp, Transaction, TxId,
+};
+
+/// Result of a batch applied by [`Engine::apply_batch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchReport {
+    /// Number of transactions applied, the whole batch.
+    pub applied: usize,
+    /// Number of events emitted, see [`Engine::last_events`].
+    pub events: usize,
+}
+
+/// State stored under a transaction ID before the batch changed it.
+enum SavedTx {
+    /// Nothing was stored, the ID is removed
+    Absent,
+    Record(DepositRecord),
+    Authorization(AuthorizationRecord),
+    /// An ID without record (or an evicted record), which transactions don't change
+    IdOnly,
+}
+
+/// State changed by the transactions of a batch, as it was before the batch.
+struct Undo {
+    clock: Timestamp,
+    accounts: HashMap<ClientId, Option<ClientAccount>>,
+    txs: HashMap<TxId, (SavedTx, Option<Metadata>, Option<u32>)>,
+}
+
+impl<A: AccountStore, T: TxStore> Engine<A, T> {
+    /// Apply transactions all-or-nothing.
+    ///
+    /// The transactions are applied in order. If one is rejected, every change made
+    /// by the batch is rolled back, and the error says which transaction failed and
+    /// why. The events of the batch are appended to the journal, if any, as a single
+    /// frame once the whole batch is applied: a rolled back batch emits no events. If
+    /// appending fails, the batch is rolled back too (the journal drops the partial
+    /// frame, see [`Journal`](super::Journal)), and every transaction of the
+    /// batch is notified as rejected with the journal error.
+    pub fn apply_batch(&mut self, batch: &[Transaction]) -> Result<BatchReport, BatchError> {
+        self.events.clear();
+        let mut undo = Undo {
+            clock: self.clock,
+            accounts: HashMap::new(),
+            txs: HashMap::new(),
+        };
+
+        let mut outcomes = Vec::with_capacity(batch.len());
+        for (index, transaction) in batch.iter().enumerate() {
+            let first = self.events.len();
+            let result = self
+                .save(&mut undo, transaction)
+                .map_err(EngineError::from)
+                .and_then(|()| self.apply_transaction(transaction))
+                .map(|()| self.outcome(transaction, first));
+            let source = match result {
+                Ok(outcome) => {
+                    outcomes.push(outcome);
+                    continue;
+                }
+                Err(source) => source,
+            };
+
+            let tx = transaction.tx();
+            info!(index, tx = %tx, reason = %source, "batch rolled back");
+            self.events.clear();
+            let rollback = self.rollback(undo);
+            self.notify_rejected(transaction, &source);
+            return Err(match rollback {
+                Ok(()) => BatchError::Rejected { index, tx, source },
+                Err(error) => BatchError::RollbackFailed {
+                    index,
+                    tx,
+                    source,
+                    error,
+                },
+            });
+        }
+
+        if let Err(source) = self.write_journal() {
+            info!(reason = %source, "batch rolled back");
+            self.events.clear();
+            let rollback = self.rollback(undo);
+            let source = EngineError::Journal(source);
+            for transaction in batch {
+                self.notify_rejected(transaction, &source);
+            }
+            let EngineError::Journal(source) = source else {
+                unreachable!("journal error")
+            };
+            return Err(match rollback {
+                Ok(()) => BatchError::Journal(source),
+                Err(error) => BatchError::JournalRollbackFailed { source, error },
+            });
+        }
+        for (transaction, outcome) in batch.iter().zip(&outcomes) {
+            self.notify_applied(transaction, outcome);
+        }
+        Ok(BatchReport {
+            applied: batch.len(),
+            events: self.events.len(),
+        })
+    }
+
+    /// Save the state a transaction can change, unless saved for an earlier one.
+    fn save(&mut self, undo: &mut Undo, transaction: &Transaction) -> Result<(), StoreError> {
+        let clients = match *transaction {
+            Transaction::Transfer { from, to, .. } => [Some(from), Some(to)],
+            Transaction::Deposit { client, .. }
+            | Transaction::Withdrawal { client, .. }
+            | Transaction::Authorize { client, .. }
+            | Transaction::Capture { client, .. }
+            | Transaction::Void { client, .. }
+            | Transaction::Admin { client, .. }
+            | Transaction::Dispute { client, .. }
+            | Transaction::Resolve { client, .. }
+            | Transaction::Chargeback { client, .. }
+            | Transaction::Represent { client, .. } => [Some(client), None],
+        };
+        for client in clients.into_iter().flatten() {
+            if let Entry::Vacant(entry) = undo.accounts.entry(client) {
+                entry.insert(self.clients.get(client)?.cloned());
+            }
+        }
+
+        let tx = transaction.tx();
+        if let Entry::Vacant(entry) = undo.txs.entry(tx) {
+            let saved = if let Some((_, record)) = self.txs.record_mut(tx)? {
+                SavedTx::Record(record.clone())
+            } else if let Some(authorization) = self.txs.authorization_mut(tx)? {
+                SavedTx::Authorization(authorization.clone())
+            } else if self.txs.contains(tx)? {
+                SavedTx::IdOnly
+            } else {
+                SavedTx::Absent
+            };
+            let metadata = self.deposit_metadata.get(&tx).cloned();
+            let disputes = self.dispute_counts.get(&tx).copied();
+            entry.insert((saved, metadata, disputes));
+        }
+        Ok(())
+    }
+
+    /// Restore the state saved before the batch.
+    fn rollback(&mut self, undo: Undo) -> Result<(), StoreError> {
+        self.clock = undo.clock;
+
+        for (client, saved) in undo.accounts {
+            match saved {
+                Some(saved) => {
+                    if let Some(account) = self.clients.get_mut(client)? {
+                        *account = saved;
+                    }
+                }
+                None => self.clients.remove(client)?,
+            }
+        }
+
+        for (tx, (saved, metadata, disputes)) in undo.txs {
+            match saved {
+                SavedTx::Absent => self.txs.remove(tx)?,
+                SavedTx::Record(saved) => {
+                    if let Some((_, record)) = self.txs.record_mut(tx)? {
+                        *record = saved;
+                    }
+                }
+                SavedTx::Authorization(saved) => {
+                    if let Some(authorization) = self.txs.authorization_mut(tx)? {
+                        *authorization = saved;
+                    }
+                }
+                SavedTx::IdOnly => {}
+            }
+            match metadata {
+                Some(metadata) => self.deposit_metadata.insert(tx, metadata),
+                None => self.deposit_metadata.remove(&tx),
+            };
+            match disputes {
+                Some(count) => self.dispute_counts.insert(tx, count),
+                None => self.dispute_counts.remove(&tx),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::event::{FailingFile, FailingWriter, SharedBuffer};
+    use crate::engine::test_utils::*;
+    use crate::engine::{DiskTxStore, Journal, JournalError, MemoryAccountStore, WithdrawalError};
+    use crate::{Amount, EngineConfig};
+    use std::fs::File;
+    use std::sync::atomic::Ordering;
+    use tempfile::TempDir;
+
+    fn config() -> EngineConfig {
+        EngineConfig {
+            retain_deposit_metadata: true,
+            max_redisputes: Some(1),
+            ..EngineConfig::default()
+        }
+    }
+
+    /// Changes every kind of state, and fails on the last transaction.
+    fn failing_batch() -> Vec<Transaction> {
+        vec![
+            recorded(1, 3, 10),
+            recorded(5, 4, 20), // New account
+            dispute(1, 1),
+            transfer(1, 2, 5, 5),
+            transfer(2, 6, 6, 10), // New receiver
+            withdrawal(2, 7, 1_000),
+        ]
+    }
+
+    #[test]
+    fn batch_applies_every_transaction() {
+        let mut engine = Engine::with_config(config());
+        let batch = [
+            recorded(1, 1, 100),
+            transfer(1, 2, 2, 40),
+            withdrawal(2, 3, 10),
+        ];
+
+        let report = engine.apply_batch(&batch).unwrap();
+        assert_eq!(report.applied, 3);
+        assert_eq!(report.events, engine.last_events().len());
+
+        let balance = |client| engine.get_client(client).unwrap().unwrap().available(USD);
+        assert_eq!(balance(1), Amount::from_scaled(60));
+        assert_eq!(balance(2), Amount::from_scaled(30));
+    }
+
+    #[test]
+    fn rejected_batch_is_rolled_back() {
+        let mut engine = Engine::with_config(config());
+        engine.apply(recorded(1, 1, 100)).unwrap();
+        engine.apply(recorded(2, 2, 50)).unwrap();
+        let before = snapshot(&engine);
+
+        let result = engine.apply_batch(&failing_batch());
+        assert!(matches!(
+            result,
+            Err(BatchError::Rejected {
+                index: 5,
+                tx: 7,
+                source: EngineError::Withdrawal(WithdrawalError::InsufficientFunds(2, ..)),
+            })
+        ));
+        assert!(engine.last_events().is_empty());
+
+        // Accounts, records, IDs, metadata, dispute counts and clock
+        assert_eq!(snapshot(&engine), before);
+
+        // The IDs of the rolled back batch are free again
+        let batch = failing_batch();
+        engine.apply_batch(&batch[..5]).unwrap();
+        assert_eq!(engine.clients().count(), 4);
+    }
+
+    #[test]
+    fn journal_gets_one_frame_per_applied_batch() {
+        let journal = SharedBuffer::default();
+        let mut engine = Engine::with_config(config());
+        engine.set_journal(Journal::new(journal.clone()).unwrap());
+        engine.apply(recorded(1, 1, 100)).unwrap();
+        engine.apply(recorded(2, 2, 50)).unwrap();
+
+        assert!(engine.apply_batch(&failing_batch()).is_err());
+        engine.apply_batch(&failing_batch()[..5]).unwrap();
+
+        let bytes = journal.0.lock().unwrap().clone();
+        let mut replayed = Engine::with_config(config());
+        assert_eq!(replayed.replay(bytes.as_slice()).unwrap(), 3);
+        assert_eq!(snapshot(&replayed), snapshot(&engine));
+    }
+
+    #[test]
+    fn batch_failing_to_be_journaled_is_rolled_back() {
+        let writer = FailingWriter::default();
+        let mut engine = Engine::with_config(config());
+        engine.set_journal(Journal::new(writer.clone()).unwrap());
+        engine.apply(recorded(1, 1, 100)).unwrap();
+        engine.apply(recorded(2, 2, 50)).unwrap();
+        let before = snapshot(&engine);
+
+        writer.fail();
+        let batch = failing_batch();
+        assert!(matches!(
+            engine.apply_batch(&batch[..5]),
+            Err(BatchError::Journal(_))
+        ));
+        assert!(engine.last_events().is_empty());
+        assert_eq!(snapshot(&engine), before);
+    }
+
+    #[test]
+    fn failed_batch_frame_is_truncated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("journal.bin");
+        let (journal, budget) = FailingFile::journal(&path, false);
+        let mut engine = Engine::with_config(config());
+        engine.set_journal(journal);
+        engine.apply(recorded(1, 1, 100)).unwrap();
+        engine.apply(recorded(2, 2, 50)).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // Fails in the middle of the frame
+        budget.store(10, Ordering::Relaxed);
+        let batch = failing_batch();
+        assert!(matches!(
+            engine.apply_batch(&batch[..5]),
+            Err(BatchError::Journal(_))
+        ));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+        // The journal stays readable, and appends after the last complete frame
+        budget.store(usize::MAX, Ordering::Relaxed);
+        engine.apply_batch(&batch[..5]).unwrap();
+        let mut replayed = Engine::with_config(config());
+        let frames = replayed.replay(File::open(&path).unwrap()).unwrap();
+        assert_eq!(frames, 3);
+        assert_eq!(snapshot(&replayed), snapshot(&engine));
+    }
+
+    #[test]
+    fn journal_is_poisoned_if_not_truncated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("journal.bin");
+        let (journal, budget) = FailingFile::journal(&path, true);
+        let mut engine = Engine::with_config(config());
+        engine.set_journal(journal);
+        engine.apply(recorded(1, 1, 100)).unwrap();
+
+        budget.store(10, Ordering::Relaxed);
+        assert!(engine.apply_batch(&[recorded(1, 2, 10)]).is_err());
+        budget.store(usize::MAX, Ordering::Relaxed);
+        assert!(matches!(
+            engine.apply_batch(&[recorded(1, 2, 10)]),
+            Err(BatchError::Journal(JournalError::Poisoned))
+        ));
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn disk_store_batch_is_rolled_back() {
+        let dir = TempDir::new().unwrap();
+        // A single cached record, the batch records are written back to disk
+        let txs = DiskTxStore::create(dir.path(), 1).unwrap();
+        let mut engine = Engine::with_stores(config(), MemoryAccountStore::new(), txs);
+        engine.apply(recorded(1, 1, 100)).unwrap();
+        engine.apply(recorded(2, 2, 50)).unwrap();
+
+        assert!(matches!(
+            engine.apply_batch(&failing_batch()),
+            Err(BatchError::Rejected { index: 5, .. })
+        ));
+        for tx in 3..=7 {
+            assert!(!engine.txs.contains(tx).unwrap());
+        }
+        let record = engine.txs.record_mut(1).unwrap().unwrap().1;
+        assert_eq!(record.disputed, Amount::default());
+        assert!(engine.get_client(5).unwrap().is_none());
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.held(USD), Amount::default());
+    }
+}
@@ -0,0 +1,185 @@
+//! Binary encoding shared by snapshots and journals.
+//!
+//! Integers are little endian, amounts their scaled `i64` then scale, assets their 7 byte
+//! code, lengths `u64` and strings a length then UTF-8 bytes.
+
+use std::io::{self, Read, Write};
+
+use super::state::FreezeReason;
+use super::store::RecordKind;
+use crate::model::{Asset, AuthorizationState, DepositState};
+use crate::{Amount, Precision, RoundingMode};
+
+/// Error decoding a value: either reading failed, or the bytes read are invalid.
+#[derive(Debug)]
+pub(crate) enum CodecError {
+    Io(io::Error),
+    Invalid(&'static str),
+}
+
+impl From<io::Error> for CodecError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+pub(crate) struct Writer<W>(W);
+
+impl<W: Write> Writer<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self(writer)
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.0
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.bytes(&[value])
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub(crate) fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    pub(crate) fn len(&mut self, len: usize) -> io::Result<()> {
+        self.u64(len as u64)
+    }
+
+    pub(crate) fn amount(&mut self, amount: Amount) -> io::Result<()> {
+        self.bytes(&amount.scaled().to_le_bytes())?;
+        self.u8(amount.scale() as u8)
+    }
+
+    pub(crate) fn asset(&mut self, asset: Asset) -> io::Result<()> {
+        self.bytes(&asset.to_bytes())
+    }
+
+    pub(crate) fn str(&mut self, value: &str) -> io::Result<()> {
+        self.len(value.len())?;
+        self.bytes(value.as_bytes())
+    }
+
+    pub(crate) fn record_kind(&mut self, kind: RecordKind) -> io::Result<()> {
+        self.u8(match kind {
+            RecordKind::Deposit => 0,
+            RecordKind::Withdrawal => 1,
+        })
+    }
+
+    pub(crate) fn deposit_state(&mut self, state: DepositState) -> io::Result<()> {
+        self.u8(state as u8)
+    }
+
+    pub(crate) fn freeze_reason(&mut self, reason: Option<FreezeReason>) -> io::Result<()> {
+        self.u8(match reason {
+            None => 0,
+            Some(FreezeReason::Chargeback) => 1,
+            Some(FreezeReason::Admin) => 2,
+        })
+    }
+
+    pub(crate) fn authorization_state(&mut self, state: AuthorizationState) -> io::Result<()> {
+        self.u8(state as u8)
+    }
+}
+
+pub(crate) struct Reader<R>(R);
+
+impl<R: Read> Reader<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self(reader)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let mut bytes = [0; N];
+        self.0.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Whether nothing is left to read, consumes a byte otherwise.
+    pub(crate) fn at_end(&mut self) -> Result<bool, CodecError> {
+        Ok(self.0.read(&mut [0])? == 0)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, CodecError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, CodecError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, CodecError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn len(&mut self) -> Result<u64, CodecError> {
+        self.u64()
+    }
+
+    pub(crate) fn amount(&mut self) -> Result<Amount, CodecError> {
+        let value = i64::from_le_bytes(self.array()?);
+        let precision = Precision::new(u32::from(self.u8()?), RoundingMode::Exact)
+            .ok_or(CodecError::Invalid("invalid amount scale"))?;
+        Ok(Amount::from_scaled_at(value, precision))
+    }
+
+    pub(crate) fn asset(&mut self) -> Result<Asset, CodecError> {
+        Asset::from_bytes(self.array()?).ok_or(CodecError::Invalid("invalid asset code"))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, CodecError> {
+        let len = self.len()?;
+        let mut bytes = Vec::new();
+        // Don't trust the length for the allocation
+        (&mut self.0).take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(CodecError::Invalid("truncated string"));
+        }
+        String::from_utf8(bytes).map_err(|_| CodecError::Invalid("invalid UTF-8 string"))
+    }
+
+    pub(crate) fn record_kind(&mut self) -> Result<RecordKind, CodecError> {
+        match self.u8()? {
+            0 => Ok(RecordKind::Deposit),
+            1 => Ok(RecordKind::Withdrawal),
+            _ => Err(CodecError::Invalid("unknown record kind")),
+        }
+    }
+
+    pub(crate) fn deposit_state(&mut self) -> Result<DepositState, CodecError> {
+        match self.u8()? {
+            0 => Ok(DepositState::Ok),
+            1 => Ok(DepositState::Disputed),
+            2 => Ok(DepositState::ChargedBack),
+            _ => Err(CodecError::Invalid("unknown record state")),
+        }
+    }
+
+    pub(crate) fn freeze_reason(&mut self) -> Result<Option<FreezeReason>, CodecError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(FreezeReason::Chargeback)),
+            2 => Ok(Some(FreezeReason::Admin)),
+            _ => Err(CodecError::Invalid("unknown freeze reason")),
+        }
+    }
+
+    pub(crate) fn authorization_state(&mut self) -> Result<AuthorizationState, CodecError> {
+        match self.u8()? {
+            0 => Ok(AuthorizationState::Pending),
+            1 => Ok(AuthorizationState::Captured),
+            2 => Ok(AuthorizationState::Voided),
+            _ => Err(CodecError::Invalid("unknown authorization state")),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Engine configuration.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::ConfigError;
+use crate::amount::Precision;
+use crate::model::Asset;
+
+/// Business policies applied by the [`Engine`](super::Engine).
+///
+/// The default configuration matches the behaviour of [`Engine::new`](super::Engine::new).
+/// It can be loaded from TOML with [`EngineConfig::from_toml`], where every field is
+/// optional and defaults to the value below:
+///
+/// ```
+/// use txs_eng::engine::{EngineConfig, NegativeAvailablePolicy};
+///
+/// let config = EngineConfig::from_toml(
+///     r#"
+///     default_asset = "EUR"
+///     negative_available = "reject"
+///     max_redisputes = 1
+///
+///     [precision]
+///     scale = 2
+///     rounding = "half_even"
+///     "#,
+/// )
+/// .unwrap();
+/// assert_eq!(config.negative_available, NegativeAvailablePolicy::Reject);
+/// assert_eq!(config.precision.scale(), 2);
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Accept zero-amount "probe" deposits. Negative amounts are always rejected.
+    pub allow_zero_deposits: bool,
+    /// Decimal places and rounding policy used to parse and format amounts.
+    pub precision: Precision,
+    /// Per-asset overrides of `precision`.
+    pub asset_precision: HashMap<Asset, Precision>,
+    /// Asset of money-moving transactions that don't specify one (`USD` by default).
+    pub default_asset: Asset,
+    /// Which transactions can be disputed.
+    pub dispute_policy: DisputePolicy,
+    /// Maximum age of a disputed transaction in seconds, unlimited if `None`.
+    ///
+    /// Records older than the window can be evicted with
+    /// [`Engine::evict_expired`](super::Engine::evict_expired), which
+    /// [`Engine::run`](super::Engine::run) calls as the clock moves forward.
+    pub dispute_window: Option<u64>,
+    /// Whether a re-presentment unfreezes the account once no other chargeback
+    /// remains on it (`false` by default). Accounts locked by an operator stay
+    /// frozen until unlocked.
+    pub unfreeze_on_represent: bool,
+    /// Whether to keep the metadata of deposits with their retained record, to log it
+    /// with later disputes, resolves, chargebacks and re-presentments (`false` by
+    /// default: metadata is only logged with its own transaction).
+    pub retain_deposit_metadata: bool,
+    /// What a dispute does when the available funds can't cover the disputed amount.
+    pub negative_available: NegativeAvailablePolicy,
+    /// How many times a transaction can be disputed again once its disputes were
+    /// resolved, unlimited if `None`. Concurrent partial disputes don't count.
+    pub max_redisputes: Option<u32>,
+    /// Whether frozen accounts accept disputes, resolves and chargebacks (`true` by
+    /// default). Re-presentments are always accepted, as they reverse the chargeback
+    /// that froze the account.
+    pub allow_frozen_disputes: bool,
+    /// Whether a withdrawal from an unknown client creates its (empty) account
+    /// before being rejected for insufficient funds (`true` by default).
+    pub create_accounts_on_withdrawal: bool,
+}
+
+/// Which transactions can be disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputePolicy {
+    /// Only deposits, withdrawals are only tracked for duplicate IDs.
+    #[default]
+    DepositsOnly,
+    /// Deposits and withdrawals. Withdrawal records are kept for dispute tracking,
+    /// which uses as much memory as deposit records.
+    ///
+    /// A disputed withdrawal credits the withdrawn funds back as held. Resolving
+    /// removes them again (the withdrawal stands), a chargeback releases them to
+    /// available (the client is refunded, and the account is not frozen).
+    DepositsAndWithdrawals,
+}
+
+/// What a deposit dispute does when the client's available funds can't cover the
+/// disputed amount, e.g. because the funds were already withdrawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NegativeAvailablePolicy {
+    /// Hold the whole amount, the available balance goes negative. This represents
+    /// debt owed by the client.
+    #[default]
+    Allow,
+    /// Hold only the available funds, the dispute covers the held part. Rejected if
+    /// no funds are available.
+    Cap,
+    /// Reject the dispute.
+    Reject,
+}
+
+impl EngineConfig {
+    /// Parse a configuration from TOML, missing fields take their default value.
+    pub fn from_toml(config: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(config)?)
+    }
+
+    /// Load a configuration from a TOML file, see [`EngineConfig::from_toml`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Returns the precision used for amounts of the given asset.
+    pub fn precision_for(&self, asset: Asset) -> Precision {
+        self.asset_precision
+            .get(&asset)
+            .copied()
+            .unwrap_or(self.precision)
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            allow_zero_deposits: false,
+            precision: Precision::DEFAULT,
+            asset_precision: HashMap::new(),
+            default_asset: Asset::new("USD").unwrap(),
+            dispute_policy: DisputePolicy::default(),
+            dispute_window: None,
+            unfreeze_on_represent: false,
+            retain_deposit_metadata: false,
+            negative_available: NegativeAvailablePolicy::default(),
+            max_redisputes: None,
+            allow_frozen_disputes: true,
+            create_accounts_on_withdrawal: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::RoundingMode;
+
+    #[test]
+    fn empty_toml_is_default() {
+        let config = EngineConfig::from_toml("").unwrap();
+        let default = EngineConfig::default();
+        assert_eq!(config.precision, default.precision);
+        assert_eq!(config.default_asset, default.default_asset);
+        assert_eq!(config.negative_available, NegativeAvailablePolicy::Allow);
+        assert_eq!(config.max_redisputes, None);
+        assert!(config.allow_frozen_disputes);
+        assert!(config.create_accounts_on_withdrawal);
+    }
+
+    #[test]
+    fn from_toml_reads_policies() {
+        let config = EngineConfig::from_toml(
+            r#"
+            dispute_policy = "deposits_and_withdrawals"
+            dispute_window = 86400
+            negative_available = "cap"
+            max_redisputes = 0
+            allow_frozen_disputes = false
+            create_accounts_on_withdrawal = false
+
+            [asset_precision.BTC]
+            scale = 8
+            rounding = "toward_zero"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.dispute_policy, DisputePolicy::DepositsAndWithdrawals);
+        assert_eq!(config.dispute_window, Some(86_400));
+        assert_eq!(config.negative_available, NegativeAvailablePolicy::Cap);
+        assert_eq!(config.max_redisputes, Some(0));
+        assert!(!config.allow_frozen_disputes);
+        assert!(!config.create_accounts_on_withdrawal);
+        assert_eq!(
+            config.precision_for(Asset::new("BTC").unwrap()),
+            Precision::new(8, RoundingMode::TowardZero).unwrap()
+        );
+    }
+
+    #[test]
+    fn from_toml_rejects_invalid_config() {
+        assert!(matches!(
+            EngineConfig::from_toml("unknown_policy = true"),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(EngineConfig::from_toml(r#"negative_available = "ignore""#).is_err());
+        assert!(EngineConfig::from_toml(r#"default_asset = "TOO_LONG_ASSET""#).is_err());
+    }
+
+    #[test]
+    fn load_missing_file() {
+        assert!(matches!(
+            EngineConfig::load("does/not/exist.toml"),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
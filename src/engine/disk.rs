@@ -0,0 +1,822 @@
+//! Disk-backed transaction store.
+//!
+//! Records are appended to a records file, and located through an index file: an
+//! open-addressing hash table of transaction IDs to record offsets, doubled when
+//! half full. Only the most recently used records stay in memory, in a cache of
+//! bounded size, so memory use doesn't grow with the number of transactions.
+//!
+//! The files are scratch storage for one engine: they are truncated when the
+//! store is created, and can be deleted once it is dropped.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::StoreError;
+use super::store::{RecordKind, TxStore};
+use crate::model::{
+    Asset, AuthorizationRecord, AuthorizationState, DepositRecord, DepositState, TxId,
+};
+use crate::{Amount, Precision, RoundingMode};
+
+/// Size of an entry in the records file.
+const ENTRY_SIZE: usize = 64;
+/// Size of a slot in the index file: transaction ID and value.
+const SLOT_SIZE: usize = 16;
+/// Initial number of index slots.
+const INITIAL_SLOTS: u64 = 1 << 10;
+/// Number of index slots read at once when scanning the index.
+const SCAN_SLOTS: usize = 1 << 12;
+
+// Index slot values, any other value is the offset of the transaction's latest
+// entry in the records file, plus one.
+const EMPTY: u64 = 0;
+const ID_ONLY: u64 = u64::MAX;
+const EVICTED: u64 = u64::MAX - 1;
+
+/// [`TxStore`] keeping records on disk, with a bounded in-memory cache.
+///
+/// New and updated records are written back to disk when they leave the cache,
+/// and cold records are read back when a dispute (or capture, void) refers to them.
+/// Updated records are appended again, the records file only grows. Records read
+/// back and left unchanged are not written again.
+#[derive(Debug)]
+pub struct DiskTxStore {
+    records: File,
+    records_len: u64,
+    index: DiskIndex,
+    cache: Cache,
+}
+
+impl DiskTxStore {
+    /// File name of the records file, in the store directory.
+    pub const RECORDS_FILE: &str = "records.bin";
+    /// File name of the index file, in the store directory.
+    pub const INDEX_FILE: &str = "index.bin";
+
+    /// Create a store in the given directory, keeping at most `cache_capacity`
+    /// records (at least one) in memory.
+    ///
+    /// Existing store files in the directory are truncated.
+    pub fn create(dir: impl AsRef<Path>, cache_capacity: usize) -> Result<Self, StoreError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let records = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.join(Self::RECORDS_FILE))?;
+        Ok(Self {
+            records,
+            records_len: 0,
+            index: DiskIndex::create(dir.join(Self::INDEX_FILE), INITIAL_SLOTS)?,
+            cache: Cache::new(cache_capacity.max(1)),
+        })
+    }
+
+    /// Returns the number of records held in memory.
+    pub fn cached(&self) -> usize {
+        self.cache.entries.len()
+    }
+
+    /// Return an entry to update, reading it from disk if it isn't cached.
+    ///
+    /// The entry is only written back if it was changed.
+    fn entry_mut(&mut self, tx: TxId) -> Result<Option<&mut Entry>, StoreError> {
+        if !self.cache.contains(tx) {
+            let Some(offset) = self.index.get(tx)?.and_then(entry_offset) else {
+                return Ok(None);
+            };
+            let (_, entry) = self.read_entry(offset)?;
+            self.cache_entry(tx, entry, true)?;
+        }
+        Ok(self.cache.get_mut(tx))
+    }
+
+    /// Add an entry to the cache, as `written` to disk or not yet, writing back the
+    /// least recently used one if full.
+    ///
+    /// The cache is left unchanged if writing back fails.
+    fn cache_entry(&mut self, tx: TxId, entry: Entry, written: bool) -> Result<(), StoreError> {
+        if !self.cache.contains(tx)
+            && let Some(lru) = self.cache.full_lru()
+        {
+            self.write_back(lru)?;
+            self.cache.remove(lru);
+        }
+        self.cache.insert(tx, entry, written);
+        Ok(())
+    }
+
+    /// Write a cached entry back to disk, if it changed since it was last written.
+    fn write_back(&mut self, tx: TxId) -> Result<(), StoreError> {
+        if let Some(entry) = self.cache.dirty(tx) {
+            let entry = entry.clone();
+            self.write_entry(tx, &entry)?;
+            self.cache.mark_written(tx);
+        }
+        Ok(())
+    }
+
+    /// Append an entry to the records file and point the index to it.
+    fn write_entry(&mut self, tx: TxId, entry: &Entry) -> Result<(), StoreError> {
+        let offset = self.records_len;
+        (&self.records).seek(SeekFrom::Start(offset))?;
+        (&self.records).write_all(&entry.encode(tx))?;
+        self.records_len += ENTRY_SIZE as u64;
+        self.index.insert(tx, offset + 1)?;
+        Ok(())
+    }
+
+    fn read_entry(&self, offset: u64) -> Result<(TxId, Entry), StoreError> {
+        let mut bytes = [0; ENTRY_SIZE];
+        (&self.records).seek(SeekFrom::Start(offset))?;
+        (&self.records).read_exact(&mut bytes)?;
+        Entry::decode(&bytes).ok_or_else(|| StoreError::new("corrupt record entry"))
+    }
+
+    /// Write every changed cached entry back to disk, keeping them cached.
+    fn flush(&mut self) -> Result<(), StoreError> {
+        for tx in self.cache.txs() {
+            self.write_back(tx)?;
+        }
+        Ok(())
+    }
+}
+
+impl TxStore for DiskTxStore {
+    fn contains(&self, tx: TxId) -> Result<bool, StoreError> {
+        Ok(self.cache.contains(tx) || self.index.get(tx)?.is_some())
+    }
+
+    fn insert_id(&mut self, tx: TxId) -> Result<(), StoreError> {
+        Ok(self.index.insert(tx, ID_ONLY)?)
+    }
+
+    fn insert_record(
+        &mut self,
+        tx: TxId,
+        kind: RecordKind,
+        record: DepositRecord,
+    ) -> Result<(), StoreError> {
+        self.cache_entry(tx, Entry::Record(kind, record), false)
+    }
+
+    fn record_mut(
+        &mut self,
+        tx: TxId,
+    ) -> Result<Option<(RecordKind, &mut DepositRecord)>, StoreError> {
+        Ok(match self.entry_mut(tx)? {
+            Some(Entry::Record(kind, record)) => Some((*kind, record)),
+            Some(Entry::Authorization(_)) | None => None,
+        })
+    }
+
+    fn insert_authorization(
+        &mut self,
+        tx: TxId,
+        record: AuthorizationRecord,
+    ) -> Result<(), StoreError> {
+        self.cache_entry(tx, Entry::Authorization(record), false)
+    }
+
+    fn authorization_mut(
+        &mut self,
+        tx: TxId,
+    ) -> Result<Option<&mut AuthorizationRecord>, StoreError> {
+        Ok(match self.entry_mut(tx)? {
+            Some(Entry::Authorization(record)) => Some(record),
+            Some(Entry::Record(..)) | None => None,
+        })
+    }
+
+    fn remove(&mut self, tx: TxId) -> Result<(), StoreError> {
+        // Entries already written stay in the records file, unreferenced
+        self.cache.remove(tx);
+        Ok(self.index.remove(tx)?)
+    }
+
+    fn is_evicted(&self, tx: TxId) -> Result<bool, StoreError> {
+        Ok(!self.cache.contains(tx) && self.index.get(tx)? == Some(EVICTED))
+    }
+
+    fn evict(
+        &mut self,
+        evict: &mut dyn FnMut(&DepositRecord) -> bool,
+    ) -> Result<Vec<TxId>, StoreError> {
+        // Every record is indexed once the cache is flushed, cached ones aren't read
+        // from disk
+        self.flush()?;
+
+        let mut evicted = Vec::new();
+        let (records, cache) = (&self.records, &self.cache);
+        self.index.scan(|tx, value| {
+            let Some(offset) = entry_offset(value) else {
+                return Ok(value);
+            };
+            let read;
+            let entry = match cache.get(tx) {
+                Some(entry) => entry,
+                None => {
+                    let mut bytes = [0; ENTRY_SIZE];
+                    let mut records = records;
+                    records.seek(SeekFrom::Start(offset))?;
+                    records.read_exact(&mut bytes)?;
+                    let (_, entry) = Entry::decode(&bytes).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "corrupt record entry")
+                    })?;
+                    read = entry;
+                    &read
+                }
+            };
+            match entry {
+                Entry::Record(_, record) if evict(record) => {
+                    evicted.push(tx);
+                    Ok(EVICTED)
+                }
+                _ => Ok(value),
+            }
+        })?;
+        for &tx in &evicted {
+            self.cache.remove(tx);
+        }
+        Ok(evicted)
+    }
+}
+
+/// Returns the records file offset an index value points to, if any.
+fn entry_offset(value: u64) -> Option<u64> {
+    match value {
+        EMPTY | ID_ONLY | EVICTED => None,
+        value => Some(value - 1),
+    }
+}
+
+/// A record, as cached and stored in the records file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Record(RecordKind, DepositRecord),
+    Authorization(AuthorizationRecord),
+}
+
+impl Entry {
+    /// Encode an entry as: tx ID, tag, asset, client, amount, disputed, charged back,
+    /// timestamp, state, the scales of the 3 amounts (integers in little endian),
+    /// zero padded.
+    fn encode(&self, tx: TxId) -> [u8; ENTRY_SIZE] {
+        let (tag, asset, client, amounts, timestamp, state) = match self {
+            Entry::Record(kind, record) => (
+                match kind {
+                    RecordKind::Deposit => 0,
+                    RecordKind::Withdrawal => 1,
+                },
+                record.asset,
+                record.client,
+                [record.amount, record.disputed, record.charged_back],
+                record.timestamp,
+                record.state as u8,
+            ),
+            Entry::Authorization(record) => (
+                2,
+                record.asset,
+                record.client,
+                [record.amount, Amount::default(), Amount::default()],
+                0,
+                record.state as u8,
+            ),
+        };
+
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..8].copy_from_slice(&tx.to_le_bytes());
+        bytes[8] = tag;
+        bytes[9..16].copy_from_slice(&asset.to_bytes());
+        bytes[16..24].copy_from_slice(&client.to_le_bytes());
+        for (i, amount) in amounts.iter().enumerate() {
+            let start = 24 + 8 * i;
+            bytes[start..start + 8].copy_from_slice(&amount.scaled().to_le_bytes());
+            bytes[57 + i] = amount.scale() as u8;
+        }
+        bytes[48..56].copy_from_slice(&timestamp.to_le_bytes());
+        bytes[56] = state;
+        bytes
+    }
+
+    /// Decode an entry encoded by [`Entry::encode`], returns `None` if it is invalid.
+    fn decode(bytes: &[u8; ENTRY_SIZE]) -> Option<(TxId, Self)> {
+        let u64_at = |start: usize| u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap());
+        let amount_at = |i: usize| {
+            let precision = Precision::new(u32::from(bytes[57 + i]), RoundingMode::Exact)?;
+            Some(Amount::from_scaled_at(u64_at(24 + 8 * i) as i64, precision))
+        };
+
+        let tx = u64_at(0);
+        let asset = Asset::from_bytes(bytes[9..16].try_into().unwrap())?;
+        let client = u64_at(16);
+        let amount = amount_at(0)?;
+        let kind = match bytes[8] {
+            0 => RecordKind::Deposit,
+            1 => RecordKind::Withdrawal,
+            2 => {
+                let state = match bytes[56] {
+                    0 => AuthorizationState::Pending,
+                    1 => AuthorizationState::Captured,
+                    2 => AuthorizationState::Voided,
+                    _ => return None,
+                };
+                let record = AuthorizationRecord {
+                    client,
+                    asset,
+                    amount,
+                    state,
+                };
+                return Some((tx, Entry::Authorization(record)));
+            }
+            _ => return None,
+        };
+        let state = match bytes[56] {
+            0 => DepositState::Ok,
+            1 => DepositState::Disputed,
+            2 => DepositState::ChargedBack,
+            _ => return None,
+        };
+        let record = DepositRecord {
+            client,
+            asset,
+            amount,
+            disputed: amount_at(1)?,
+            charged_back: amount_at(2)?,
+            timestamp: u64_at(48),
+            state,
+        };
+        Some((tx, Entry::Record(kind, record)))
+    }
+}
+
+/// Least recently used cache of entries.
+#[derive(Debug)]
+struct Cache {
+    capacity: usize,
+    entries: HashMap<TxId, Cached>,
+    /// Entries by last use
+    uses: BTreeMap<u64, TxId>,
+    clock: u64,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            uses: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn contains(&self, tx: TxId) -> bool {
+        self.entries.contains_key(&tx)
+    }
+
+    /// Return a cached entry, without marking it as used.
+    fn get(&self, tx: TxId) -> Option<&Entry> {
+        self.entries.get(&tx).map(|cached| &cached.entry)
+    }
+
+    /// Return a cached entry, marking it as the most recently used.
+    fn get_mut(&mut self, tx: TxId) -> Option<&mut Entry> {
+        let cached = self.entries.get_mut(&tx)?;
+        self.uses.remove(&cached.used);
+        self.clock += 1;
+        cached.used = self.clock;
+        self.uses.insert(self.clock, tx);
+        Some(&mut cached.entry)
+    }
+
+    /// Return the IDs of the cached entries.
+    fn txs(&self) -> Vec<TxId> {
+        self.entries.keys().copied().collect()
+    }
+
+    /// Return the least recently used entry, if the cache is full.
+    fn full_lru(&self) -> Option<TxId> {
+        if self.entries.len() < self.capacity {
+            return None;
+        }
+        self.uses.first_key_value().map(|(_, &lru)| lru)
+    }
+
+    /// Return a cached entry if it changed since it was last written to disk.
+    fn dirty(&self, tx: TxId) -> Option<&Entry> {
+        self.entries
+            .get(&tx)
+            .filter(|cached| cached.written.as_ref() != Some(&cached.entry))
+            .map(|cached| &cached.entry)
+    }
+
+    /// Mark a cached entry as written to disk as it is.
+    fn mark_written(&mut self, tx: TxId) {
+        if let Some(cached) = self.entries.get_mut(&tx) {
+            cached.written = Some(cached.entry.clone());
+        }
+    }
+
+    /// Add or replace an entry, as `written` to disk or not yet, marking it as the
+    /// most recently used.
+    fn insert(&mut self, tx: TxId, entry: Entry, written: bool) {
+        self.clock += 1;
+        let cached = Cached {
+            written: written.then(|| entry.clone()),
+            entry,
+            used: self.clock,
+        };
+        if let Some(replaced) = self.entries.insert(tx, cached) {
+            self.uses.remove(&replaced.used);
+        }
+        self.uses.insert(self.clock, tx);
+    }
+
+    fn remove(&mut self, tx: TxId) {
+        if let Some(cached) = self.entries.remove(&tx) {
+            self.uses.remove(&cached.used);
+        }
+    }
+}
+
+/// A cached entry.
+#[derive(Debug)]
+struct Cached {
+    entry: Entry,
+    /// Last use, by the cache clock
+    used: u64,
+    /// The entry as last written to disk, `None` if it never was. The entry is dirty,
+    /// to be written back, when it differs.
+    written: Option<Entry>,
+}
+
+/// On-disk hash table of transaction IDs to `u64` values, with linear probing.
+#[derive(Debug)]
+struct DiskIndex {
+    path: PathBuf,
+    file: File,
+    /// Number of slots, a power of two
+    slots: u64,
+    /// Number of used slots
+    len: u64,
+}
+
+impl DiskIndex {
+    fn create(path: PathBuf, slots: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        // Zero filled: every slot is empty
+        file.set_len(slots * SLOT_SIZE as u64)?;
+        Ok(Self {
+            path,
+            file,
+            slots,
+            len: 0,
+        })
+    }
+
+    fn home(&self, tx: TxId) -> u64 {
+        // Fibonacci hashing, spreads sequential IDs
+        tx.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - self.slots.trailing_zeros())
+    }
+
+    fn read_slot(&self, slot: u64) -> io::Result<(TxId, u64)> {
+        let mut bytes = [0; SLOT_SIZE];
+        (&self.file).seek(SeekFrom::Start(slot * SLOT_SIZE as u64))?;
+        (&self.file).read_exact(&mut bytes)?;
+        let (tx, value) = bytes.split_at(8);
+        Ok((
+            u64::from_le_bytes(tx.try_into().unwrap()),
+            u64::from_le_bytes(value.try_into().unwrap()),
+        ))
+    }
+
+    fn write_slot(&self, slot: u64, tx: TxId, value: u64) -> io::Result<()> {
+        let mut bytes = [0; SLOT_SIZE];
+        bytes[..8].copy_from_slice(&tx.to_le_bytes());
+        bytes[8..].copy_from_slice(&value.to_le_bytes());
+        (&self.file).seek(SeekFrom::Start(slot * SLOT_SIZE as u64))?;
+        (&self.file).write_all(&bytes)
+    }
+
+    /// Return the slot of a transaction ID, or the empty slot where it would be
+    /// inserted, and its value.
+    fn find(&self, tx: TxId) -> io::Result<(u64, u64)> {
+        let mut slot = self.home(tx);
+        loop {
+            let (slot_tx, value) = self.read_slot(slot)?;
+            if value == EMPTY || slot_tx == tx {
+                return Ok((slot, value));
+            }
+            slot = (slot + 1) & (self.slots - 1);
+        }
+    }
+
+    fn get(&self, tx: TxId) -> io::Result<Option<u64>> {
+        let (_, value) = self.find(tx)?;
+        Ok((value != EMPTY).then_some(value))
+    }
+
+    /// Insert or replace the value of a transaction ID, which must not be `EMPTY`.
+    fn insert(&mut self, tx: TxId, value: u64) -> io::Result<()> {
+        let (slot, previous) = self.find(tx)?;
+        self.write_slot(slot, tx, value)?;
+        if previous == EMPTY {
+            self.len += 1;
+            // Keep probe sequences short
+            if self.len * 2 > self.slots {
+                self.grow()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a transaction ID, if present.
+    ///
+    /// The following slots of the probe sequence are shifted back into the freed
+    /// slot, so lookups don't stop early at it.
+    fn remove(&mut self, tx: TxId) -> io::Result<()> {
+        let (mut free, value) = self.find(tx)?;
+        if value == EMPTY {
+            return Ok(());
+        }
+        let mask = self.slots - 1;
+        let mut slot = free;
+        loop {
+            slot = (slot + 1) & mask;
+            let (slot_tx, value) = self.read_slot(slot)?;
+            if value == EMPTY {
+                break;
+            }
+            // Only move IDs whose probe sequence goes through the free slot
+            let home = self.home(slot_tx);
+            if slot.wrapping_sub(home) & mask >= slot.wrapping_sub(free) & mask {
+                self.write_slot(free, slot_tx, value)?;
+                free = slot;
+            }
+        }
+        self.write_slot(free, 0, EMPTY)?;
+        self.len -= 1;
+        Ok(())
+    }
+
+    /// Call `f` with every used slot, replacing its value with the returned one.
+    fn scan(&mut self, mut f: impl FnMut(TxId, u64) -> io::Result<u64>) -> io::Result<()> {
+        let mut chunk = vec![0; SCAN_SLOTS * SLOT_SIZE];
+        let mut start = 0;
+        while start < self.slots {
+            let count = SCAN_SLOTS.min((self.slots - start) as usize);
+            let bytes = &mut chunk[..count * SLOT_SIZE];
+            (&self.file).seek(SeekFrom::Start(start * SLOT_SIZE as u64))?;
+            (&self.file).read_exact(bytes)?;
+
+            let mut changed = false;
+            for slot in bytes.chunks_exact_mut(SLOT_SIZE) {
+                let (tx, value) = slot.split_at_mut(8);
+                let tx = u64::from_le_bytes((&*tx).try_into().unwrap());
+                let old = u64::from_le_bytes((&*value).try_into().unwrap());
+                if old == EMPTY {
+                    continue;
+                }
+                let new = f(tx, old)?;
+                if new != old {
+                    value.copy_from_slice(&new.to_le_bytes());
+                    changed = true;
+                }
+            }
+
+            if changed {
+                (&self.file).seek(SeekFrom::Start(start * SLOT_SIZE as u64))?;
+                (&self.file).write_all(bytes)?;
+            }
+            start += count as u64;
+        }
+        Ok(())
+    }
+
+    /// Double the number of slots, rehashing into a new file.
+    fn grow(&mut self) -> io::Result<()> {
+        let mut grown = DiskIndex::create(self.path.with_extension("tmp"), self.slots * 2)?;
+        self.scan(|tx, value| {
+            let (slot, _) = grown.find(tx)?;
+            grown.write_slot(slot, tx, value)?;
+            grown.len += 1;
+            Ok(value)
+        })?;
+        fs::rename(&grown.path, &self.path)?;
+        grown.path = self.path.clone();
+        *self = grown;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const USD: Asset = Asset::new("USD").unwrap();
+
+    fn record(client: u64, amount: i64) -> DepositRecord {
+        DepositRecord::new(client, USD, Amount::from_scaled(amount), 7)
+    }
+
+    fn store(cache_capacity: usize) -> (TempDir, DiskTxStore) {
+        let dir = TempDir::new().unwrap();
+        let store = DiskTxStore::create(dir.path(), cache_capacity).unwrap();
+        (dir, store)
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let mut disputed = record(3, 100);
+        disputed.set_disputed(Amount::from_scaled(-40));
+        let sats = Precision::new(8, RoundingMode::Exact).unwrap();
+        disputed.set_charged_back(Amount::from_scaled_at(7, sats));
+        let authorization = AuthorizationRecord {
+            state: AuthorizationState::Voided,
+            ..AuthorizationRecord::new(4, USD, Amount::from_scaled(i64::MIN))
+        };
+
+        for entry in [
+            Entry::Record(RecordKind::Withdrawal, disputed),
+            Entry::Authorization(authorization),
+        ] {
+            let (tx, decoded) = Entry::decode(&entry.encode(u64::MAX)).unwrap();
+            assert_eq!(tx, u64::MAX);
+            assert_eq!(format!("{decoded:?}"), format!("{entry:?}"));
+        }
+        assert!(Entry::decode(&[0; ENTRY_SIZE]).is_none());
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        let (_dir, mut store) = store(10);
+        for tx in 0..1_000 {
+            store
+                .insert_record(tx, RecordKind::Deposit, record(tx, tx as i64))
+                .unwrap();
+        }
+        assert_eq!(store.cached(), 10);
+
+        // Cold records are read back from disk
+        for tx in 0..1_000 {
+            assert!(store.contains(tx).unwrap());
+            let (kind, record) = store.record_mut(tx).unwrap().unwrap();
+            assert_eq!(kind, RecordKind::Deposit);
+            assert_eq!(record.client, tx);
+            assert_eq!(record.amount, Amount::from_scaled(tx as i64));
+        }
+        assert!(!store.contains(1_000).unwrap());
+        assert!(store.record_mut(1_000).unwrap().is_none());
+        assert_eq!(store.cached(), 10);
+    }
+
+    #[test]
+    fn updates_are_written_back() {
+        let (_dir, mut store) = store(1);
+        store
+            .insert_record(1, RecordKind::Deposit, record(1, 100))
+            .unwrap();
+        store
+            .insert_authorization(2, AuthorizationRecord::new(1, USD, Amount::from_scaled(5)))
+            .unwrap();
+
+        let (_, record) = store.record_mut(1).unwrap().unwrap();
+        record.set_disputed(Amount::from_scaled(30));
+        store.authorization_mut(2).unwrap().unwrap().state = AuthorizationState::Captured;
+
+        let (_, record) = store.record_mut(1).unwrap().unwrap();
+        assert_eq!(record.disputed, Amount::from_scaled(30));
+        assert_eq!(record.state, DepositState::Disputed);
+        assert_eq!(
+            store.authorization_mut(2).unwrap().unwrap().state,
+            AuthorizationState::Captured
+        );
+        // Records and authorizations are not confused
+        assert!(store.authorization_mut(1).unwrap().is_none());
+        assert!(store.record_mut(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn unchanged_records_are_not_written_back() {
+        let (_dir, mut store) = store(1);
+        for tx in 1..=2 {
+            store
+                .insert_record(tx, RecordKind::Deposit, record(1, 100))
+                .unwrap();
+        }
+        assert_eq!(store.records_len, ENTRY_SIZE as u64);
+
+        // Reading 1 writes back 2, which was never written, reading 2 then doesn't
+        // write back 1
+        store.record_mut(1).unwrap().unwrap();
+        store.record_mut(2).unwrap().unwrap();
+        assert_eq!(store.records_len, 2 * ENTRY_SIZE as u64);
+
+        // Nor does evicting, which writes back changed records only
+        store.evict(&mut |_| false).unwrap();
+        assert_eq!(store.records_len, 2 * ENTRY_SIZE as u64);
+        let (_, record) = store.record_mut(2).unwrap().unwrap();
+        record.set_disputed(Amount::from_scaled(10));
+        store.evict(&mut |_| false).unwrap();
+        assert_eq!(store.records_len, 3 * ENTRY_SIZE as u64);
+        store.evict(&mut |_| false).unwrap();
+        assert_eq!(store.records_len, 3 * ENTRY_SIZE as u64);
+
+        // Changes are read back
+        store.record_mut(1).unwrap().unwrap();
+        let (_, record) = store.record_mut(2).unwrap().unwrap();
+        assert_eq!(record.disputed, Amount::from_scaled(10));
+    }
+
+    #[test]
+    fn ids_and_eviction() {
+        let (_dir, mut store) = store(4);
+        store.insert_id(1).unwrap();
+        for tx in 2..100 {
+            store
+                .insert_record(tx, RecordKind::Deposit, record(tx % 2, 10))
+                .unwrap();
+        }
+        assert!(store.contains(1).unwrap());
+        assert!(store.record_mut(1).unwrap().is_none());
+
+        let evicted = store.evict(&mut |record| record.client == 0).unwrap();
+        assert_eq!(evicted.len(), 49);
+        // Evicted records leave the cache, the others stay
+        assert_eq!(store.cached(), 2);
+        for tx in 2..100 {
+            assert!(store.contains(tx).unwrap());
+            assert_eq!(store.is_evicted(tx).unwrap(), tx % 2 == 0);
+            assert_eq!(store.record_mut(tx).unwrap().is_some(), tx % 2 == 1);
+        }
+        assert!(!store.is_evicted(1).unwrap());
+    }
+
+    #[test]
+    fn index_grows() {
+        let dir = TempDir::new().unwrap();
+        let mut index = DiskIndex::create(dir.path().join("index"), 4).unwrap();
+        for tx in 0..100 {
+            index.insert(tx * 7, tx + 1).unwrap();
+        }
+        assert_eq!(index.len, 100);
+        assert!(index.slots >= 200);
+        for tx in 0..100 {
+            assert_eq!(index.get(tx * 7).unwrap(), Some(tx + 1));
+        }
+        assert_eq!(index.get(1).unwrap(), None);
+
+        // Replacing a value doesn't use a new slot
+        index.insert(0, ID_ONLY).unwrap();
+        assert_eq!(index.len, 100);
+        assert_eq!(index.get(0).unwrap(), Some(ID_ONLY));
+    }
+
+    #[test]
+    fn index_removes_ids() {
+        let dir = TempDir::new().unwrap();
+        // Few slots, long probe sequences wrapping around
+        let mut index = DiskIndex::create(dir.path().join("index"), 64).unwrap();
+        for tx in 0..30 {
+            index.insert(tx * 64, tx + 1).unwrap();
+        }
+        for tx in (0..30).step_by(3) {
+            index.remove(tx * 64).unwrap();
+        }
+        index.remove(1).unwrap();
+
+        assert_eq!(index.len, 20);
+        for tx in 0..30 {
+            let expected = (tx % 3 != 0).then_some(tx + 1);
+            assert_eq!(index.get(tx * 64).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn removed_records_are_forgotten() {
+        let (_dir, mut store) = store(2);
+        for tx in 1..10 {
+            store
+                .insert_record(tx, RecordKind::Deposit, record(1, 10))
+                .unwrap();
+        }
+        store.insert_id(10).unwrap();
+        // Cached, written back and ID only
+        for tx in [9, 1, 10] {
+            store.remove(tx).unwrap();
+            assert!(!store.contains(tx).unwrap());
+            assert!(store.record_mut(tx).unwrap().is_none());
+        }
+        assert!(store.record_mut(5).unwrap().is_some());
+    }
+}
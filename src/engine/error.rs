@@ -1,9 +1,14 @@
 //! Error types for transaction processing.
 
+use std::io;
+
+use super::codec::CodecError;
+use super::event::Event;
+
 use thiserror::Error;
 
-use crate::Amount;
-use crate::model::{ClientId, TxId};
+use crate::amount::{AmountDisplay, ParseAmountError};
+use crate::model::{Asset, AuthorizationState, ClientId, OperatorId, TxId};
 
 /// Top-level error returned by [`Engine::apply`](super::Engine::apply).
 #[derive(Debug, Error)]
@@ -14,8 +19,64 @@ pub enum EngineError {
     #[error("withdrawal failed: {0}")]
     Withdrawal(#[from] WithdrawalError),
 
+    #[error("authorization failed: {0}")]
+    Authorization(#[from] AuthorizationError),
+
+    #[error("{0}")]
+    AuthorizationOperation(#[from] AuthorizationOperationError),
+
+    #[error("transfer failed: {0}")]
+    Transfer(#[from] TransferError),
+
+    #[error("admin transaction by operator {0} failed: {1}")]
+    Admin(OperatorId, AdminError),
+
     #[error("{0}")]
     DepositOperation(#[from] DepositOperationError),
+
+    #[error("{0}")]
+    Overflow(#[from] BalanceOverflow),
+
+    #[error("{0}")]
+    Storage(#[from] StoreError),
+
+    #[error("{0}")]
+    Journal(#[from] JournalError),
+
+    /// The transaction was rejected, and appending the changes it kept to the journal
+    /// failed.
+    #[error("{rejection}, and journaling it failed: {source}")]
+    RejectionNotJournaled {
+        rejection: Box<EngineError>,
+        source: JournalError,
+    },
+}
+
+/// A balance update would overflow the range of [`Amount`](crate::Amount).
+///
+/// The account is left unchanged when this error is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("balance overflow on account {0}")]
+pub struct BalanceOverflow(pub ClientId);
+
+/// A storage backend failed, see [`AccountStore`](super::AccountStore) and
+/// [`TxStore`](super::TxStore).
+///
+/// The transaction is rejected, and the engine state left unchanged.
+#[derive(Debug, Error)]
+#[error("storage failure: {0}")]
+pub struct StoreError(pub Box<dyn std::error::Error + Send + Sync>);
+
+impl StoreError {
+    pub fn new(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self(error.into())
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(error: io::Error) -> Self {
+        Self::new(error)
+    }
 }
 
 /// Error during deposit processing.
@@ -25,6 +86,8 @@ pub enum DepositError {
     AccountFrozen(ClientId),
     #[error("duplicate transaction id {0}")]
     DuplicateTxId(TxId),
+    #[error("transaction {0} has non-positive amount {1}")]
+    NonPositiveAmount(TxId, AmountDisplay),
 }
 
 /// Error during withdrawal processing.
@@ -32,10 +95,84 @@ pub enum DepositError {
 pub enum WithdrawalError {
     #[error("account {0} is frozen")]
     AccountFrozen(ClientId),
-    #[error("insufficient available funds for client {0}: available {1}, requested {2}")]
-    InsufficientFunds(ClientId, Amount, Amount),
+    #[error("client {0} not found")]
+    ClientNotFound(ClientId),
+    #[error("insufficient available {1} funds for client {0}: available {2}, requested {3}")]
+    InsufficientFunds(ClientId, Asset, AmountDisplay, AmountDisplay),
+    #[error("duplicate transaction id {0}")]
+    DuplicateTxId(TxId),
+    #[error("transaction {0} has non-positive amount {1}")]
+    NonPositiveAmount(TxId, AmountDisplay),
+}
+
+/// Error during withdrawal authorization processing.
+#[derive(Debug, Error)]
+pub enum AuthorizationError {
+    #[error("account {0} is frozen")]
+    AccountFrozen(ClientId),
+    #[error("insufficient available {1} funds for client {0}: available {2}, requested {3}")]
+    InsufficientFunds(ClientId, Asset, AmountDisplay, AmountDisplay),
+    #[error("duplicate transaction id {0}")]
+    DuplicateTxId(TxId),
+    #[error("transaction {0} has non-positive amount {1}")]
+    NonPositiveAmount(TxId, AmountDisplay),
+}
+
+/// The type of authorization operation being performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorizationOperation {
+    Capture,
+    Void,
+}
+
+/// Unified error for authorization operations (capture, void).
+#[derive(Debug, Error)]
+pub enum AuthorizationOperationError {
+    #[error("{0:?}: authorization {1} not found")]
+    TxNotFound(AuthorizationOperation, TxId),
+
+    #[error("{0:?}: authorization {1} belongs to client {2}, not {3}")]
+    ClientMismatch(AuthorizationOperation, TxId, ClientId, ClientId),
+
+    #[error("{0:?}: authorization {1} is already {2:?}")]
+    InvalidState(AuthorizationOperation, TxId, AuthorizationState),
+
+    #[error("{0:?}: client {1} not found")]
+    ClientNotFound(AuthorizationOperation, ClientId),
+}
+
+/// Error during transfer processing.
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("sender account {0} is frozen")]
+    SenderFrozen(ClientId),
+    #[error("receiver account {0} is frozen")]
+    ReceiverFrozen(ClientId),
+    #[error("client {0} cannot transfer to itself")]
+    SameClient(ClientId),
+    #[error("insufficient available {1} funds for client {0}: available {2}, requested {3}")]
+    InsufficientFunds(ClientId, Asset, AmountDisplay, AmountDisplay),
+    #[error("duplicate transaction id {0}")]
+    DuplicateTxId(TxId),
+    #[error("transaction {0} has non-positive amount {1}")]
+    NonPositiveAmount(TxId, AmountDisplay),
+}
+
+/// Error during admin transaction processing.
+#[derive(Debug, Error)]
+pub enum AdminError {
+    #[error("client {0} not found")]
+    ClientNotFound(ClientId),
+    #[error("account {0} is already locked")]
+    AlreadyLocked(ClientId),
+    #[error("account {0} is not locked")]
+    NotLocked(ClientId),
+    #[error("insufficient available {1} funds for client {0}: available {2}, adjustment {3}")]
+    InsufficientFunds(ClientId, Asset, AmountDisplay, AmountDisplay),
     #[error("duplicate transaction id {0}")]
     DuplicateTxId(TxId),
+    #[error("transaction {0} has zero amount")]
+    ZeroAmount(TxId),
 }
 
 /// The type of deposit operation being performed.
@@ -44,20 +181,149 @@ pub enum DepositOperation {
     Dispute,
     Resolve,
     Chargeback,
+    Represent,
 }
 
-/// Unified error for deposit operations (dispute, resolve, chargeback).
+/// Unified error for deposit operations (dispute, resolve, chargeback, represent).
+///
+/// Also returned for operations on disputable withdrawals.
 #[derive(Debug, Error)]
 pub enum DepositOperationError {
-    #[error("{0:?}: deposit {1} not found")]
+    #[error("{0:?}: transaction {1} not found")]
     TxNotFound(DepositOperation, TxId),
 
-    #[error("{0:?}: deposit {1} belongs to client {2}, not {3}")]
+    #[error("{0:?}: transaction {1} belongs to client {2}, not {3}")]
     ClientMismatch(DepositOperation, TxId, ClientId, ClientId),
 
-    #[error("{0:?}: deposit {1} is not in expected state")]
+    #[error("{0:?}: transaction {1} is not in expected state")]
     InvalidState(DepositOperation, TxId),
 
     #[error("{0:?}: client {1} not found")]
     ClientNotFound(DepositOperation, ClientId),
+
+    #[error("{0:?}: transaction {1} has non-positive amount {2}")]
+    NonPositiveAmount(DepositOperation, TxId, AmountDisplay),
+
+    /// The requested amount can't be scaled at the precision of the transaction's
+    /// asset.
+    #[error("{0:?}: invalid amount on transaction {1}: {2}")]
+    InvalidAmount(DepositOperation, TxId, ParseAmountError),
+
+    #[error("{0:?}: transaction {1} is outside the dispute window")]
+    WindowExpired(DepositOperation, TxId),
+
+    /// The requested amount exceeds the undisputed remainder (dispute), the
+    /// disputed portion (resolve, chargeback) or the charged back portion (represent)
+    /// of the transaction.
+    #[error("{0:?}: amount {2} exceeds the {3} remaining on transaction {1}")]
+    AmountExceeded(DepositOperation, TxId, AmountDisplay, AmountDisplay),
+
+    #[error("{0:?}: account {1} is frozen")]
+    AccountFrozen(DepositOperation, ClientId),
+
+    /// The available funds can't cover the disputed amount, see
+    /// [`NegativeAvailablePolicy`](super::NegativeAvailablePolicy).
+    #[error("{0:?}: available {2} can't cover the {3} disputed on transaction {1}")]
+    InsufficientFunds(DepositOperation, TxId, AmountDisplay, AmountDisplay),
+
+    #[error("{0:?}: transaction {1} was already disputed and resolved {2} times")]
+    DisputeLimitReached(DepositOperation, TxId, u32),
+}
+
+/// Error applying a batch, see [`Engine::apply_batch`](super::Engine::apply_batch).
+#[derive(Debug, Error)]
+pub enum BatchError {
+    /// A transaction (at `index` in the batch) was rejected, the batch was rolled back.
+    #[error("transaction {tx} (#{index} of the batch) failed, batch rolled back: {source}")]
+    Rejected {
+        index: usize,
+        tx: TxId,
+        source: EngineError,
+    },
+    /// A transaction was rejected, and a storage failure interrupted the rollback: the
+    /// batch is partially rolled back.
+    #[error("transaction {tx} (#{index} of the batch) failed ({source}), rollback failed: {error}")]
+    RollbackFailed {
+        index: usize,
+        tx: TxId,
+        source: EngineError,
+        error: StoreError,
+    },
+    /// Appending the events of the batch to the journal failed, the batch was rolled
+    /// back.
+    #[error("batch rolled back: {0}")]
+    Journal(JournalError),
+    /// Appending the events of the batch to the journal failed, and a storage failure
+    /// interrupted the rollback: the batch is partially rolled back.
+    #[error("{source}, rollback failed: {error}")]
+    JournalRollbackFailed {
+        source: JournalError,
+        error: StoreError,
+    },
+}
+
+/// Error loading an [`EngineConfig`](super::EngineConfig).
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid config: {0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+/// Error writing or reading an engine snapshot, see
+/// [`Engine::snapshot`](super::Engine::snapshot).
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("snapshot i/o failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a snapshot file")]
+    InvalidMagic,
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedVersion(u32),
+    #[error("invalid snapshot: {0}")]
+    Invalid(&'static str),
+    /// Amounts of an asset were stored with a different scale than configured.
+    #[error("asset {0} has scale {1} in the snapshot, but {2} in the config")]
+    PrecisionMismatch(Asset, u32, u32),
+}
+
+impl From<CodecError> for SnapshotError {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::Io(e) => Self::Io(e),
+            CodecError::Invalid(reason) => Self::Invalid(reason),
+        }
+    }
+}
+
+/// Error appending to or replaying a [`Journal`](super::Journal).
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("journal i/o failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a journal file")]
+    InvalidMagic,
+    #[error("unsupported journal version {0}")]
+    UnsupportedVersion(u32),
+    #[error("invalid journal: {0}")]
+    Invalid(&'static str),
+    /// A replayed event refers to a missing account or record, or overflows a balance.
+    #[error("journal event doesn't apply to the engine state: {0:?}")]
+    Inconsistent(Box<Event>),
+    #[error("{0}")]
+    Storage(#[from] StoreError),
+    /// A failed append left a partial frame which couldn't be truncated, the journal
+    /// refuses further appends.
+    #[error("journal unusable after a failed append")]
+    Poisoned,
+}
+
+impl From<CodecError> for JournalError {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::Io(e) => Self::Io(e),
+            CodecError::Invalid(reason) => Self::Invalid(reason),
+        }
+    }
 }
@@ -0,0 +1,1205 @@
+//! Domain events and the append-only journal.
+//!
+//! Applying a transaction emits the [`Event`]s describing every state change it
+//! made, see [`Engine::last_events`]. With a [`Journal`] set, they are appended to
+//! it after each transaction, and [`Engine::replay`] rebuilds the same state from
+//! the journal alone.
+//!
+//! Journal layout: a `TXJOURNL` magic and `u32` version header, then one frame per
+//! transaction (or batch, see [`Engine::apply_batch`]) with events: its `u32` byte
+//! length, then its events (tag byte and fields, see [`codec`](super::codec)). A
+//! frame is written at once and synced to disk, a crash while appending leaves at
+//! most a partial last frame, which is dropped when replaying or reopening the journal.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::codec::{CodecError, Reader, Writer};
+use super::{AccountStore, ClientAccount, Engine, FreezeReason, JournalError, RecordKind, TxStore};
+use crate::Amount;
+use crate::model::{
+    Asset, AuthorizationRecord, AuthorizationState, ClientId, DepositRecord, DepositState,
+    Metadata, Timestamp, TxId,
+};
+
+const MAGIC: &[u8; 8] = b"TXJOURNL";
+/// Current format version, bumped on any layout change.
+const VERSION: u32 = 3;
+const HEADER_LEN: u64 = 12;
+
+/// A change of the engine state, emitted by a transaction.
+///
+/// Balance events only move funds, record events update the transaction records
+/// (and the account chargeback count), so a transaction usually emits one of each.
+/// Rejected transactions can still emit [`Event::AccountOpened`], as this change is
+/// kept. The clock only moves forward with applied transactions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The engine clock moved forward.
+    ClockAdvanced { timestamp: Timestamp },
+    /// An empty account was opened.
+    AccountOpened { client: ClientId },
+    /// The account was frozen, by a chargeback or an admin lock.
+    AccountFrozen {
+        client: ClientId,
+        reason: FreezeReason,
+    },
+    /// The account was unfrozen, by a re-presentment or an admin unlock.
+    AccountUnfrozen { client: ClientId },
+
+    /// Funds were credited to the available balance.
+    FundsCredited {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Funds were debited from the available balance.
+    FundsDebited {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Funds moved from available to held, by a deposit dispute.
+    FundsHeld {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Funds moved from held back to available, by a deposit resolve or a
+    /// withdrawal chargeback.
+    FundsReleased {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Withdrawn funds were provisionally returned as held, by a withdrawal dispute.
+    HeldFundsAdded {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Held funds were removed, by a deposit chargeback or a withdrawal resolve.
+    HeldFundsRemoved {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Funds moved from available to reserved, by an authorization.
+    FundsReserved {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Reserved funds moved back to available, by a void.
+    ReservationReleased {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// Reserved funds were removed, by a capture.
+    ReservedFundsRemoved {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    },
+
+    /// A deposit (or disputable withdrawal) was recorded for disputes.
+    TransactionRecorded {
+        tx: TxId,
+        kind: RecordKind,
+        client: ClientId,
+        asset: Asset,
+        amount: Amount,
+        timestamp: Timestamp,
+    },
+    /// The ID of a transaction without record was stored, for duplicate checking.
+    TransactionIdRecorded { tx: TxId },
+    /// A pending withdrawal authorization was recorded.
+    AuthorizationRecorded {
+        tx: TxId,
+        client: ClientId,
+        asset: Asset,
+        amount: Amount,
+    },
+    /// A withdrawal authorization was captured.
+    AuthorizationCaptured { client: ClientId, tx: TxId },
+    /// A withdrawal authorization was voided.
+    AuthorizationVoided { client: ClientId, tx: TxId },
+    /// The metadata of a deposit was retained.
+    MetadataRetained { tx: TxId, metadata: Metadata },
+    /// An amount of a record was put under dispute.
+    DisputeOpened {
+        client: ClientId,
+        tx: TxId,
+        amount: Amount,
+    },
+    /// An amount of the disputed portion of a record was resolved.
+    DisputeResolved {
+        client: ClientId,
+        tx: TxId,
+        amount: Amount,
+    },
+    /// An amount of the disputed portion of a record was charged back.
+    ChargebackApplied {
+        client: ClientId,
+        tx: TxId,
+        amount: Amount,
+    },
+    /// An amount of the charged back portion of a record was re-presented.
+    ChargebackReversed {
+        client: ClientId,
+        tx: TxId,
+        amount: Amount,
+    },
+    /// Records older than the dispute window were evicted, see
+    /// [`Engine::evict_expired`].
+    RecordsEvicted { window: u64, txs: Vec<TxId> },
+}
+
+impl Event {
+    fn encode<W: Write>(&self, w: &mut Writer<W>) -> io::Result<()> {
+        match self {
+            Event::ClockAdvanced { timestamp } => {
+                w.u8(0)?;
+                w.u64(*timestamp)
+            }
+            Event::AccountOpened { client } => {
+                w.u8(1)?;
+                w.u64(*client)
+            }
+            Event::AccountFrozen { client, reason } => {
+                w.u8(2)?;
+                w.u64(*client)?;
+                w.freeze_reason(Some(*reason))
+            }
+            Event::AccountUnfrozen { client } => {
+                w.u8(3)?;
+                w.u64(*client)
+            }
+            Event::FundsCredited {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::FundsDebited {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::FundsHeld {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::FundsReleased {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::HeldFundsAdded {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::HeldFundsRemoved {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::FundsReserved {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::ReservationReleased {
+                client,
+                tx,
+                asset,
+                amount,
+            }
+            | Event::ReservedFundsRemoved {
+                client,
+                tx,
+                asset,
+                amount,
+            } => {
+                w.u8(match self {
+                    Event::FundsCredited { .. } => 4,
+                    Event::FundsDebited { .. } => 5,
+                    Event::FundsHeld { .. } => 6,
+                    Event::FundsReleased { .. } => 7,
+                    Event::HeldFundsAdded { .. } => 8,
+                    Event::HeldFundsRemoved { .. } => 9,
+                    Event::FundsReserved { .. } => 10,
+                    Event::ReservationReleased { .. } => 11,
+                    _ => 12,
+                })?;
+                w.u64(*client)?;
+                w.u64(*tx)?;
+                w.asset(*asset)?;
+                w.amount(*amount)
+            }
+            Event::TransactionRecorded {
+                tx,
+                kind,
+                client,
+                asset,
+                amount,
+                timestamp,
+            } => {
+                w.u8(13)?;
+                w.u64(*tx)?;
+                w.record_kind(*kind)?;
+                w.u64(*client)?;
+                w.asset(*asset)?;
+                w.amount(*amount)?;
+                w.u64(*timestamp)
+            }
+            Event::TransactionIdRecorded { tx } => {
+                w.u8(14)?;
+                w.u64(*tx)
+            }
+            Event::AuthorizationRecorded {
+                tx,
+                client,
+                asset,
+                amount,
+            } => {
+                w.u8(15)?;
+                w.u64(*tx)?;
+                w.u64(*client)?;
+                w.asset(*asset)?;
+                w.amount(*amount)
+            }
+            Event::AuthorizationCaptured { client, tx }
+            | Event::AuthorizationVoided { client, tx } => {
+                let captured = matches!(self, Event::AuthorizationCaptured { .. });
+                w.u8(if captured { 16 } else { 17 })?;
+                w.u64(*client)?;
+                w.u64(*tx)
+            }
+            Event::MetadataRetained { tx, metadata } => {
+                w.u8(18)?;
+                w.u64(*tx)?;
+                w.len(metadata.len())?;
+                for (key, value) in metadata {
+                    w.str(key)?;
+                    w.str(value)?;
+                }
+                Ok(())
+            }
+            Event::DisputeOpened { client, tx, amount }
+            | Event::DisputeResolved { client, tx, amount }
+            | Event::ChargebackApplied { client, tx, amount }
+            | Event::ChargebackReversed { client, tx, amount } => {
+                w.u8(match self {
+                    Event::DisputeOpened { .. } => 19,
+                    Event::DisputeResolved { .. } => 20,
+                    Event::ChargebackApplied { .. } => 21,
+                    _ => 22,
+                })?;
+                w.u64(*client)?;
+                w.u64(*tx)?;
+                w.amount(*amount)
+            }
+            Event::RecordsEvicted { window, txs } => {
+                w.u8(23)?;
+                w.u64(*window)?;
+                w.len(txs.len())?;
+                txs.iter().try_for_each(|tx| w.u64(*tx))
+            }
+        }
+    }
+
+    fn decode<R: Read>(r: &mut Reader<R>) -> Result<Self, CodecError> {
+        Ok(match r.u8()? {
+            0 => Event::ClockAdvanced {
+                timestamp: r.u64()?,
+            },
+            1 => Event::AccountOpened { client: r.u64()? },
+            2 => Event::AccountFrozen {
+                client: r.u64()?,
+                reason: r
+                    .freeze_reason()?
+                    .ok_or(CodecError::Invalid("unknown freeze reason"))?,
+            },
+            3 => Event::AccountUnfrozen { client: r.u64()? },
+            tag @ 4..=12 => {
+                let (client, tx, asset, amount) = (r.u64()?, r.u64()?, r.asset()?, r.amount()?);
+                match tag {
+                    4 => Event::FundsCredited {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    5 => Event::FundsDebited {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    6 => Event::FundsHeld {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    7 => Event::FundsReleased {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    8 => Event::HeldFundsAdded {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    9 => Event::HeldFundsRemoved {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    10 => Event::FundsReserved {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    11 => Event::ReservationReleased {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                    _ => Event::ReservedFundsRemoved {
+                        client,
+                        tx,
+                        asset,
+                        amount,
+                    },
+                }
+            }
+            13 => Event::TransactionRecorded {
+                tx: r.u64()?,
+                kind: r.record_kind()?,
+                client: r.u64()?,
+                asset: r.asset()?,
+                amount: r.amount()?,
+                timestamp: r.u64()?,
+            },
+            14 => Event::TransactionIdRecorded { tx: r.u64()? },
+            15 => Event::AuthorizationRecorded {
+                tx: r.u64()?,
+                client: r.u64()?,
+                asset: r.asset()?,
+                amount: r.amount()?,
+            },
+            16 => Event::AuthorizationCaptured {
+                client: r.u64()?,
+                tx: r.u64()?,
+            },
+            17 => Event::AuthorizationVoided {
+                client: r.u64()?,
+                tx: r.u64()?,
+            },
+            18 => {
+                let tx = r.u64()?;
+                let mut metadata = Metadata::new();
+                for _ in 0..r.len()? {
+                    metadata.insert(r.string()?, r.string()?);
+                }
+                Event::MetadataRetained { tx, metadata }
+            }
+            tag @ 19..=22 => {
+                let (client, tx, amount) = (r.u64()?, r.u64()?, r.amount()?);
+                match tag {
+                    19 => Event::DisputeOpened { client, tx, amount },
+                    20 => Event::DisputeResolved { client, tx, amount },
+                    21 => Event::ChargebackApplied { client, tx, amount },
+                    _ => Event::ChargebackReversed { client, tx, amount },
+                }
+            }
+            23 => {
+                let window = r.u64()?;
+                let txs = (0..r.len()?).map(|_| r.u64()).collect::<Result<_, _>>()?;
+                Event::RecordsEvicted { window, txs }
+            }
+            _ => return Err(CodecError::Invalid("unknown event")),
+        })
+    }
+}
+
+/// Append-only journal of the events emitted by an engine, see
+/// [`Engine::set_journal`].
+pub struct Journal {
+    sink: Sink,
+    /// Set when a failed append couldn't be undone, refusing further appends
+    poisoned: bool,
+}
+
+/// Where a journal is written: frames are synced to disk for journal files, only
+/// flushed for other writers.
+enum Sink {
+    File(Box<dyn JournalFile>),
+    Writer(Box<dyn Write + Send>),
+}
+
+/// A journal file, which frames are synced to and truncated from.
+pub(super) trait JournalFile: Write + Send {
+    /// Return the current length of the journal, where the next frame starts.
+    fn position(&mut self) -> io::Result<u64>;
+
+    /// Sync the written frames to disk.
+    fn sync(&mut self) -> io::Result<()>;
+
+    /// Drop the bytes written after `len`, appending from there.
+    fn truncate(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl JournalFile for File {
+    fn position(&mut self) -> io::Result<u64> {
+        self.stream_position()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        self.set_len(len)?;
+        self.seek(SeekFrom::Start(len))?;
+        self.sync_data()
+    }
+}
+
+impl Journal {
+    /// Open a journal file to append to, creating it if needed.
+    ///
+    /// A partial last frame, left by a crash while appending, is truncated.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, JournalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        if len == 0 {
+            write_header(&mut file)?;
+            file.sync_data()?;
+            return Ok(Self::with_file(file));
+        }
+
+        read_header(&mut file)?;
+        // Skip complete frames, up to the end of the last one
+        let mut end = HEADER_LEN;
+        while end + 4 <= len {
+            let mut frame_len = [0; 4];
+            file.read_exact(&mut frame_len)?;
+            let next = end + 4 + u32::from_le_bytes(frame_len) as u64;
+            if next > len {
+                break;
+            }
+            end = file.seek(SeekFrom::Start(next))?;
+        }
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end))?;
+        Ok(Self::with_file(file))
+    }
+
+    /// Append to a journal file positioned after its last frame.
+    pub(super) fn with_file(file: impl JournalFile + 'static) -> Self {
+        Self {
+            sink: Sink::File(Box::new(file)),
+            poisoned: false,
+        }
+    }
+
+    /// Start a new journal, writing its header to `writer`.
+    ///
+    /// Frames are flushed to `writer`, not synced to disk: use [`Journal::open`] for
+    /// a journal file that survives a crash.
+    pub fn new(mut writer: impl Write + Send + 'static) -> Result<Self, JournalError> {
+        write_header(&mut writer)?;
+        writer.flush()?;
+        Ok(Self {
+            sink: Sink::Writer(Box::new(writer)),
+            poisoned: false,
+        })
+    }
+
+    /// Append the events of one transaction as a frame.
+    ///
+    /// If appending fails, a journal file is truncated back to its previous frame, so
+    /// that it stays readable. If that fails too, or for other writers which can't
+    /// take back what they wrote, the journal is poisoned: further appends fail with
+    /// [`JournalError::Poisoned`].
+    pub(crate) fn append(&mut self, events: &[Event]) -> Result<(), JournalError> {
+        if self.poisoned {
+            return Err(JournalError::Poisoned);
+        }
+        // Length placeholder, set once the events are encoded
+        let mut w = Writer::new(vec![0; 4]);
+        for event in events {
+            event.encode(&mut w)?;
+        }
+        let mut frame = w.into_inner();
+        let len =
+            u32::try_from(frame.len() - 4).map_err(|_| JournalError::Invalid("frame too large"))?;
+        frame[..4].copy_from_slice(&len.to_le_bytes());
+
+        let result = match &mut self.sink {
+            Sink::File(file) => {
+                let start = file.position()?;
+                let result = file.write_all(&frame).and_then(|()| file.sync());
+                if result.is_err() && file.truncate(start).is_err() {
+                    self.poisoned = true;
+                }
+                result
+            }
+            Sink::Writer(writer) => {
+                let result = writer.write_all(&frame).and_then(|()| writer.flush());
+                self.poisoned = result.is_err();
+                result
+            }
+        };
+        Ok(result?)
+    }
+}
+
+fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    let mut w = Writer::new(writer);
+    w.bytes(MAGIC)?;
+    w.u32(VERSION)
+}
+
+fn read_header(reader: &mut impl Read) -> Result<(), JournalError> {
+    let mut r = Reader::new(reader);
+    if &r.array::<8>()? != MAGIC {
+        return Err(JournalError::InvalidMagic);
+    }
+    let version = r.u32()?;
+    if version != VERSION {
+        return Err(JournalError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+impl<A: AccountStore, T: TxStore> Engine<A, T> {
+    /// Replay the events of a journal on top of the current engine state, returning
+    /// the number of transactions replayed.
+    ///
+    /// Starting from an empty engine with the same config as the journaled one,
+    /// this rebuilds the same state. The events are not journaled again, and a
+    /// partial last frame is ignored.
+    pub fn replay(&mut self, mut journal: impl Read) -> Result<usize, JournalError> {
+        read_header(&mut journal)?;
+
+        let mut frames = 0;
+        let mut frame = Vec::new();
+        loop {
+            frame.clear();
+            journal.by_ref().take(4).read_to_end(&mut frame)?;
+            let Ok(len) = <[u8; 4]>::try_from(frame.as_slice()) else {
+                // End of journal, or partial length
+                return Ok(frames);
+            };
+            let len = u32::from_le_bytes(len) as u64;
+            frame.clear();
+            journal.by_ref().take(len).read_to_end(&mut frame)?;
+            if (frame.len() as u64) < len {
+                return Ok(frames);
+            }
+
+            let mut events = frame.as_slice();
+            while !events.is_empty() {
+                let event = Event::decode(&mut Reader::new(&mut events))?;
+                self.apply_event(&event)?;
+            }
+            frames += 1;
+        }
+    }
+
+    /// Apply the state change described by an event, without validation.
+    fn apply_event(&mut self, event: &Event) -> Result<(), JournalError> {
+        let inconsistent = || JournalError::Inconsistent(Box::new(event.clone()));
+        match *event {
+            Event::ClockAdvanced { timestamp } => self.clock = timestamp,
+            Event::AccountOpened { client } => {
+                self.clients.get_or_create(client)?;
+            }
+            Event::AccountFrozen { client, reason } => {
+                account(&mut self.clients, client, event)?.freeze(reason)
+            }
+            Event::AccountUnfrozen { client } => {
+                account(&mut self.clients, client, event)?.unfreeze()
+            }
+            Event::FundsCredited {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .credit(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::FundsDebited {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .debit(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::FundsHeld {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .hold(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::FundsReleased {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .release(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::HeldFundsAdded {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .add_held(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::HeldFundsRemoved {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .remove_held(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::FundsReserved {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .reserve(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::ReservationReleased {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .unreserve(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::ReservedFundsRemoved {
+                client,
+                asset,
+                amount,
+                ..
+            } => account(&mut self.clients, client, event)?
+                .remove_reserved(asset, amount)
+                .map_err(|_| inconsistent())?,
+            Event::TransactionRecorded {
+                tx,
+                kind,
+                client,
+                asset,
+                amount,
+                timestamp,
+            } => {
+                let record = DepositRecord::new(client, asset, amount, timestamp);
+                self.txs.insert_record(tx, kind, record)?;
+            }
+            Event::TransactionIdRecorded { tx } => self.txs.insert_id(tx)?,
+            Event::AuthorizationRecorded {
+                tx,
+                client,
+                asset,
+                amount,
+            } => {
+                let record = AuthorizationRecord::new(client, asset, amount);
+                self.txs.insert_authorization(tx, record)?;
+            }
+            Event::AuthorizationCaptured { tx, .. } | Event::AuthorizationVoided { tx, .. } => {
+                let record = self.txs.authorization_mut(tx)?.ok_or_else(inconsistent)?;
+                record.state = match event {
+                    Event::AuthorizationCaptured { .. } => AuthorizationState::Captured,
+                    _ => AuthorizationState::Voided,
+                };
+            }
+            Event::MetadataRetained { tx, ref metadata } => {
+                self.deposit_metadata.insert(tx, metadata.clone());
+            }
+            Event::DisputeOpened { tx, amount, .. } => {
+                let (_, record) = record(&mut self.txs, tx, event)?;
+                let disputed = record.disputed.checked_add(amount);
+                record.set_disputed(disputed.ok_or_else(inconsistent)?);
+            }
+            Event::DisputeResolved { tx, amount, .. } => {
+                let (_, record) = record(&mut self.txs, tx, event)?;
+                let disputed = record.disputed.checked_sub(amount);
+                let disputed = disputed.ok_or_else(inconsistent)?;
+                record.set_disputed(disputed);
+                if self.config.max_redisputes.is_some() && disputed == Amount::default() {
+                    *self.dispute_counts.entry(tx).or_default() += 1;
+                }
+            }
+            Event::ChargebackApplied { client, tx, amount } => {
+                let (kind, record) = record(&mut self.txs, tx, event)?;
+                let disputed = record.disputed.checked_sub(amount);
+                let charged_back = record.charged_back.checked_add(amount);
+                let (disputed, charged_back) =
+                    disputed.zip(charged_back).ok_or_else(inconsistent)?;
+                if kind == RecordKind::Deposit && record.charged_back == Amount::default() {
+                    account(&mut self.clients, client, event)?.add_chargeback();
+                }
+                record.disputed = disputed;
+                record.set_charged_back(charged_back);
+            }
+            Event::ChargebackReversed { client, tx, amount } => {
+                let (kind, record) = record(&mut self.txs, tx, event)?;
+                let charged_back = record.charged_back.checked_sub(amount);
+                let charged_back = charged_back.ok_or_else(inconsistent)?;
+                if kind == RecordKind::Deposit && record.charged_back == amount {
+                    account(&mut self.clients, client, event)?.remove_chargeback();
+                }
+                record.set_charged_back(charged_back);
+            }
+            Event::RecordsEvicted { window, ref txs } => {
+                let clock = self.clock;
+                let mut evicted = self.txs.evict(&mut |record: &DepositRecord| {
+                    record.state == DepositState::Ok
+                        && clock.saturating_sub(record.timestamp) > window
+                })?;
+                for tx in &evicted {
+                    self.deposit_metadata.remove(tx);
+                    self.dispute_counts.remove(tx);
+                }
+                let mut expected = txs.clone();
+                evicted.sort_unstable();
+                expected.sort_unstable();
+                if evicted != expected {
+                    return Err(inconsistent());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the account an event applies to.
+fn account<'a>(
+    clients: &'a mut impl AccountStore,
+    client: ClientId,
+    event: &Event,
+) -> Result<&'a mut ClientAccount, JournalError> {
+    let account = clients.get_mut(client)?;
+    account.ok_or_else(|| JournalError::Inconsistent(Box::new(event.clone())))
+}
+
+/// Return the record an event applies to.
+fn record<'a>(
+    txs: &'a mut impl TxStore,
+    tx: TxId,
+    event: &Event,
+) -> Result<(RecordKind, &'a mut DepositRecord), JournalError> {
+    let record = txs.record_mut(tx)?;
+    record.ok_or_else(|| JournalError::Inconsistent(Box::new(event.clone())))
+}
+
+/// Appending to a shared buffer, to read back what was journaled in tests.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedBuffer(pub(crate) std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Failing writes once set, to test journal failures.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct FailingWriter(pub(crate) std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+#[cfg(test)]
+impl FailingWriter {
+    pub(crate) fn fail(&self) {
+        self.0.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.0.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(io::Error::other("disk full"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A journal file accepting a number of bytes before failing writes (and truncation,
+/// if `fail_truncate` is set), to test journal failures.
+#[cfg(test)]
+pub(crate) struct FailingFile {
+    file: File,
+    budget: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    fail_truncate: bool,
+}
+
+#[cfg(test)]
+impl FailingFile {
+    /// Return a journal appending to the journal file at `path`, and the number of
+    /// bytes it accepts (unlimited at first).
+    pub(crate) fn journal(
+        path: &Path,
+        fail_truncate: bool,
+    ) -> (Journal, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        drop(Journal::open(path).unwrap());
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        let budget = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(usize::MAX));
+        let file = Self {
+            file,
+            budget: budget.clone(),
+            fail_truncate,
+        };
+        (Journal::with_file(file), budget)
+    }
+}
+
+#[cfg(test)]
+impl Write for FailingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        use std::sync::atomic::Ordering;
+        let budget = self.budget.load(Ordering::Relaxed);
+        if budget == 0 {
+            return Err(io::Error::other("disk full"));
+        }
+        let written = self.file.write(&buf[..buf.len().min(budget)])?;
+        self.budget.store(budget - written, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+impl JournalFile for FailingFile {
+    fn position(&mut self) -> io::Result<u64> {
+        self.file.position()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync()
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        if self.fail_truncate {
+            return Err(io::Error::other("read-only file system"));
+        }
+        self.file.truncate(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::model::{AdminAction, Transaction};
+    use crate::{DisputePolicy, EngineConfig};
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    fn config() -> EngineConfig {
+        EngineConfig {
+            dispute_policy: DisputePolicy::DepositsAndWithdrawals,
+            dispute_window: Some(1_000),
+            retain_deposit_metadata: true,
+            max_redisputes: Some(2),
+            unfreeze_on_represent: true,
+            ..EngineConfig::default()
+        }
+    }
+
+    fn money(client: ClientId, tx: TxId, amount: i64) -> (ClientId, TxId, Asset, Amount) {
+        (client, tx, USD, Amount::from_scaled(amount))
+    }
+
+    /// Transactions of every type, some of them rejected.
+    fn transactions() -> Vec<Transaction> {
+        let mut txs: Vec<_> = [(1, 1, 100), (2, 2, 50), (1, 3, 30)]
+            .into_iter()
+            .map(|(client, tx, amount)| {
+                let deposit = at(deposit(client, tx, amount), tx * 100);
+                with_metadata(deposit, &[("ref", "dep")])
+            })
+            .collect();
+        txs.extend([
+            withdrawal(2, 4, 20),
+            // Rejected, but opens an account
+            withdrawal(9, 5, 20),
+            authorize(1, 6, 10),
+            authorize(1, 7, 10),
+            capture(1, 6),
+            void(1, 7),
+            transfer(1, 3, 8, 5),
+            dispute_part(1, 1, 40),
+            dispute(1, 1),
+            dispute(2, 4),
+            dispute(1, 3),
+            resolve_part(1, 1, 30),
+            resolve(2, 4),
+            chargeback(1, 1),
+            chargeback(1, 3),
+            represent(1, 1),
+        ]);
+        for (tx, action) in [
+            (9, AdminAction::Lock),
+            (10, AdminAction::Unlock),
+            (
+                11,
+                AdminAction::Adjustment {
+                    asset: USD,
+                    amount: Amount::from_scaled(-5),
+                    reason: "fee".to_string(),
+                },
+            ),
+        ] {
+            txs.push(at(admin(2, tx, action), 5_000));
+        }
+        txs
+    }
+
+    #[test]
+    fn transactions_emit_events() {
+        let mut engine = Engine::with_config(config());
+        let mut txs = transactions().into_iter();
+
+        engine.apply(txs.next().unwrap()).unwrap();
+        let (client, tx, asset, amount) = money(1, 1, 100);
+        assert_eq!(
+            engine.last_events(),
+            [
+                Event::ClockAdvanced { timestamp: 100 },
+                Event::AccountOpened { client },
+                Event::FundsCredited {
+                    client,
+                    tx,
+                    asset,
+                    amount
+                },
+                Event::TransactionRecorded {
+                    tx,
+                    kind: RecordKind::Deposit,
+                    client,
+                    asset,
+                    amount,
+                    timestamp: 100
+                },
+                Event::MetadataRetained {
+                    tx,
+                    metadata: Metadata::from([("ref".to_string(), "dep".to_string())])
+                },
+            ]
+        );
+
+        // A rejected transaction only emits the changes it keeps
+        assert!(engine.apply(deposit(1, 1, 10)).is_err());
+        assert!(engine.last_events().is_empty());
+    }
+
+    #[test]
+    fn chargeback_events() {
+        let mut engine = Engine::with_config(config());
+        let txs = transactions();
+        let first = txs
+            .iter()
+            .position(|tx| matches!(tx, Transaction::Chargeback { .. }))
+            .unwrap();
+        for tx in &txs[..=first] {
+            let _ = engine.apply(tx.clone());
+        }
+
+        let amount = Amount::from_scaled(70);
+        assert_eq!(
+            engine.last_events(),
+            [
+                Event::HeldFundsRemoved {
+                    client: 1,
+                    tx: 1,
+                    asset: USD,
+                    amount
+                },
+                Event::AccountFrozen {
+                    client: 1,
+                    reason: FreezeReason::Chargeback
+                },
+                Event::ChargebackApplied {
+                    client: 1,
+                    tx: 1,
+                    amount
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_rebuilds_identical_state() {
+        let journal = SharedBuffer::default();
+        let mut engine = Engine::with_config(config());
+        engine.set_journal(Journal::new(journal.clone()).unwrap());
+        let mut applied = 0;
+        for tx in transactions() {
+            applied += engine.apply(tx).is_ok() as usize;
+        }
+        assert_eq!(engine.evict_expired().unwrap(), 3);
+
+        let bytes = journal.0.lock().unwrap().clone();
+        let mut replayed = Engine::with_config(config());
+        let frames = replayed.replay(bytes.as_slice()).unwrap();
+
+        // Each applied transaction, the rejected withdrawal opening an account and
+        // the eviction
+        assert_eq!(frames, applied + 2);
+        assert_eq!(snapshot(&replayed), snapshot(&engine));
+    }
+
+    #[test]
+    fn partial_frames_are_dropped() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("journal.bin");
+        let mut engine = Engine::with_config(config());
+        engine.set_journal(Journal::open(&path).unwrap());
+        for tx in transactions().into_iter().take(3) {
+            engine.apply(tx).unwrap();
+        }
+        drop(engine);
+
+        // Crash in the middle of the last frame
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+        let mut replayed = Engine::with_config(config());
+        assert_eq!(replayed.replay(File::open(&path).unwrap()).unwrap(), 2);
+
+        // Reopening drops it, and appends after the last complete frame
+        replayed.set_journal(Journal::open(&path).unwrap());
+        let last = transactions().into_iter().nth(2).unwrap();
+        replayed.apply(last).unwrap();
+        drop(replayed);
+
+        let mut again = Engine::with_config(config());
+        assert_eq!(again.replay(File::open(&path).unwrap()).unwrap(), 3);
+        assert_eq!(
+            again.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(130)
+        );
+    }
+
+    #[test]
+    fn invalid_journals_are_rejected() {
+        let mut engine = Engine::with_config(config());
+        assert!(matches!(
+            engine.replay(&b"TXSNAPSH\x01\x00\x00\x00"[..]),
+            Err(JournalError::InvalidMagic)
+        ));
+
+        // A dispute of a deposit the journal doesn't have
+        let journal = SharedBuffer::default();
+        let mut journaled = Engine::with_config(config());
+        journaled.set_journal(Journal::new(journal.clone()).unwrap());
+        journaled
+            .journal
+            .as_mut()
+            .unwrap()
+            .append(&[Event::DisputeOpened {
+                client: 1,
+                tx: 1,
+                amount: Amount::from_scaled(10),
+            }])
+            .unwrap();
+        let bytes = journal.0.lock().unwrap().clone();
+        assert!(matches!(
+            engine.replay(bytes.as_slice()),
+            Err(JournalError::Inconsistent(event)) if matches!(*event, Event::DisputeOpened { .. })
+        ));
+    }
+
+    #[test]
+    fn overflowing_journals_are_rejected() {
+        let journal = SharedBuffer::default();
+        let mut journaled = Engine::with_config(config());
+        journaled.set_journal(Journal::new(journal.clone()).unwrap());
+        let (client, tx, asset, amount) = money(1, 1, i64::MAX);
+        let dispute = Event::DisputeOpened { client, tx, amount };
+        journaled
+            .journal
+            .as_mut()
+            .unwrap()
+            .append(&[
+                Event::TransactionRecorded {
+                    tx,
+                    kind: RecordKind::Deposit,
+                    client,
+                    asset,
+                    amount,
+                    timestamp: 0,
+                },
+                dispute.clone(),
+                dispute,
+            ])
+            .unwrap();
+        let bytes = journal.0.lock().unwrap().clone();
+        let mut engine = Engine::with_config(config());
+        assert!(matches!(
+            engine.replay(bytes.as_slice()),
+            Err(JournalError::Inconsistent(event)) if matches!(*event, Event::DisputeOpened { .. })
+        ));
+    }
+}
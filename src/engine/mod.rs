@@ -4,108 +4,454 @@
 //! It supports deposits, withdrawals, disputes, resolutions, and chargebacks.
 //! Also supports async stream of transactions.
 
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use tokio_stream::{Stream, StreamExt};
-use tracing::{info, warn};
+use tracing::{error, field, info, warn};
 
 use crate::Amount;
-use crate::model::{ClientId, DepositRecord, DepositState, Transaction, TxId};
+use crate::amount::{Decimal, Precision};
+use crate::model::{
+    AdminAction, Asset, AuthorizationRecord, AuthorizationState, ClientId, DepositRecord,
+    DepositState, Metadata, Timestamp, Transaction, TxId, WithdrawalRecord,
+};
+
+mod config;
+pub use config::{DisputePolicy, EngineConfig, NegativeAvailablePolicy};
 
 mod state;
-pub use state::ClientAccount;
+pub use state::{Balance, ClientAccount, FreezeReason};
+
+mod store;
+pub use store::{AccountStore, MemoryAccountStore, MemoryTxStore, RecordKind, TxStore};
+
+mod disk;
+pub use disk::DiskTxStore;
+
+mod codec;
+mod snapshot;
+
+mod event;
+pub use event::{Event, Journal};
+
+mod sharded;
+pub use sharded::ShardedEngine;
+
+mod batch;
+pub use batch::BatchReport;
+
+mod outcome;
+pub use outcome::{AccountOutcome, ApplyOutcome};
+
+mod observer;
+pub use observer::EngineObserver;
 
 mod error;
 pub use error::{
-    DepositError, DepositOperation, DepositOperationError, EngineError, WithdrawalError,
+    AdminError, AuthorizationError, AuthorizationOperation, AuthorizationOperationError,
+    BalanceOverflow, BatchError, ConfigError, DepositError, DepositOperation,
+    DepositOperationError, EngineError, JournalError, SnapshotError, StoreError, TransferError,
+    WithdrawalError,
 };
 
 /// The transaction processing engine.
 ///
-/// Maintains client accounts and deposit records for dispute tracking.
-pub struct Engine {
-    clients: HashMap<ClientId, ClientAccount>,
-    /// Deposit records for dispute tracking (chargedback deposits are evicted)
-    deposits: HashMap<TxId, DepositRecord>,
-    /// Track withdrawal tx IDs for duplicate checking only
-    withdrawal_ids: HashSet<TxId>,
+/// Maintains client accounts and deposit records for dispute tracking, in the given
+/// storage backends (in memory by default, see [`AccountStore`] and [`TxStore`]).
+pub struct Engine<A = MemoryAccountStore, T = MemoryTxStore> {
+    config: EngineConfig,
+    clients: A,
+    /// Deposit (and disputable withdrawal) records, authorizations and tx IDs
+    txs: T,
+    /// Metadata of retained deposit records, only kept if configured
+    deposit_metadata: HashMap<TxId, Metadata>,
+    /// Number of times the disputes of a record were all resolved, only kept when
+    /// re-disputes are limited
+    dispute_counts: HashMap<TxId, u32>,
+    /// Latest transaction time seen, the time of transactions without a timestamp
+    clock: Timestamp,
+    /// Clock at the last periodic eviction of expired records
+    evicted_at: Timestamp,
+    /// Events emitted by the last transaction
+    events: Vec<Event>,
+    /// Journal the events are appended to, if any
+    journal: Option<Journal>,
+    /// Notified of every transaction, in order
+    observers: Vec<Box<dyn EngineObserver + Send>>,
 }
 
-/// Public API
 impl Engine {
     pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    /// Create an engine applying the given business policies
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self::with_stores(config, MemoryAccountStore::new(), MemoryTxStore::new())
+    }
+}
+
+/// Public API
+impl<A: AccountStore, T: TxStore> Engine<A, T> {
+    /// Create an engine applying the given business policies, with its own storage
+    /// backends
+    pub fn with_stores(config: EngineConfig, clients: A, txs: T) -> Self {
         Self {
-            clients: HashMap::new(),
-            deposits: HashMap::new(),
-            withdrawal_ids: HashSet::new(),
+            config,
+            clients,
+            txs,
+            deposit_metadata: HashMap::new(),
+            dispute_counts: HashMap::new(),
+            clock: 0,
+            evicted_at: 0,
+            events: Vec::new(),
+            journal: None,
+            observers: Vec::new(),
         }
     }
 
+    /// Append the events emitted from now on to a journal, see [`Engine::replay`].
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
     /// Run the engine with the given transaction stream
-    pub async fn run(&mut self, mut stream: impl Stream<Item = Transaction> + Unpin) {
+    ///
+    /// Records older than the dispute window are evicted each time the clock moves
+    /// forward by the window, see [`Engine::evict_expired`].
+    ///
+    /// Stops if appending to the journal fails, as the journal would miss the
+    /// following transactions, returning the journal error.
+    pub async fn run(
+        &mut self,
+        mut stream: impl Stream<Item = Transaction> + Unpin,
+    ) -> Result<(), JournalError> {
         while let Some(tx) = stream.next().await {
-            // any error should not stop the engine, so we just ignore the application result
-            let _ = self.apply(tx);
+            // any other error should not stop the engine, so we just ignore the application result
+            if let Err(
+                EngineError::Journal(e) | EngineError::RejectionNotJournaled { source: e, .. },
+            ) = self.apply(tx)
+            {
+                error!("{e}, stopping");
+                return Err(e);
+            }
+            if let Err(e) = self.evict_expired_periodically() {
+                error!("{e}, stopping");
+                return Err(e);
+            }
         }
+        Ok(())
+    }
+
+    /// Return the engine configuration
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
     }
 
     /// Return the state of client accounts.
     pub fn clients(&self) -> impl Iterator<Item = &ClientAccount> + '_ {
-        self.clients.values()
+        self.clients.iter()
     }
 
     /// Return the state of one client account
-    pub fn get_client(&self, client: ClientId) -> Option<&ClientAccount> {
-        self.clients.get(&client)
+    pub fn get_client(&self, client: ClientId) -> Result<Option<&ClientAccount>, StoreError> {
+        self.clients.get(client)
+    }
+
+    /// Return the events emitted by the last transaction applied (or eviction).
+    pub fn last_events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Return the metadata of a retained deposit record, if metadata is retained.
+    pub fn deposit_metadata(&self, tx: TxId) -> Option<&Metadata> {
+        self.deposit_metadata.get(&tx)
+    }
+
+    /// Evict deposit (and disputable withdrawal) records older than the dispute window.
+    ///
+    /// Records under dispute are kept so they can still be resolved or charged back,
+    /// and charged back records so they can still be re-presented.
+    /// Evicted IDs are still checked for duplicates, and disputes referencing them
+    /// are rejected as outside the window. Returns the number of evicted records.
+    pub fn evict_expired(&mut self) -> Result<usize, EngineError> {
+        self.events.clear();
+        let Some(window) = self.config.dispute_window else {
+            return Ok(0);
+        };
+        let clock = self.clock;
+        let evicted = self.txs.evict(&mut |record: &DepositRecord| {
+            record.state == DepositState::Ok && clock.saturating_sub(record.timestamp) > window
+        })?;
+
+        for tx in &evicted {
+            self.deposit_metadata.remove(tx);
+            self.dispute_counts.remove(tx);
+        }
+
+        let count = evicted.len();
+        if count > 0 {
+            let txs = evicted;
+            self.events.push(Event::RecordsEvicted { window, txs });
+            self.write_journal()?;
+        }
+        Ok(count)
+    }
+
+    /// Apply a single transaction on top of the current engine state, returning its
+    /// effect on the accounts it changed
+    ///
+    /// The emitted events are appended to the journal, if any, even if the
+    /// transaction is rejected (see [`Event`]). If appending fails, the partial frame
+    /// is dropped from the journal (see [`Journal`]) but the transaction stays
+    /// applied, and a journal error is returned, with the reason the transaction was
+    /// rejected if it was. The observers are notified either way, of the outcome the
+    /// engine kept.
+    pub fn apply(&mut self, transaction: Transaction) -> Result<ApplyOutcome, EngineError> {
+        self.events.clear();
+        let result = self.apply_transaction(&transaction);
+        let journaled = self.write_journal();
+        let result = result.map(|()| self.outcome(&transaction, 0));
+        match &result {
+            Ok(outcome) => self.notify_applied(&transaction, outcome),
+            Err(error) => self.notify_rejected(&transaction, error),
+        }
+        match (journaled, result) {
+            (Ok(()), result) => result,
+            (Err(source), Ok(_)) => Err(EngineError::Journal(source)),
+            (Err(source), Err(rejection)) => Err(EngineError::RejectionNotJournaled {
+                rejection: Box::new(rejection),
+                source,
+            }),
+        }
+    }
+}
+
+/// Private API
+impl<A: AccountStore, T: TxStore> Engine<A, T> {
+    /// Evict expired records if the clock moved forward by the dispute window since
+    /// the last time, logging store failures. Only journal failures are returned.
+    fn evict_expired_periodically(&mut self) -> Result<(), JournalError> {
+        let Some(window) = self.config.dispute_window else {
+            return Ok(());
+        };
+        if self.clock.saturating_sub(self.evicted_at) < window.max(1) {
+            return Ok(());
+        }
+        self.evicted_at = self.clock;
+        match self.evict_expired() {
+            Ok(_) => Ok(()),
+            Err(EngineError::Journal(e)) => Err(e),
+            Err(e) => {
+                error!("failed to evict expired records: {e}");
+                Ok(())
+            }
+        }
     }
 
-    /// Apply a single transaction on top of the current engine state
-    pub fn apply(&mut self, tx: Transaction) -> Result<(), EngineError> {
-        match &tx {
-            Transaction::Deposit { client, tx, amount } => {
-                let result = self.apply_deposit(*client, *tx, *amount);
-                Self::log_result("deposit", *client, *tx, Some(*amount), &result);
+    /// Apply a transaction, logging its result and collecting its events
+    fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        // Time only moves forward, transactions without a timestamp happen now. The
+        // clock is only advanced once the transaction is applied, so a rejected one
+        // with a bogus timestamp can't expire the dispute windows.
+        let now = transaction.timestamp().unwrap_or(self.clock);
+        let clock = self.clock.max(now);
+        let first = self.events.len();
+        let metadata = transaction.metadata();
+
+        match transaction {
+            Transaction::Deposit {
+                client,
+                tx,
+                asset,
+                amount,
+                ..
+            } => {
+                let result = self.apply_deposit(*client, *tx, *asset, *amount, now);
+                if result.is_ok() && self.config.retain_deposit_metadata && !metadata.is_empty() {
+                    self.deposit_metadata.insert(*tx, metadata.clone());
+                    let metadata = metadata.clone();
+                    self.events
+                        .push(Event::MetadataRetained { tx: *tx, metadata });
+                }
+                self.log_result(
+                    "deposit",
+                    transaction,
+                    Some((*asset, *amount)),
+                    metadata,
+                    &result,
+                );
+                result?;
+            }
+            Transaction::Withdrawal {
+                client,
+                tx,
+                asset,
+                amount,
+                ..
+            } => {
+                let result = self.apply_withdrawal(*client, *tx, *asset, *amount, now);
+                self.log_result(
+                    "withdrawal",
+                    transaction,
+                    Some((*asset, *amount)),
+                    metadata,
+                    &result,
+                );
+                result?;
+            }
+            Transaction::Authorize {
+                client,
+                tx,
+                asset,
+                amount,
+                ..
+            } => {
+                let result = self.apply_authorize(*client, *tx, *asset, *amount);
+                self.log_result(
+                    "authorize",
+                    transaction,
+                    Some((*asset, *amount)),
+                    metadata,
+                    &result,
+                );
+                result?;
+            }
+            Transaction::Capture { client, tx, .. } => {
+                let money = self.authorization_money(*tx);
+                let result = self.apply_capture(*client, *tx);
+                self.log_result("capture", transaction, money, metadata, &result);
+                result?;
+            }
+            Transaction::Void { client, tx, .. } => {
+                let money = self.authorization_money(*tx);
+                let result = self.apply_void(*client, *tx);
+                self.log_result("void", transaction, money, metadata, &result);
+                result?;
+            }
+            Transaction::Transfer {
+                from,
+                to,
+                tx,
+                asset,
+                amount,
+                ..
+            } => {
+                let result = self.apply_transfer(*from, *to, *tx, *asset, *amount);
+                self.log_result(
+                    "transfer",
+                    transaction,
+                    Some((*asset, *amount)),
+                    metadata,
+                    &result,
+                );
+                result?;
+            }
+            Transaction::Admin {
+                client,
+                tx,
+                operator,
+                action,
+                ..
+            } => {
+                let result = self.apply_admin(*client, *tx, operator, action);
+                self.log_admin_result(*client, *tx, operator, action, metadata, &result);
                 result?;
             }
-            Transaction::Withdrawal { client, tx, amount } => {
-                let result = self.apply_withdrawal(*client, *tx, *amount);
-                Self::log_result("withdrawal", *client, *tx, Some(*amount), &result);
+            Transaction::Dispute {
+                client, tx, amount, ..
+            } => {
+                let money = self.record_money(*tx, amount.as_ref());
+                // Checked against the engine time, a late dispute can't carry an older one
+                let result = self.apply_dispute(*client, *tx, amount.as_ref(), clock);
+                let metadata = self.operation_metadata(*tx, metadata);
+                self.log_result("dispute", transaction, money, &metadata, &result);
                 result?;
             }
-            Transaction::Dispute { client, tx } => {
-                let result = self.apply_dispute(*client, *tx);
-                Self::log_result("dispute", *client, *tx, None, &result);
+            Transaction::Resolve {
+                client, tx, amount, ..
+            } => {
+                let money = self.record_money(*tx, amount.as_ref());
+                let result = self.apply_resolve(*client, *tx, amount.as_ref());
+                let metadata = self.operation_metadata(*tx, metadata);
+                self.log_result("resolve", transaction, money, &metadata, &result);
                 result?;
             }
-            Transaction::Resolve { client, tx } => {
-                let result = self.apply_resolve(*client, *tx);
-                Self::log_result("resolve", *client, *tx, None, &result);
+            Transaction::Chargeback {
+                client, tx, amount, ..
+            } => {
+                let money = self.record_money(*tx, amount.as_ref());
+                let result = self.apply_chargeback(*client, *tx, amount.as_ref());
+                let metadata = self.operation_metadata(*tx, metadata);
+                self.log_result("chargeback", transaction, money, &metadata, &result);
                 result?;
             }
-            Transaction::Chargeback { client, tx } => {
-                let result = self.apply_chargeback(*client, *tx);
-                Self::log_result("chargeback", *client, *tx, None, &result);
+            Transaction::Represent {
+                client, tx, amount, ..
+            } => {
+                let money = self.record_money(*tx, amount.as_ref());
+                let result = self.apply_represent(*client, *tx, amount.as_ref());
+                let metadata = self.operation_metadata(*tx, metadata);
+                self.log_result("represent", transaction, money, &metadata, &result);
                 result?;
             }
         }
+
+        if clock > self.clock {
+            self.clock = clock;
+            let event = Event::ClockAdvanced { timestamp: clock };
+            self.events.insert(first, event);
+        }
         Ok(())
     }
-}
 
-/// Private API
-impl Engine {
-    /// Small helper to log `apply` results
+    /// Append the events of the last transaction to the journal, if any
+    fn write_journal(&mut self) -> Result<(), JournalError> {
+        match &mut self.journal {
+            Some(journal) if !self.events.is_empty() => journal.append(&self.events),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return an account to update, creating it if needed (with its event)
+    fn open_account<'a>(
+        clients: &'a mut A,
+        events: &mut Vec<Event>,
+        client: ClientId,
+    ) -> Result<&'a mut ClientAccount, StoreError> {
+        let exists = clients.get(client)?.is_some();
+        let account = clients.get_or_create(client)?;
+        if !exists {
+            events.push(Event::AccountOpened { client });
+        }
+        Ok(account)
+    }
+
+    /// Small helper to log `apply` results, with the receiver of a transfer
     fn log_result<E: std::fmt::Display>(
+        &self,
         tx_type: &str,
-        client: ClientId,
-        tx: TxId,
-        amount: Option<Amount>,
+        transaction: &Transaction,
+        amount: Option<(Asset, Amount)>,
+        metadata: &Metadata,
         result: &Result<(), E>,
     ) {
+        let (client, tx) = (transaction.client(), transaction.tx());
+        let to = match *transaction {
+            Transaction::Transfer { to, .. } => Some(to),
+            _ => None,
+        };
+        let amount =
+            amount.map(|(asset, amt)| (asset, amt.display(self.config.precision_for(asset))));
+        let metadata = (!metadata.is_empty()).then(|| field::debug(metadata));
         match (result, amount) {
-            (Ok(()), Some(amt)) => {
+            (Ok(()), Some((asset, amt))) => {
                 info!(
                     client = %client,
+                    to,
                     tx = %tx,
+                    metadata,
+                    asset = %asset,
                     amount = %amt,
                     "{tx_type} applied"
                 );
@@ -113,14 +459,19 @@ impl Engine {
             (Ok(()), None) => {
                 info!(
                     client = %client,
+                    to,
                     tx = %tx,
+                    metadata,
                     "{tx_type} applied"
                 );
             }
-            (Err(e), Some(amt)) => {
+            (Err(e), Some((asset, amt))) => {
                 info!(
                     client = %client,
+                    to,
                     tx = %tx,
+                    metadata,
+                    asset = %asset,
                     amount = %amt,
                     reason = %e,
                     "{tx_type} skipped"
@@ -129,7 +480,9 @@ impl Engine {
             (Err(e), None) => {
                 info!(
                     client = %client,
+                    to,
                     tx = %tx,
+                    metadata,
                     reason = %e,
                     "{tx_type} skipped"
                 );
@@ -137,12 +490,77 @@ impl Engine {
         }
     }
 
+    /// Log `apply` results of admin transactions, under their own target and with
+    /// the operator, so they can be audited apart from client transactions
+    fn log_admin_result(
+        &self,
+        client: ClientId,
+        tx: TxId,
+        operator: &str,
+        action: &AdminAction,
+        metadata: &Metadata,
+        result: &Result<(), EngineError>,
+    ) {
+        let action_name = action.name();
+        let metadata = (!metadata.is_empty()).then(|| field::debug(metadata));
+        let (asset, amount, reason) = match action {
+            AdminAction::Adjustment {
+                asset,
+                amount,
+                reason,
+            } => (
+                Some(asset.as_str()),
+                Some(amount.display(self.config.precision_for(*asset))),
+                Some(reason.as_str()),
+            ),
+            AdminAction::Lock | AdminAction::Unlock => (None, None, None),
+        };
+        match result {
+            Ok(()) => info!(
+                target: "txs_eng::admin",
+                client = %client,
+                tx = %tx,
+                operator = %operator,
+                asset,
+                amount = amount.map(field::display),
+                reason,
+                metadata,
+                "admin {action_name} applied"
+            ),
+            Err(e) => warn!(
+                target: "txs_eng::admin",
+                client = %client,
+                tx = %tx,
+                operator = %operator,
+                asset,
+                amount = amount.map(field::display),
+                reason,
+                metadata,
+                error = %e,
+                "admin {action_name} skipped"
+            ),
+        }
+    }
+
+    /// Remove the account of a client opened by the transaction being applied, with
+    /// its event, when storing the transaction failed (a failing store leaves the
+    /// engine state as it was)
+    fn discard_opened(&mut self, client: ClientId) -> Result<(), StoreError> {
+        // Opening the account is the last event before storing the transaction
+        if self.events.last() == Some(&Event::AccountOpened { client }) {
+            self.clients.remove(client)?;
+            self.events.pop();
+        }
+        Ok(())
+    }
+
     /// Ensure transaction ID is unique
-    fn is_unique(&self, tx: &TxId) -> bool {
-        !self.deposits.contains_key(tx) && !self.withdrawal_ids.contains(tx)
+    fn is_unique(&self, tx: TxId) -> Result<bool, StoreError> {
+        Ok(!self.txs.contains(tx)?)
     }
 
     /// Apply a `Transaction::Deposit`:
+    /// - Ensure amount is positive (or zero, if probe deposits are allowed)
     /// - Ensure transaction ID is unique
     /// - Ensure account is unfrozen
     /// - Increment account available balance by the deposit amount
@@ -151,628 +569,3179 @@ impl Engine {
         &mut self,
         client: ClientId,
         tx: TxId,
+        asset: Asset,
         amount: Amount,
-    ) -> Result<(), DepositError> {
-        if !self.is_unique(&tx) {
-            return Err(DepositError::DuplicateTxId(tx));
+        timestamp: Timestamp,
+    ) -> Result<(), EngineError> {
+        let zero = Amount::default();
+        let precision = self.config.precision_for(asset);
+        if amount < zero || (amount == zero && !self.config.allow_zero_deposits) {
+            return Err(DepositError::NonPositiveAmount(tx, amount.display(precision)).into());
         }
 
-        let account = self
-            .clients
-            .entry(client)
-            .or_insert_with(|| ClientAccount::new(client));
+        if !self.is_unique(tx)? {
+            return Err(DepositError::DuplicateTxId(tx).into());
+        }
+
+        let account = Self::open_account(&mut self.clients, &mut self.events, client)?;
 
         if account.is_frozen() {
-            return Err(DepositError::AccountFrozen(client));
+            return Err(DepositError::AccountFrozen(client).into());
         }
 
-        account.credit(amount);
+        let balance = account.stored_balance(asset);
+        account.credit(asset, amount)?;
 
         // Store deposit for potential disputes
-        self.deposits.insert(tx, DepositRecord::new(client, amount));
+        let record = DepositRecord::new(client, asset, amount, timestamp);
+        if let Err(e) = self.txs.insert_record(tx, RecordKind::Deposit, record) {
+            account.restore_balance(asset, balance);
+            self.discard_opened(client)?;
+            return Err(e.into());
+        }
 
+        self.events.extend([
+            Event::FundsCredited {
+                client,
+                tx,
+                asset,
+                amount,
+            },
+            Event::TransactionRecorded {
+                tx,
+                kind: RecordKind::Deposit,
+                client,
+                asset,
+                amount,
+                timestamp,
+            },
+        ]);
         Ok(())
     }
 
     /// Apply a `Transaction::Withdrawal`:
+    /// - Ensure amount is positive
     /// - Ensure transaction ID is unique
     /// - Ensure account is unfrozen and has enough available balance
     /// - Decrement account available balance by the withdrawal amount
+    /// - Store withdrawal for potential disputes, if withdrawals are disputable
     fn apply_withdrawal(
         &mut self,
         client: ClientId,
         tx: TxId,
+        asset: Asset,
         amount: Amount,
-    ) -> Result<(), WithdrawalError> {
-        if !self.is_unique(&tx) {
-            return Err(WithdrawalError::DuplicateTxId(tx));
+        timestamp: Timestamp,
+    ) -> Result<(), EngineError> {
+        let precision = self.config.precision_for(asset);
+        if amount <= Amount::default() {
+            return Err(WithdrawalError::NonPositiveAmount(tx, amount.display(precision)).into());
         }
 
-        let account = self
-            .clients
-            .entry(client)
-            .or_insert_with(|| ClientAccount::new(client));
+        if !self.is_unique(tx)? {
+            return Err(WithdrawalError::DuplicateTxId(tx).into());
+        }
+
+        let account = if self.config.create_accounts_on_withdrawal {
+            Self::open_account(&mut self.clients, &mut self.events, client)?
+        } else {
+            self.clients
+                .get_mut(client)?
+                .ok_or(WithdrawalError::ClientNotFound(client))?
+        };
 
         if account.is_frozen() {
-            return Err(WithdrawalError::AccountFrozen(client));
+            return Err(WithdrawalError::AccountFrozen(client).into());
         }
 
-        if account.available() < amount {
+        let available = account.available(asset);
+        if available < amount {
             return Err(WithdrawalError::InsufficientFunds(
                 client,
-                account.available(),
-                amount,
-            ));
+                asset,
+                available.display(precision),
+                amount.display(precision),
+            )
+            .into());
         }
 
-        account.debit(amount);
+        let balance = account.stored_balance(asset);
+        account.debit(asset, amount)?;
 
-        // Store only tx ID for duplicate checking (as withdrawals can't be disputed)
-        self.withdrawal_ids.insert(tx);
+        let (stored, recorded) = match self.config.dispute_policy {
+            // Store only tx ID for duplicate checking (as withdrawals can't be disputed)
+            DisputePolicy::DepositsOnly => {
+                (self.txs.insert_id(tx), Event::TransactionIdRecorded { tx })
+            }
+            DisputePolicy::DepositsAndWithdrawals => (
+                self.txs.insert_record(
+                    tx,
+                    RecordKind::Withdrawal,
+                    WithdrawalRecord::new(client, asset, amount, timestamp),
+                ),
+                Event::TransactionRecorded {
+                    tx,
+                    kind: RecordKind::Withdrawal,
+                    client,
+                    asset,
+                    amount,
+                    timestamp,
+                },
+            ),
+        };
+        if let Err(e) = stored {
+            account.restore_balance(asset, balance);
+            return Err(e.into());
+        }
 
+        self.events.extend([
+            Event::FundsDebited {
+                client,
+                tx,
+                asset,
+                amount,
+            },
+            recorded,
+        ]);
         Ok(())
     }
 
-    /// Apply a `Transaction::Dispute`:
-    /// - Find the referenced deposit
-    /// - Validate client ownership
-    /// - Check deposit is in Ok state
-    /// - Move funds from available to held
-    ///
-    /// Note: Disputes may result in negative available balance if funds were
-    /// already withdrawn. This represents debt owed by the client.
-    fn apply_dispute(&mut self, client: ClientId, tx: TxId) -> Result<(), DepositOperationError> {
-        use DepositOperation::Dispute;
-
-        // Only deposits can be disputed; other transaction types return "not found"
-        let record = self
-            .deposits
-            .get_mut(&tx)
-            .ok_or(DepositOperationError::TxNotFound(Dispute, tx))?;
-
-        // Validate client ownership
-        if record.client != client {
-            return Err(DepositOperationError::ClientMismatch(
-                Dispute,
-                tx,
-                record.client,
-                client,
-            ));
+    /// Apply a `Transaction::Authorize`:
+    /// - Ensure amount is positive
+    /// - Ensure transaction ID is unique
+    /// - Ensure account exists, is unfrozen and has enough available balance
+    /// - Move the amount from available to reserved balance
+    /// - Store authorization for its capture or void
+    fn apply_authorize(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<(), EngineError> {
+        let precision = self.config.precision_for(asset);
+        if amount <= Amount::default() {
+            return Err(
+                AuthorizationError::NonPositiveAmount(tx, amount.display(precision)).into(),
+            );
         }
 
-        // Check state (ChargedBack deposits are evicted, so not found)
-        if record.state == DepositState::Disputed {
-            return Err(DepositOperationError::InvalidState(Dispute, tx));
+        if !self.is_unique(tx)? {
+            return Err(AuthorizationError::DuplicateTxId(tx).into());
         }
 
-        let amount = record.amount;
-        record.state = DepositState::Disputed; // Update state in place (no second lookup)
+        // An unknown client has no funds, its account is not created
+        let account = match self.clients.get_mut(client)? {
+            Some(account) if account.is_frozen() => {
+                return Err(AuthorizationError::AccountFrozen(client).into());
+            }
+            Some(account) if account.available(asset) >= amount => account,
+            account => {
+                let available =
+                    account.map_or(Amount::default(), |account| account.available(asset));
+                return Err(AuthorizationError::InsufficientFunds(
+                    client,
+                    asset,
+                    available.display(precision),
+                    amount.display(precision),
+                )
+                .into());
+            }
+        };
 
-        let account = self
-            .clients
-            .get_mut(&client)
-            .ok_or(DepositOperationError::ClientNotFound(Dispute, client))?;
-
-        // Move funds from available to held (may result in negative available balance)
-        if account.available() < amount {
-            warn!(
-                client = client,
-                available = %account.available(),
-                required = %amount,
-                "dispute will cause negative available balance"
-            );
+        let balance = account.stored_balance(asset);
+        account.reserve(asset, amount)?;
+
+        let record = AuthorizationRecord::new(client, asset, amount);
+        if let Err(e) = self.txs.insert_authorization(tx, record) {
+            account.restore_balance(asset, balance);
+            return Err(e.into());
         }
-        account.hold(amount);
 
+        self.events.extend([
+            Event::FundsReserved {
+                client,
+                tx,
+                asset,
+                amount,
+            },
+            Event::AuthorizationRecorded {
+                tx,
+                client,
+                asset,
+                amount,
+            },
+        ]);
         Ok(())
     }
 
-    /// Apply a `Transaction::Resolve`:
-    /// - Find the referenced deposit
-    /// - Validate client ownership
-    /// - Check deposit is in Disputed state
-    /// - Move funds from held back to available
-    fn apply_resolve(&mut self, client: ClientId, tx: TxId) -> Result<(), DepositOperationError> {
-        use DepositOperation::Resolve;
-
-        let record = self
-            .deposits
-            .get_mut(&tx)
-            .ok_or(DepositOperationError::TxNotFound(Resolve, tx))?;
+    /// Find the pending authorization referenced by a capture or void, and validate
+    /// client ownership.
+    fn find_authorization(
+        txs: &mut T,
+        op: AuthorizationOperation,
+        client: ClientId,
+        tx: TxId,
+    ) -> Result<&mut AuthorizationRecord, EngineError> {
+        let record = txs
+            .authorization_mut(tx)?
+            .ok_or(AuthorizationOperationError::TxNotFound(op, tx))?;
 
-        // Validate client ownership
         if record.client != client {
-            return Err(DepositOperationError::ClientMismatch(
-                Resolve,
-                tx,
-                record.client,
-                client,
-            ));
+            return Err(
+                AuthorizationOperationError::ClientMismatch(op, tx, record.client, client).into(),
+            );
         }
 
-        // Check state (ChargedBack deposits are evicted, so not found)
-        if record.state == DepositState::Ok {
-            return Err(DepositOperationError::InvalidState(Resolve, tx));
+        if record.state != AuthorizationState::Pending {
+            return Err(AuthorizationOperationError::InvalidState(op, tx, record.state).into());
         }
 
-        let amount = record.amount;
-        record.state = DepositState::Ok; // Update state in place (no second lookup)
+        Ok(record)
+    }
+
+    /// Apply a `Transaction::Capture`:
+    /// - Find the referenced pending authorization and validate client ownership
+    /// - Remove the reserved funds (total decreases), even if the account is frozen
+    /// - Set authorization state to Captured
+    fn apply_capture(&mut self, client: ClientId, tx: TxId) -> Result<(), EngineError> {
+        use AuthorizationOperation::Capture;
 
-        let account = self
-            .clients
-            .get_mut(&client)
-            .ok_or(DepositOperationError::ClientNotFound(Resolve, client))?;
+        let record = Self::find_authorization(&mut self.txs, Capture, client, tx)?;
+
+        // Reserved funds imply the account exists, unless the account store lost it
+        self.clients
+            .get_mut(client)?
+            .ok_or(AuthorizationOperationError::ClientNotFound(Capture, client))?
+            .remove_reserved(record.asset, record.amount)?;
 
-        // Move held back to available
-        account.release(amount);
+        record.state = AuthorizationState::Captured;
 
+        let (asset, amount) = (record.asset, record.amount);
+        self.events.extend([
+            Event::ReservedFundsRemoved {
+                client,
+                tx,
+                asset,
+                amount,
+            },
+            Event::AuthorizationCaptured { client, tx },
+        ]);
         Ok(())
     }
 
-    /// Apply a `Transaction::Chargeback`:
-    /// - Find the referenced deposit
-    /// - Validate client ownership
-    /// - Check deposit is in Disputed state
-    /// - Remove held funds (total decreases), freeze account
-    /// - Evict deposit (terminal state, can never be referenced again)
-    fn apply_chargeback(
-        &mut self,
-        client: ClientId,
-        tx: TxId,
-    ) -> Result<(), DepositOperationError> {
-        use DepositOperation::Chargeback;
+    /// Apply a `Transaction::Void`:
+    /// - Find the referenced pending authorization and validate client ownership
+    /// - Move the reserved funds back to available, even if the account is frozen
+    /// - Set authorization state to Voided
+    fn apply_void(&mut self, client: ClientId, tx: TxId) -> Result<(), EngineError> {
+        use AuthorizationOperation::Void;
 
-        let record = self
-            .deposits
-            .get(&tx)
-            .ok_or(DepositOperationError::TxNotFound(Chargeback, tx))?;
+        let record = Self::find_authorization(&mut self.txs, Void, client, tx)?;
 
-        // Validate client ownership
-        if record.client != client {
-            return Err(DepositOperationError::ClientMismatch(
-                Chargeback,
-                tx,
-                record.client,
+        // Reserved funds imply the account exists, unless the account store lost it
+        self.clients
+            .get_mut(client)?
+            .ok_or(AuthorizationOperationError::ClientNotFound(Void, client))?
+            .unreserve(record.asset, record.amount)?;
+
+        record.state = AuthorizationState::Voided;
+
+        let (asset, amount) = (record.asset, record.amount);
+        self.events.extend([
+            Event::ReservationReleased {
                 client,
-            ));
+                tx,
+                asset,
+                amount,
+            },
+            Event::AuthorizationVoided { client, tx },
+        ]);
+        Ok(())
+    }
+
+    /// Return the asset and amount of an authorization, for logging
+    fn authorization_money(&mut self, tx: TxId) -> Option<(Asset, Amount)> {
+        let record = self.txs.authorization_mut(tx).ok()??;
+        Some((record.asset, record.amount))
+    }
+
+    /// Apply a `Transaction::Transfer`:
+    /// - Ensure amount is positive and sender and receiver are different clients
+    /// - Ensure transaction ID is unique
+    /// - Ensure sender is unfrozen and has enough available balance
+    /// - Ensure receiver is unfrozen, creating its account if needed
+    /// - Move the amount from sender to receiver available balance
+    ///
+    /// Either both accounts are updated or none is.
+    fn apply_transfer(
+        &mut self,
+        from: ClientId,
+        to: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+    ) -> Result<(), EngineError> {
+        let precision = self.config.precision_for(asset);
+        if amount <= Amount::default() {
+            return Err(TransferError::NonPositiveAmount(tx, amount.display(precision)).into());
+        }
+
+        if from == to {
+            return Err(TransferError::SameClient(from).into());
         }
 
-        // Check state (ChargedBack deposits are evicted, so not found)
-        if record.state == DepositState::Ok {
-            return Err(DepositOperationError::InvalidState(Chargeback, tx));
+        if !self.is_unique(tx)? {
+            return Err(TransferError::DuplicateTxId(tx).into());
         }
 
-        let amount = record.amount;
+        // An unknown sender has no funds, its account is not created
+        let sender = self.clients.get(from)?;
+        if sender.is_some_and(ClientAccount::is_frozen) {
+            return Err(TransferError::SenderFrozen(from).into());
+        }
 
-        let account = self
-            .clients
-            .get_mut(&client)
-            .ok_or(DepositOperationError::ClientNotFound(Chargeback, client))?;
+        let available = sender.map_or(Amount::default(), |account| account.available(asset));
+        if available < amount {
+            return Err(TransferError::InsufficientFunds(
+                from,
+                asset,
+                available.display(precision),
+                amount.display(precision),
+            )
+            .into());
+        }
 
-        // Remove held funds (total decreases)
-        account.remove_held(amount);
+        if self.clients.get(to)?.is_some_and(ClientAccount::is_frozen) {
+            return Err(TransferError::ReceiverFrozen(to).into());
+        }
 
-        // Freeze account and evict deposit (terminal state)
-        account.freeze();
-        self.deposits.remove(&tx);
+        Self::open_account(&mut self.clients, &mut self.events, to)?;
+        let pair = match self.clients.get_pair_mut(from, to) {
+            Ok(pair) => pair,
+            Err(e) => {
+                self.discard_opened(to)?;
+                return Err(e.into());
+            }
+        };
+        let [Some(sender), Some(receiver)] = pair else {
+            unreachable!("sender has funds and receiver was created, so both have an account");
+        };
+
+        // Credit the receiver first: on overflow nothing was changed yet, and debiting
+        // the sender can't fail once its available balance was checked.
+        let balances = (sender.stored_balance(asset), receiver.stored_balance(asset));
+        receiver.credit(asset, amount)?;
+        sender.debit(asset, amount)?;
+
+        // Store only tx ID for duplicate checking (as transfers can't be disputed)
+        if let Err(e) = self.txs.insert_id(tx) {
+            sender.restore_balance(asset, balances.0);
+            receiver.restore_balance(asset, balances.1);
+            self.discard_opened(to)?;
+            return Err(e.into());
+        }
 
+        self.events.extend([
+            Event::FundsCredited {
+                client: to,
+                tx,
+                asset,
+                amount,
+            },
+            Event::FundsDebited {
+                client: from,
+                tx,
+                asset,
+                amount,
+            },
+            Event::TransactionIdRecorded { tx },
+        ]);
         Ok(())
     }
-}
 
-impl Default for Engine {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+    /// Apply a `Transaction::Admin`:
+    /// - Ensure transaction ID is unique and the client exists
+    /// - Lock: freeze the account, unless already frozen
+    /// - Unlock: unfreeze the account, unless not frozen
+    /// - Adjustment: credit or debit the available balance, which can't go negative
+    ///
+    /// Admin transactions apply to frozen accounts. Their rejections carry the
+    /// operator, to be told apart from the ones of client transactions.
+    fn apply_admin(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        operator: &str,
+        action: &AdminAction,
+    ) -> Result<(), EngineError> {
+        let rejected = |e| EngineError::Admin(operator.to_string(), e);
+        if !self.is_unique(tx)? {
+            return Err(rejected(AdminError::DuplicateTxId(tx)));
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let account = self
+            .clients
+            .get_mut(client)?
+            .ok_or_else(|| rejected(AdminError::ClientNotFound(client)))?;
 
-    // test utils
+        let (frozen, balance) = match action {
+            AdminAction::Adjustment { asset, .. } => {
+                (account.frozen_by(), account.stored_balance(*asset))
+            }
+            AdminAction::Lock | AdminAction::Unlock => (account.frozen_by(), None),
+        };
+        match action {
+            AdminAction::Lock if frozen == Some(FreezeReason::Admin) => {
+                return Err(rejected(AdminError::AlreadyLocked(client)));
+            }
+            // Also locks an account frozen by a chargeback, so re-presenting won't lift it
+            AdminAction::Lock => {
+                let reason = FreezeReason::Admin;
+                account.freeze(reason);
+                self.events.push(Event::AccountFrozen { client, reason });
+            }
+            AdminAction::Unlock if !account.is_frozen() => {
+                return Err(rejected(AdminError::NotLocked(client)));
+            }
+            AdminAction::Unlock => {
+                account.unfreeze();
+                self.events.push(Event::AccountUnfrozen { client });
+            }
+            AdminAction::Adjustment { asset, amount, .. } => {
+                let zero = Amount::default();
+                if *amount == zero {
+                    return Err(rejected(AdminError::ZeroAmount(tx)));
+                }
+                let asset = *asset;
+                if *amount > zero {
+                    account.credit(asset, *amount)?;
+                    self.events.push(Event::FundsCredited {
+                        client,
+                        tx,
+                        asset,
+                        amount: *amount,
+                    });
+                } else {
+                    let available = account.available(asset);
+                    let debit = zero.checked_sub(*amount).ok_or(BalanceOverflow(client))?;
+                    if available < debit {
+                        let precision = self.config.precision_for(asset);
+                        return Err(rejected(AdminError::InsufficientFunds(
+                            client,
+                            asset,
+                            available.display(precision),
+                            amount.display(precision),
+                        )));
+                    }
+                    account.debit(asset, debit)?;
+                    self.events.push(Event::FundsDebited {
+                        client,
+                        tx,
+                        asset,
+                        amount: debit,
+                    });
+                }
+            }
+        }
 
-    fn deposit(client: ClientId, tx: TxId, amount: i64) -> Transaction {
-        Transaction::Deposit {
-            client,
-            tx,
-            amount: Amount::from_scaled(amount),
+        // Store only tx ID for duplicate checking (as admin transactions can't be disputed)
+        if let Err(e) = self.txs.insert_id(tx) {
+            match action {
+                AdminAction::Adjustment { asset, .. } => account.restore_balance(*asset, balance),
+                AdminAction::Lock | AdminAction::Unlock => match frozen {
+                    Some(reason) => account.freeze(reason),
+                    None => account.unfreeze(),
+                },
+            }
+            self.events.pop();
+            return Err(e.into());
         }
+
+        self.events.push(Event::TransactionIdRecorded { tx });
+        Ok(())
     }
 
-    fn withdrawal(client: ClientId, tx: TxId, amount: i64) -> Transaction {
-        Transaction::Withdrawal {
-            client,
-            tx,
-            amount: Amount::from_scaled(amount),
+    /// Return the metadata to log with a deposit operation: its own, merged with the
+    /// retained metadata of the referenced deposit (the operation's own keys win).
+    ///
+    /// Only copied when both are present, either one is borrowed otherwise.
+    fn operation_metadata<'a>(&'a self, tx: TxId, metadata: &'a Metadata) -> Cow<'a, Metadata> {
+        if !self.config.retain_deposit_metadata {
+            return Cow::Borrowed(metadata);
+        }
+        match self.deposit_metadata.get(&tx) {
+            Some(retained) if metadata.is_empty() => Cow::Borrowed(retained),
+            Some(retained) => {
+                let mut merged = retained.clone();
+                merged.extend(metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+                Cow::Owned(merged)
+            }
+            None => Cow::Borrowed(metadata),
         }
     }
 
-    #[test]
-    fn new_engine() {
-        let engine = Engine::new();
-        assert_eq!(engine.clients().count(), 0);
+    /// Return the asset of a deposit (or disputable withdrawal) record, and the amount
+    /// requested by an operation on it at the precision of that asset, for logging
+    fn record_money(&mut self, tx: TxId, requested: Option<&Decimal>) -> Option<(Asset, Amount)> {
+        let (_, record) = self.txs.record_mut(tx).ok()??;
+        let asset = record.asset;
+        let amount = requested?
+            .to_amount(self.config.precision_for(asset))
+            .ok()?;
+        Some((asset, amount))
     }
 
-    // Deposit
+    /// Find the record referenced by a deposit operation and validate client ownership.
+    ///
+    /// Withdrawals are only found if they are disputable (see [`DisputePolicy`]),
+    /// other transaction types return "not found".
+    fn find_record(
+        txs: &mut T,
+        op: DepositOperation,
+        client: ClientId,
+        tx: TxId,
+    ) -> Result<(RecordKind, &mut DepositRecord), EngineError> {
+        let (kind, record) = txs
+            .record_mut(tx)?
+            .ok_or(DepositOperationError::TxNotFound(op, tx))?;
 
-    #[test]
-    fn deposit_creates_account_and_increases_balance() {
-        let mut engine = Engine::new();
+        if record.client != client {
+            return Err(
+                DepositOperationError::ClientMismatch(op, tx, record.client, client).into(),
+            );
+        }
+
+        Ok((kind, record))
+    }
+
+    /// Return the amount a (possibly partial) deposit operation applies to.
+    ///
+    /// `remaining` is the most the operation can cover: the undisputed remainder
+    /// for a dispute, the disputed portion for a resolve or chargeback. Without a
+    /// requested amount, the operation covers all of it. The requested amount is
+    /// scaled at `precision`, the precision of the record's asset.
+    fn operation_amount(
+        op: DepositOperation,
+        tx: TxId,
+        requested: Option<&Decimal>,
+        remaining: Amount,
+        precision: Precision,
+    ) -> Result<Amount, DepositOperationError> {
+        // Nothing left to dispute, or nothing disputed to resolve or charge back
+        if remaining <= Amount::default() {
+            return Err(DepositOperationError::InvalidState(op, tx));
+        }
+
+        let requested = requested
+            .map(|amount| amount.to_amount(precision))
+            .transpose()
+            .map_err(|e| DepositOperationError::InvalidAmount(op, tx, e))?;
+        match requested {
+            None => Ok(remaining),
+            Some(amount) if amount <= Amount::default() => Err(
+                DepositOperationError::NonPositiveAmount(op, tx, amount.display(precision)),
+            ),
+            Some(amount) if amount > remaining => Err(DepositOperationError::AmountExceeded(
+                op,
+                tx,
+                amount.display(precision),
+                remaining.display(precision),
+            )),
+            Some(amount) => Ok(amount),
+        }
+    }
+
+    /// Return the account of the client of a dispute, resolve or chargeback,
+    /// rejecting frozen accounts unless the config allows these operations on them.
+    fn operation_account<'a>(
+        clients: &'a mut A,
+        config: &EngineConfig,
+        op: DepositOperation,
+        client: ClientId,
+    ) -> Result<&'a mut ClientAccount, EngineError> {
+        let account = clients
+            .get_mut(client)?
+            .ok_or(DepositOperationError::ClientNotFound(op, client))?;
+        if account.is_frozen() && !config.allow_frozen_disputes {
+            return Err(DepositOperationError::AccountFrozen(op, client).into());
+        }
+        Ok(account)
+    }
+
+    /// Apply a `Transaction::Dispute`:
+    /// - Find the referenced deposit (or disputable withdrawal)
+    /// - Validate client ownership
+    /// - Check the record is within the dispute window of `now`, the engine time, if any
+    /// - Check the record was not disputed too many times, if limited
+    /// - Check the disputed amount is within the undisputed remainder (all of it by default)
+    /// - Check the account is not frozen, unless allowed
+    /// - Deposit: move funds from available to held
+    /// - Withdrawal: credit the withdrawn funds back as held
+    /// - Add the amount to the disputed portion of the record
+    ///
+    /// Note: By default disputes may result in negative available balance if funds
+    /// were already withdrawn. This represents debt owed by the client. See
+    /// [`NegativeAvailablePolicy`] for the alternatives.
+    fn apply_dispute(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        requested: Option<&Decimal>,
+        now: Timestamp,
+    ) -> Result<(), EngineError> {
+        use DepositOperation::Dispute;
+
+        // Records evicted after the dispute window are known to be too old
+        if self.txs.is_evicted(tx)? {
+            return Err(DepositOperationError::WindowExpired(Dispute, tx).into());
+        }
+
+        let (kind, record) = Self::find_record(&mut self.txs, Dispute, client, tx)?;
+
+        if let Some(window) = self.config.dispute_window
+            && now.saturating_sub(record.timestamp) > window
+        {
+            return Err(DepositOperationError::WindowExpired(Dispute, tx).into());
+        }
+
+        // Reopening a resolved dispute counts, not a concurrent partial dispute
+        if let Some(max) = self.config.max_redisputes
+            && record.disputed == Amount::default()
+        {
+            let count = self.dispute_counts.get(&tx).copied().unwrap_or(0);
+            if count > max {
+                return Err(DepositOperationError::DisputeLimitReached(Dispute, tx, count).into());
+            }
+        }
+
+        // Fully disputed or charged back records have nothing left to dispute
+        let undisputed = record.undisputed().ok_or(BalanceOverflow(client))?;
+        let mut amount = Self::operation_amount(
+            Dispute,
+            tx,
+            requested,
+            undisputed,
+            self.config.precision_for(record.asset),
+        )?;
+        let asset = record.asset;
+
+        let account = Self::operation_account(&mut self.clients, &self.config, Dispute, client)?;
+
+        if kind == RecordKind::Deposit {
+            // Funds move from available to held (may result in negative available balance)
+            let available = account.available(asset);
+            if available < amount {
+                match self.config.negative_available {
+                    NegativeAvailablePolicy::Allow => {
+                        let precision = self.config.precision_for(asset);
+                        warn!(
+                            client = client,
+                            asset = %asset,
+                            available = %available.display(precision),
+                            required = %amount.display(precision),
+                            "dispute will cause negative available balance"
+                        );
+                    }
+                    NegativeAvailablePolicy::Cap if available > Amount::default() => {
+                        amount = available;
+                    }
+                    NegativeAvailablePolicy::Cap | NegativeAvailablePolicy::Reject => {
+                        let precision = self.config.precision_for(asset);
+                        return Err(DepositOperationError::InsufficientFunds(
+                            Dispute,
+                            tx,
+                            available.display(precision),
+                            amount.display(precision),
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+        let disputed = record.disputed.checked_add(amount);
+        let disputed = disputed.ok_or(BalanceOverflow(client))?;
+
+        match kind {
+            RecordKind::Deposit => account.hold(asset, amount)?,
+            // Provisionally return the withdrawn funds, held until the dispute is settled
+            RecordKind::Withdrawal => account.add_held(asset, amount)?,
+        }
+
+        // Update state in place (no second lookup), once the funds are moved
+        record.set_disputed(disputed);
+
+        self.events.extend([
+            match kind {
+                RecordKind::Deposit => Event::FundsHeld {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                },
+                RecordKind::Withdrawal => Event::HeldFundsAdded {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                },
+            },
+            Event::DisputeOpened { client, tx, amount },
+        ]);
+        Ok(())
+    }
+
+    /// Apply a `Transaction::Resolve`:
+    /// - Find the referenced deposit (or disputable withdrawal)
+    /// - Validate client ownership
+    /// - Check the resolved amount is within the disputed portion (all of it by default)
+    /// - Check the account is not frozen, unless allowed
+    /// - Deposit: move funds from held back to available
+    /// - Withdrawal: remove held funds, the withdrawal stands
+    /// - Remove the amount from the disputed portion of the record
+    fn apply_resolve(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        requested: Option<&Decimal>,
+    ) -> Result<(), EngineError> {
+        use DepositOperation::Resolve;
+
+        let (kind, record) = Self::find_record(&mut self.txs, Resolve, client, tx)?;
+
+        // Records in the Ok state have nothing disputed to resolve
+        let amount = Self::operation_amount(
+            Resolve,
+            tx,
+            requested,
+            record.disputed,
+            self.config.precision_for(record.asset),
+        )?;
+        let asset = record.asset;
+
+        let disputed = record.disputed.checked_sub(amount);
+        let disputed = disputed.ok_or(BalanceOverflow(client))?;
+
+        let account = Self::operation_account(&mut self.clients, &self.config, Resolve, client)?;
+
+        match kind {
+            // Move held back to available
+            RecordKind::Deposit => account.release(asset, amount)?,
+            // Dispute rejected, the provisionally returned funds are taken back
+            RecordKind::Withdrawal => account.remove_held(asset, amount)?,
+        }
+
+        // Update state in place (no second lookup), once the funds are moved
+        record.set_disputed(disputed);
+        if self.config.max_redisputes.is_some() && disputed == Amount::default() {
+            *self.dispute_counts.entry(tx).or_default() += 1;
+        }
+
+        self.events.extend([
+            match kind {
+                RecordKind::Deposit => Event::FundsReleased {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                },
+                RecordKind::Withdrawal => Event::HeldFundsRemoved {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                },
+            },
+            Event::DisputeResolved { client, tx, amount },
+        ]);
+        Ok(())
+    }
+
+    /// Apply a `Transaction::Chargeback`:
+    /// - Find the referenced deposit (or disputable withdrawal)
+    /// - Validate client ownership
+    /// - Check the charged back amount is within the disputed portion (all of it by default)
+    /// - Check the account is not frozen, unless allowed
+    /// - Deposit: remove held funds (total decreases), freeze account
+    /// - Withdrawal: move held funds to available (client refunded)
+    /// - Move the amount from the disputed to the charged back portion of the record,
+    ///   which is retained for re-presentment
+    fn apply_chargeback(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        requested: Option<&Decimal>,
+    ) -> Result<(), EngineError> {
+        use DepositOperation::Chargeback;
+
+        let (kind, record) = Self::find_record(&mut self.txs, Chargeback, client, tx)?;
+
+        // Records in the Ok state have nothing disputed to charge back
+        let amount = Self::operation_amount(
+            Chargeback,
+            tx,
+            requested,
+            record.disputed,
+            self.config.precision_for(record.asset),
+        )?;
+        let asset = record.asset;
+
+        let disputed = record.disputed.checked_sub(amount);
+        let charged_back = record.charged_back.checked_add(amount);
+        let (disputed, charged_back) = disputed.zip(charged_back).ok_or(BalanceOverflow(client))?;
+
+        let account = Self::operation_account(&mut self.clients, &self.config, Chargeback, client)?;
+
+        match kind {
+            RecordKind::Deposit => {
+                // Remove held funds (total decreases) and freeze account
+                account.remove_held(asset, amount)?;
+                self.events.push(Event::HeldFundsRemoved {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                });
+                // An admin lock stays the reason, so re-presenting won't lift it
+                if !account.is_frozen() {
+                    let reason = FreezeReason::Chargeback;
+                    account.freeze(reason);
+                    self.events.push(Event::AccountFrozen { client, reason });
+                }
+                if record.charged_back == Amount::default() {
+                    account.add_chargeback();
+                }
+            }
+            // Refund the client, the account stays unfrozen
+            RecordKind::Withdrawal => {
+                account.release(asset, amount)?;
+                self.events.push(Event::FundsReleased {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                });
+            }
+        }
+
+        // The record is retained for re-presentment, the rest can still be disputed
+        record.disputed = disputed;
+        record.set_charged_back(charged_back);
+
+        self.events
+            .push(Event::ChargebackApplied { client, tx, amount });
+        Ok(())
+    }
+
+    /// Apply a `Transaction::Represent`:
+    /// - Find the referenced deposit (or disputable withdrawal)
+    /// - Validate client ownership
+    /// - Check the re-presented amount is within the charged back portion (all of it by default)
+    /// - Deposit: credit the funds back to available
+    /// - Withdrawal: debit the refunded funds from available, the withdrawal stands
+    /// - Remove the amount from the charged back portion of the record
+    /// - Unfreeze the account once no chargeback remains on it, if configured and
+    ///   frozen by a chargeback (an admin lock is only lifted by an admin unlock)
+    fn apply_represent(
+        &mut self,
+        client: ClientId,
+        tx: TxId,
+        requested: Option<&Decimal>,
+    ) -> Result<(), EngineError> {
+        use DepositOperation::Represent;
+
+        let (kind, record) = Self::find_record(&mut self.txs, Represent, client, tx)?;
+
+        // Records never charged back have nothing to re-present
+        let amount = Self::operation_amount(
+            Represent,
+            tx,
+            requested,
+            record.charged_back,
+            self.config.precision_for(record.asset),
+        )?;
+        let asset = record.asset;
+
+        let charged_back = record.charged_back.checked_sub(amount);
+        let charged_back = charged_back.ok_or(BalanceOverflow(client))?;
+
+        let account = self
+            .clients
+            .get_mut(client)?
+            .ok_or(DepositOperationError::ClientNotFound(Represent, client))?;
+
+        let mut unfrozen = false;
+        match kind {
+            RecordKind::Deposit => {
+                account.credit(asset, amount)?;
+                if record.charged_back == amount {
+                    account.remove_chargeback();
+                    if self.config.unfreeze_on_represent
+                        && account.chargebacks() == 0
+                        && account.frozen_by() == Some(FreezeReason::Chargeback)
+                    {
+                        account.unfreeze();
+                        unfrozen = true;
+                    }
+                }
+            }
+            // May result in negative available balance if refunded funds were withdrawn
+            RecordKind::Withdrawal => account.debit(asset, amount)?,
+        }
+
+        // Update state in place (no second lookup), once the funds are moved
+        record.set_charged_back(charged_back);
+
+        self.events.extend([
+            match kind {
+                RecordKind::Deposit => Event::FundsCredited {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                },
+                RecordKind::Withdrawal => Event::FundsDebited {
+                    client,
+                    tx,
+                    asset,
+                    amount,
+                },
+            },
+            Event::ChargebackReversed { client, tx, amount },
+        ]);
+        if unfrozen {
+            self.events.push(Event::AccountUnfrozen { client });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Transactions and helpers shared by the tests of the engine modules.
+#[cfg(test)]
+pub(super) mod test_utils {
+    use super::Engine;
+    use crate::model::{AdminAction, Asset, ClientId, Metadata, Timestamp, Transaction, TxId};
+    use crate::{Amount, Precision};
+
+    pub(super) const USD: Asset = Asset::new("USD").unwrap();
+    pub(super) const EUR: Asset = Asset::new("EUR").unwrap();
+
+    pub(super) fn deposit(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        deposit_asset(client, tx, USD, amount)
+    }
+
+    pub(super) fn deposit_asset(
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: i64,
+    ) -> Transaction {
+        Transaction::Deposit {
+            client,
+            tx,
+            asset,
+            amount: Amount::from_scaled(amount),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    /// A deposit at a time following its ID, with a reference
+    pub(super) fn recorded(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        recorded_asset(client, tx, USD, amount)
+    }
+
+    pub(super) fn recorded_asset(
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: i64,
+    ) -> Transaction {
+        let deposit = at(deposit_asset(client, tx, asset, amount), tx * 10);
+        with_metadata(deposit, &[("ref", &format!("INV-{tx}"))])
+    }
+
+    pub(super) fn withdrawal(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        withdrawal_asset(client, tx, USD, amount)
+    }
+
+    pub(super) fn withdrawal_asset(
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: i64,
+    ) -> Transaction {
+        Transaction::Withdrawal {
+            client,
+            tx,
+            asset,
+            amount: Amount::from_scaled(amount),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn authorize(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        Transaction::Authorize {
+            client,
+            tx,
+            asset: USD,
+            amount: Amount::from_scaled(amount),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn transfer(from: ClientId, to: ClientId, tx: TxId, amount: i64) -> Transaction {
+        Transaction::Transfer {
+            from,
+            to,
+            tx,
+            asset: USD,
+            amount: Amount::from_scaled(amount),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn dispute(client: ClientId, tx: TxId) -> Transaction {
+        Transaction::Dispute {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn chargeback(client: ClientId, tx: TxId) -> Transaction {
+        Transaction::Chargeback {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn dispute_part(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        Transaction::Dispute {
+            client,
+            tx,
+            amount: Some(
+                Amount::from_scaled(amount)
+                    .display(Precision::DEFAULT)
+                    .into(),
+            ),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn resolve(client: ClientId, tx: TxId) -> Transaction {
+        Transaction::Resolve {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn resolve_part(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        Transaction::Resolve {
+            client,
+            tx,
+            amount: Some(
+                Amount::from_scaled(amount)
+                    .display(Precision::DEFAULT)
+                    .into(),
+            ),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn chargeback_part(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        Transaction::Chargeback {
+            client,
+            tx,
+            amount: Some(
+                Amount::from_scaled(amount)
+                    .display(Precision::DEFAULT)
+                    .into(),
+            ),
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn capture(client: ClientId, tx: TxId) -> Transaction {
+        Transaction::Capture {
+            client,
+            tx,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn void(client: ClientId, tx: TxId) -> Transaction {
+        Transaction::Void {
+            client,
+            tx,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn represent(client: ClientId, tx: TxId) -> Transaction {
+        Transaction::Represent {
+            client,
+            tx,
+            amount: None,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn admin(client: ClientId, tx: TxId, action: AdminAction) -> Transaction {
+        Transaction::Admin {
+            client,
+            tx,
+            operator: "ops-1".to_string(),
+            action,
+            timestamp: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub(super) fn at(mut tx: Transaction, time: Timestamp) -> Transaction {
+        *tx.timestamp_mut() = Some(time);
+        tx
+    }
+
+    pub(super) fn with_metadata(mut tx: Transaction, entries: &[(&str, &str)]) -> Transaction {
+        let metadata = match &mut tx {
+            Transaction::Deposit { metadata, .. }
+            | Transaction::Withdrawal { metadata, .. }
+            | Transaction::Authorize { metadata, .. }
+            | Transaction::Capture { metadata, .. }
+            | Transaction::Void { metadata, .. }
+            | Transaction::Transfer { metadata, .. }
+            | Transaction::Admin { metadata, .. }
+            | Transaction::Dispute { metadata, .. }
+            | Transaction::Resolve { metadata, .. }
+            | Transaction::Chargeback { metadata, .. }
+            | Transaction::Represent { metadata, .. } => metadata,
+        };
+        metadata.extend(entries.iter().map(|(k, v)| (k.to_string(), v.to_string())));
+        tx
+    }
+
+    /// Return the snapshot of an engine, to compare states.
+    pub(super) fn snapshot(engine: &Engine) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        engine.snapshot(&mut bytes).unwrap();
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::*;
+    use super::*;
+    use crate::engine::event::{FailingFile, FailingWriter};
+
+    #[test]
+    fn new_engine() {
+        let engine = Engine::new();
+        assert_eq!(engine.clients().count(), 0);
+    }
+
+    // Deposit
+
+    #[test]
+    fn deposit_creates_account_and_increases_balance() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert!(!client.is_frozen());
+    }
+
+    #[test]
+    fn deposit_accumulates_balance() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(1, 2, 50)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(150));
+    }
+
+    #[test]
+    fn deposit_to_frozen_account_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine
+            .clients
+            .accounts
+            .get_mut(&1)
+            .unwrap()
+            .freeze(FreezeReason::Admin);
+
+        let result = engine.apply(deposit(1, 2, 50));
+        assert!(matches!(
+            result,
+            Err(EngineError::Deposit(DepositError::AccountFrozen(1)))
+        ));
+
+        // Balance unchanged
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn deposit_overflow_is_rejected_and_state_unchanged() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, i64::MAX)).unwrap();
+
+        let result = engine.apply(deposit(1, 2, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::Overflow(BalanceOverflow(1)))
+        ));
+
+        // Balance unchanged and the rejected deposit is not recorded
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(i64::MAX));
+        assert!(!engine.txs.deposits.contains_key(&2));
+    }
+
+    #[test]
+    fn held_funds_count_towards_overflow() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, i64::MAX)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+
+        // available is 0 but the total would overflow
+        let result = engine.apply(deposit(1, 2, 1));
+        assert!(matches!(result, Err(EngineError::Overflow(_))));
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(USD), Amount::from_scaled(i64::MAX));
+        assert_eq!(client.total(USD), Amount::from_scaled(i64::MAX));
+    }
+
+    #[test]
+    fn deposit_non_positive_amount_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        for (tx, amount) in [(2, -50), (3, 0)] {
+            let result = engine.apply(deposit(1, tx, amount));
+            assert!(matches!(
+                result,
+                Err(EngineError::Deposit(DepositError::NonPositiveAmount(t, _))) if t == tx
+            ));
+        }
+
+        // Balance unchanged, and tx IDs are not consumed
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        engine.apply(deposit(1, 2, 10)).unwrap();
+    }
+
+    #[test]
+    fn zero_deposit_allowed_by_config() {
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_zero_deposits: true,
+            ..EngineConfig::default()
+        });
+        engine.apply(deposit(1, 1, 0)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(0));
+
+        // Negative deposits are still rejected
+        let result = engine.apply(deposit(1, 2, -1));
+        assert!(matches!(
+            result,
+            Err(EngineError::Deposit(DepositError::NonPositiveAmount(2, _)))
+        ));
+    }
+
+    // Withdrawal
+
+    #[test]
+    fn withdrawal_decreases_balance() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 30)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(70));
+    }
+
+    #[test]
+    fn withdrawal_exact_amount_succeeds() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 100)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(0));
+    }
+
+    #[test]
+    fn withdrawal_insufficient_funds_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        let result = engine.apply(withdrawal(1, 2, 101));
+        assert!(matches!(
+            result,
+            Err(EngineError::Withdrawal(WithdrawalError::InsufficientFunds(
+                1,
+                USD,
+                _,
+                _
+            )))
+        ));
+
+        // Balance unchanged
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn withdrawal_non_positive_amount_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        for (tx, amount) in [(2, -100), (3, 0)] {
+            let result = engine.apply(withdrawal(1, tx, amount));
+            assert!(matches!(
+                result,
+                Err(EngineError::Withdrawal(WithdrawalError::NonPositiveAmount(t, _))) if t == tx
+            ));
+        }
+
+        // A negative withdrawal must not credit the client
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn withdrawal_from_frozen_account_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine
+            .clients
+            .accounts
+            .get_mut(&1)
+            .unwrap()
+            .freeze(FreezeReason::Admin);
+
+        let result = engine.apply(withdrawal(1, 2, 50));
+        assert!(matches!(
+            result,
+            Err(EngineError::Withdrawal(WithdrawalError::AccountFrozen(1)))
+        ));
+
+        // Balance unchanged
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn withdrawal_from_nonexistent_account_fails() {
+        let mut engine = Engine::new();
+
+        // equivalent to zero account
+        let result = engine.apply(withdrawal(1, 1, 50));
+        assert!(matches!(
+            result,
+            Err(EngineError::Withdrawal(WithdrawalError::InsufficientFunds(
+                1,
+                USD,
+                _,
+                _
+            )))
+        ));
+    }
+
+    // Multiple assets
+
+    #[test]
+    fn assets_are_independent() {
+        let mut engine = Engine::new();
+        engine.apply(deposit_asset(1, 1, USD, 100)).unwrap();
+        engine.apply(deposit_asset(1, 2, EUR, 50)).unwrap();
+        engine.apply(withdrawal_asset(1, 3, EUR, 20)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.available(EUR), Amount::from_scaled(30));
+    }
+
+    #[test]
+    fn withdrawal_checks_funds_of_the_same_asset() {
+        let mut engine = Engine::new();
+        engine.apply(deposit_asset(1, 1, USD, 100)).unwrap();
+
+        let result = engine.apply(withdrawal_asset(1, 2, EUR, 50));
+        assert!(matches!(
+            result,
+            Err(EngineError::Withdrawal(WithdrawalError::InsufficientFunds(
+                1,
+                EUR,
+                _,
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn error_amounts_use_the_asset_precision() {
+        let cents = Precision::new(2, crate::amount::RoundingMode::Exact).unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            asset_precision: HashMap::from([(EUR, cents)]),
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                asset: EUR,
+                amount: Amount::from_scaled_at(150, cents),
+                timestamp: None,
+                metadata: Metadata::default(),
+            })
+            .unwrap();
+
+        let error = engine
+            .apply(Transaction::Withdrawal {
+                client: 1,
+                tx: 2,
+                asset: EUR,
+                amount: Amount::from_scaled_at(200, cents),
+                timestamp: None,
+                metadata: Metadata::default(),
+            })
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "withdrawal failed: insufficient available EUR funds for client 1: \
+             available 1.5, requested 2"
+        );
+    }
+
+    #[test]
+    fn dispute_holds_the_deposited_asset() {
+        let mut engine = Engine::new();
+        engine.apply(deposit_asset(1, 1, USD, 100)).unwrap();
+        engine.apply(deposit_asset(1, 2, EUR, 50)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert_eq!(client.available(EUR), Amount::from_scaled(0));
+        assert_eq!(client.held(EUR), Amount::from_scaled(50));
+
+        engine.apply(chargeback(1, 2)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.total(USD), Amount::from_scaled(100));
+        assert_eq!(client.total(EUR), Amount::from_scaled(0));
+        assert!(client.is_frozen());
+    }
+
+    // Multiple Clients
+
+    #[test]
+    fn multiple_clients_are_independent() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(2, 2, 200)).unwrap();
+        engine.apply(withdrawal(1, 3, 30)).unwrap();
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        let client2 = engine.get_client(2).unwrap().unwrap();
+
+        assert_eq!(client1.available(USD), Amount::from_scaled(70));
+        assert_eq!(client2.available(USD), Amount::from_scaled(200));
+    }
+
+    // clients() iterator
+
+    #[test]
+    fn clients_iterator_returns_all_clients() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(2, 2, 200)).unwrap();
+
+        let clients: Vec<_> = engine.clients().collect();
+        assert_eq!(clients.len(), 2);
+
+        // Find each client (without any ordering guarantees)
+        let c1 = clients.iter().find(|c| c.id() == 1).unwrap();
+        let c2 = clients.iter().find(|c| c.id() == 2).unwrap();
+
+        assert_eq!(c1.available(USD), Amount::from_scaled(100));
+        assert_eq!(c2.available(USD), Amount::from_scaled(200));
+    }
+
+    //  Async run()
+
+    #[tokio::test]
+    async fn run_processes_all_transactions() {
+        let mut engine = Engine::new();
+        let transactions = vec![deposit(1, 1, 100), deposit(2, 2, 200), withdrawal(1, 3, 25)];
+
+        engine.run(tokio_stream::iter(transactions)).await.unwrap();
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        let client2 = engine.get_client(2).unwrap().unwrap();
+
+        assert_eq!(client1.available(USD), Amount::from_scaled(75));
+        assert_eq!(client2.available(USD), Amount::from_scaled(200));
+    }
+
+    #[tokio::test]
+    async fn run_evicts_expired_records() {
+        let mut engine = with_dispute_window(100);
+        let transactions = vec![
+            at(deposit(1, 1, 100), 1_000),
+            at(deposit(1, 2, 100), 1_080),
+            at(deposit(1, 3, 100), 1_150),
+            at(deposit(1, 4, 100), 1_200),
+        ];
+
+        engine.run(tokio_stream::iter(transactions)).await.unwrap();
+
+        // Evicted when the clock moved by the window, at 1_150: tx 2 only expires after
+        assert!(!engine.txs.deposits.contains_key(&1));
+        assert!(engine.txs.deposits.contains_key(&2));
+        assert!(engine.txs.deposits.contains_key(&3));
+        assert!(engine.txs.deposits.contains_key(&4));
+    }
+
+    #[tokio::test]
+    async fn run_skips_failed_transactions_and_continues() {
+        let mut engine = Engine::new();
+        let transactions = vec![
+            deposit(1, 1, 100),
+            withdrawal(1, 2, 200), // Should fail with insufficient funds
+            deposit(1, 3, 50),     // Should still process
+        ];
+
+        engine.run(tokio_stream::iter(transactions)).await.unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+
+        assert_eq!(client.available(USD), Amount::from_scaled(150)); // 100 + 50 with withdrawal skipped
+    }
+
+    #[test]
+    fn failed_journal_append_keeps_the_rejection() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("journal.bin");
+        let (journal, budget) = FailingFile::journal(&path, false);
+        let mut engine = Engine::new();
+        engine.set_journal(journal);
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        // Rejected, but opens the account of client 2
+        budget.store(3, std::sync::atomic::Ordering::Relaxed);
+        let result = engine.apply(withdrawal(2, 2, 50));
+        assert!(matches!(
+            result,
+            Err(EngineError::RejectionNotJournaled { rejection, .. })
+                if matches!(*rejection, EngineError::Withdrawal(WithdrawalError::InsufficientFunds(2, ..)))
+        ));
+        assert!(engine.get_client(2).unwrap().is_some());
+
+        // The partial frame was dropped, the following ones are readable
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+        budget.store(usize::MAX, std::sync::atomic::Ordering::Relaxed);
+        engine.apply(deposit(1, 3, 10)).unwrap();
+        let mut replayed = Engine::new();
+        let file = std::fs::File::open(&path).unwrap();
+        assert_eq!(replayed.replay(file).unwrap(), 2);
+        let client = replayed.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(110));
+    }
+
+    #[tokio::test]
+    async fn run_stops_on_journal_failure() {
+        let writer = FailingWriter::default();
+        let mut engine = Engine::new();
+        engine.set_journal(Journal::new(writer.clone()).unwrap());
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        writer.fail();
+        let transactions = vec![deposit(1, 2, 50), deposit(1, 3, 25)];
+        assert!(engine.run(tokio_stream::iter(transactions)).await.is_err());
+
+        // The failing transaction stays applied, the following one isn't
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(150));
+    }
+
+    // Dispute, Resolve, Chargeback - test utils
+
+    // Dispute tests
+
+    #[test]
+    fn dispute_deposit_moves_funds_to_held() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(0));
+        assert_eq!(client.held(USD), Amount::from_scaled(100));
+        assert_eq!(client.total(USD), Amount::from_scaled(100));
+        assert!(!client.is_frozen());
+    }
+
+    #[test]
+    fn dispute_withdrawal_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 40)).unwrap();
+
+        // Withdrawals can't be disputed - they're not in the deposits map
+        let result = engine.apply(dispute(1, 2));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::TxNotFound(DepositOperation::Dispute, 2)
+            ))
+        ));
+
+        // Balance unchanged
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(60));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+    }
+
+    #[test]
+    fn dispute_nonexistent_tx_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        let result = engine.apply(dispute(1, 999));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::TxNotFound(DepositOperation::Dispute, 999)
+            ))
+        ));
+    }
+
+    #[test]
+    fn dispute_wrong_client_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(2, 2, 50)).unwrap();
+
+        let result = engine.apply(dispute(2, 1)); // tx 1 belongs to client 1
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::ClientMismatch(DepositOperation::Dispute, 1, 1, 2)
+            ))
+        ));
+    }
+
+    #[test]
+    fn dispute_already_disputed_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+
+        let result = engine.apply(dispute(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InvalidState(DepositOperation::Dispute, 1)
+            ))
+        ));
+    }
+
+    #[test]
+    fn dispute_deposit_insufficient_funds_causes_negative_balance() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 60)).unwrap(); // leaves 40 available
+
+        // Dispute succeeds even with insufficient funds (logs warning)
+        engine.apply(dispute(1, 1)).unwrap(); // needs 100, has 40
+
+        // Available is now negative (-60), held is 100
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(-60));
+        assert_eq!(client.held(USD), Amount::from_scaled(100));
+        assert_eq!(client.total(USD), Amount::from_scaled(40)); // total unchanged
+    }
+
+    // Resolve tests
+
+    #[test]
+    fn resolve_deposit_returns_funds_to_available() {
+        let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(resolve(1, 1)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(100));
-        assert_eq!(client.held(), Amount::from_scaled(0));
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
         assert!(!client.is_frozen());
     }
 
     #[test]
-    fn deposit_accumulates_balance() {
+    fn resolve_not_disputed_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        let result = engine.apply(resolve(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InvalidState(DepositOperation::Resolve, 1)
+            ))
+        ));
+    }
+
+    #[test]
+    fn resolved_tx_can_be_disputed_again() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(resolve(1, 1)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap(); // should succeed
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(0));
+        assert_eq!(client.held(USD), Amount::from_scaled(100));
+    }
+
+    // Chargeback tests
+
+    #[test]
+    fn chargeback_deposit_removes_held_and_freezes() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(0));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert_eq!(client.total(USD), Amount::from_scaled(0));
+        assert!(client.is_frozen());
+    }
+
+    #[test]
+    fn chargeback_not_disputed_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        let result = engine.apply(chargeback(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InvalidState(DepositOperation::Chargeback, 1)
+            ))
+        ));
+    }
+
+    #[test]
+    fn chargedback_tx_cannot_be_disputed() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
+
+        // Chargedback transactions are retained, with nothing left to dispute
+        let result = engine.apply(dispute(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InvalidState(DepositOperation::Dispute, 1)
+            ))
+        ));
+        assert_eq!(engine.txs.deposits[&1].state, DepositState::ChargedBack);
+    }
+
+    // Two-phase withdrawal tests
+
+    #[test]
+    fn authorize_reserves_funds() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(authorize(1, 2, 60)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(40));
+        assert_eq!(client.reserved(USD), Amount::from_scaled(60));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert_eq!(client.total(USD), Amount::from_scaled(100));
+
+        // Reserved funds can't be withdrawn
+        assert!(matches!(
+            engine.apply(withdrawal(1, 3, 50)),
+            Err(EngineError::Withdrawal(WithdrawalError::InsufficientFunds(
+                1,
+                _,
+                _,
+                _
+            )))
+        ));
+    }
+
+    #[test]
+    fn capture_removes_reserved_funds() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(authorize(1, 2, 60)).unwrap();
+        engine.apply(capture(1, 2)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(40));
+        assert_eq!(client.reserved(USD), Amount::from_scaled(0));
+        assert_eq!(client.total(USD), Amount::from_scaled(40));
+        assert_eq!(
+            engine.txs.authorizations[&2].state,
+            AuthorizationState::Captured
+        );
+
+        assert!(matches!(
+            engine.apply(void(1, 2)),
+            Err(EngineError::AuthorizationOperation(
+                AuthorizationOperationError::InvalidState(
+                    AuthorizationOperation::Void,
+                    2,
+                    AuthorizationState::Captured
+                )
+            ))
+        ));
+    }
+
+    #[test]
+    fn void_returns_reserved_funds() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(authorize(1, 2, 60)).unwrap();
+        engine.apply(void(1, 2)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.reserved(USD), Amount::from_scaled(0));
+
+        assert!(matches!(
+            engine.apply(capture(1, 2)),
+            Err(EngineError::AuthorizationOperation(
+                AuthorizationOperationError::InvalidState(
+                    AuthorizationOperation::Capture,
+                    2,
+                    AuthorizationState::Voided
+                )
+            ))
+        ));
+    }
+
+    #[test]
+    fn authorization_validation() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(deposit(1, 2, 50)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(150));
+        assert!(matches!(
+            engine.apply(authorize(1, 2, 150)),
+            Err(EngineError::Authorization(
+                AuthorizationError::InsufficientFunds(1, _, _, _)
+            ))
+        ));
+        assert!(matches!(
+            engine.apply(authorize(2, 2, 10)),
+            Err(EngineError::Authorization(
+                AuthorizationError::InsufficientFunds(2, _, _, _)
+            ))
+        ));
+        assert!(engine.get_client(2).unwrap().is_none());
+        assert!(matches!(
+            engine.apply(authorize(1, 1, 10)),
+            Err(EngineError::Authorization(
+                AuthorizationError::DuplicateTxId(1)
+            ))
+        ));
+        assert!(matches!(
+            engine.apply(capture(1, 1)),
+            Err(EngineError::AuthorizationOperation(
+                AuthorizationOperationError::TxNotFound(AuthorizationOperation::Capture, 1)
+            ))
+        ));
+
+        engine.apply(authorize(1, 2, 10)).unwrap();
+        assert!(matches!(
+            engine.apply(capture(2, 2)),
+            Err(EngineError::AuthorizationOperation(
+                AuthorizationOperationError::ClientMismatch(
+                    AuthorizationOperation::Capture,
+                    2,
+                    1,
+                    2
+                )
+            ))
+        ));
     }
 
     #[test]
-    fn deposit_to_frozen_account_fails() {
+    fn frozen_account_can_settle_authorizations() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.clients.get_mut(&1).unwrap().freeze();
+        engine.apply(authorize(1, 2, 30)).unwrap();
+        engine.apply(authorize(1, 3, 30)).unwrap();
+        engine
+            .clients
+            .accounts
+            .get_mut(&1)
+            .unwrap()
+            .freeze(FreezeReason::Admin);
 
-        let result = engine.apply(deposit(1, 2, 50));
         assert!(matches!(
-            result,
-            Err(EngineError::Deposit(DepositError::AccountFrozen(1)))
+            engine.apply(authorize(1, 4, 10)),
+            Err(EngineError::Authorization(
+                AuthorizationError::AccountFrozen(1)
+            ))
         ));
+        engine.apply(capture(1, 2)).unwrap();
+        engine.apply(void(1, 3)).unwrap();
 
-        // Balance unchanged
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(100));
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(70));
+        assert_eq!(client.reserved(USD), Amount::from_scaled(0));
     }
 
-    // Withdrawal
+    // Transfer tests
 
     #[test]
-    fn withdrawal_decreases_balance() {
+    fn transfer_moves_funds_and_creates_receiver() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(transfer(1, 2, 2, 40)).unwrap();
+
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(60)
+        );
+        assert_eq!(
+            engine.get_client(2).unwrap().unwrap().available(USD),
+            Amount::from_scaled(40)
+        );
+    }
+
+    #[test]
+    fn transfer_insufficient_funds_fails() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(withdrawal(1, 2, 30)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(70));
+        let result = engine.apply(transfer(1, 2, 2, 150));
+        assert!(matches!(
+            result,
+            Err(EngineError::Transfer(TransferError::InsufficientFunds(
+                1,
+                _,
+                _,
+                _
+            )))
+        ));
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(100)
+        );
+        assert!(engine.get_client(2).unwrap().is_none());
+
+        // Unknown senders have no funds and are not created
+        let result = engine.apply(transfer(3, 1, 3, 10));
+        assert!(matches!(
+            result,
+            Err(EngineError::Transfer(TransferError::InsufficientFunds(
+                3,
+                _,
+                _,
+                _
+            )))
+        ));
+        assert!(engine.get_client(3).unwrap().is_none());
     }
 
     #[test]
-    fn withdrawal_exact_amount_succeeds() {
+    fn transfer_frozen_accounts_fail() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(withdrawal(1, 2, 100)).unwrap();
+        engine.apply(deposit(2, 2, 100)).unwrap();
+        engine
+            .clients
+            .accounts
+            .get_mut(&2)
+            .unwrap()
+            .freeze(FreezeReason::Admin);
+
+        let result = engine.apply(transfer(2, 1, 3, 10));
+        assert!(matches!(
+            result,
+            Err(EngineError::Transfer(TransferError::SenderFrozen(2)))
+        ));
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(0));
+        let result = engine.apply(transfer(1, 2, 4, 10));
+        assert!(matches!(
+            result,
+            Err(EngineError::Transfer(TransferError::ReceiverFrozen(2)))
+        ));
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(100)
+        );
     }
 
     #[test]
-    fn withdrawal_insufficient_funds_fails() {
+    fn transfer_receiver_overflow_leaves_sender_unchanged() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(2, 2, i64::MAX)).unwrap();
 
-        let result = engine.apply(withdrawal(1, 2, 101));
+        let result = engine.apply(transfer(1, 2, 3, 10));
         assert!(matches!(
             result,
-            Err(EngineError::Withdrawal(WithdrawalError::InsufficientFunds(
-                1,
-                _,
+            Err(EngineError::Overflow(BalanceOverflow(2)))
+        ));
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(100)
+        );
+
+        // Failed transfer ID is not recorded
+        engine.apply(transfer(1, 3, 3, 10)).unwrap();
+    }
+
+    #[test]
+    fn transfer_validation() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        assert!(matches!(
+            engine.apply(transfer(1, 1, 2, 10)),
+            Err(EngineError::Transfer(TransferError::SameClient(1)))
+        ));
+        assert!(matches!(
+            engine.apply(transfer(1, 2, 2, 0)),
+            Err(EngineError::Transfer(TransferError::NonPositiveAmount(
+                2,
                 _
             )))
         ));
+        assert!(matches!(
+            engine.apply(transfer(1, 2, 1, 10)),
+            Err(EngineError::Transfer(TransferError::DuplicateTxId(1)))
+        ));
 
-        // Balance unchanged
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(100));
+        engine.apply(transfer(1, 2, 2, 10)).unwrap();
+        assert!(matches!(
+            engine.apply(deposit(1, 2, 10)),
+            Err(EngineError::Deposit(DepositError::DuplicateTxId(2)))
+        ));
+    }
+
+    // Admin tests
+
+    fn adjustment(client: ClientId, tx: TxId, amount: i64) -> Transaction {
+        admin(
+            client,
+            tx,
+            AdminAction::Adjustment {
+                asset: USD,
+                amount: Amount::from_scaled(amount),
+                reason: "FEE_REFUND".to_string(),
+            },
+        )
     }
 
     #[test]
-    fn withdrawal_from_frozen_account_fails() {
+    fn admin_unlock_after_chargeback() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.clients.get_mut(&1).unwrap().freeze();
+        engine.apply(deposit(1, 2, 50)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
 
-        let result = engine.apply(withdrawal(1, 2, 50));
+        engine.apply(admin(1, 3, AdminAction::Unlock)).unwrap();
+        assert!(!engine.get_client(1).unwrap().unwrap().is_frozen());
+        engine.apply(deposit(1, 4, 10)).unwrap();
+
+        let result = engine.apply(admin(1, 5, AdminAction::Unlock));
         assert!(matches!(
             result,
-            Err(EngineError::Withdrawal(WithdrawalError::AccountFrozen(1)))
+            Err(EngineError::Admin(_, AdminError::NotLocked(1)))
         ));
+    }
 
-        // Balance unchanged
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(100));
+    #[test]
+    fn admin_lock_freezes_account() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+
+        engine.apply(admin(1, 2, AdminAction::Lock)).unwrap();
+        assert!(engine.get_client(1).unwrap().unwrap().is_frozen());
+        assert!(matches!(
+            engine.apply(withdrawal(1, 3, 10)),
+            Err(EngineError::Withdrawal(WithdrawalError::AccountFrozen(1)))
+        ));
+        assert!(matches!(
+            engine.apply(admin(1, 4, AdminAction::Lock)),
+            Err(EngineError::Admin(_, AdminError::AlreadyLocked(1)))
+        ));
     }
 
     #[test]
-    fn withdrawal_from_nonexistent_account_fails() {
+    fn admin_adjustment_credits_and_debits() {
         let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(admin(1, 2, AdminAction::Lock)).unwrap();
+
+        // Applies to frozen accounts
+        engine.apply(adjustment(1, 3, 25)).unwrap();
+        engine.apply(adjustment(1, 4, -50)).unwrap();
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(75)
+        );
 
-        // equivalent to zero account
-        let result = engine.apply(withdrawal(1, 1, 50));
         assert!(matches!(
-            result,
-            Err(EngineError::Withdrawal(WithdrawalError::InsufficientFunds(
-                1,
+            engine.apply(adjustment(1, 5, -100)),
+            Err(EngineError::Admin(
                 _,
-                _
-            )))
+                AdminError::InsufficientFunds(1, _, _, _)
+            ))
+        ));
+        assert!(matches!(
+            engine.apply(adjustment(1, 5, 0)),
+            Err(EngineError::Admin(_, AdminError::ZeroAmount(5)))
+        ));
+        assert!(matches!(
+            engine.apply(adjustment(1, 3, 10)),
+            Err(EngineError::Admin(_, AdminError::DuplicateTxId(3)))
+        ));
+        assert!(matches!(
+            engine.apply(adjustment(2, 5, 10)),
+            Err(EngineError::Admin(_, AdminError::ClientNotFound(2)))
         ));
     }
 
-    // Multiple Clients
+    #[test]
+    fn admin_rejections_carry_the_operator() {
+        let mut engine = Engine::new();
+
+        let error = engine.apply(admin(1, 1, AdminAction::Lock)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "admin transaction by operator ops-1 failed: client 1 not found"
+        );
+    }
+
+    // Partial dispute tests
 
     #[test]
-    fn multiple_clients_are_independent() {
+    fn partial_disputes_hold_only_disputed_part() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(deposit(2, 2, 200)).unwrap();
-        engine.apply(withdrawal(1, 3, 30)).unwrap();
+        engine.apply(dispute_part(1, 1, 30)).unwrap();
+        engine.apply(dispute_part(1, 1, 20)).unwrap();
 
-        let client1 = engine.get_client(1).unwrap();
-        let client2 = engine.get_client(2).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(50));
+        assert_eq!(client.held(USD), Amount::from_scaled(50));
+        assert_eq!(engine.txs.deposits[&1].disputed, Amount::from_scaled(50));
+        assert_eq!(engine.txs.deposits[&1].state, DepositState::Disputed);
 
-        assert_eq!(client1.available(), Amount::from_scaled(70));
-        assert_eq!(client2.available(), Amount::from_scaled(200));
+        // A full dispute covers the undisputed remainder
+        engine.apply(dispute(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(USD), Amount::from_scaled(100));
     }
 
-    // clients() iterator
+    #[test]
+    fn partial_amounts_use_the_deposit_precision() {
+        let btc = Asset::new("BTC").unwrap();
+        let sats = Precision::new(8, crate::amount::RoundingMode::Exact).unwrap();
+        let mut engine = Engine::with_config(EngineConfig {
+            asset_precision: HashMap::from([(btc, sats)]),
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(Transaction::Deposit {
+                client: 1,
+                tx: 1,
+                asset: btc,
+                amount: Amount::from_scaled_at(100_000_000, sats),
+                timestamp: None,
+                metadata: Metadata::default(),
+            })
+            .unwrap();
+
+        let dispute = |amount: &str| Transaction::Dispute {
+            client: 1,
+            tx: 1,
+            amount: Some(amount.parse().unwrap()),
+            timestamp: None,
+            metadata: Metadata::default(),
+        };
+        engine.apply(dispute("0.5")).unwrap();
+        // More decimal places than the default precision
+        engine.apply(dispute("0.00000001")).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(btc), Amount::from_scaled_at(50_000_001, sats));
+
+        assert!(matches!(
+            engine.apply(dispute("0.000000001")),
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InvalidAmount(
+                    DepositOperation::Dispute,
+                    1,
+                    crate::ParseAmountError::TooManyDecimals(_, 8)
+                )
+            ))
+        ));
+    }
 
     #[test]
-    fn clients_iterator_returns_all_clients() {
+    fn partial_dispute_exceeding_remainder_fails() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(deposit(2, 2, 200)).unwrap();
+        engine.apply(dispute_part(1, 1, 70)).unwrap();
 
-        let clients: Vec<_> = engine.clients().collect();
-        assert_eq!(clients.len(), 2);
+        let result = engine.apply(dispute_part(1, 1, 40));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::AmountExceeded(DepositOperation::Dispute, 1, requested, remaining)
+            )) if requested.amount() == Amount::from_scaled(40) && remaining.amount() == Amount::from_scaled(30)
+        ));
 
-        // Find each client (without any ordering guarantees)
-        let c1 = clients.iter().find(|c| c.id() == 1).unwrap();
-        let c2 = clients.iter().find(|c| c.id() == 2).unwrap();
+        let result = engine.apply(dispute_part(1, 1, 0));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::NonPositiveAmount(DepositOperation::Dispute, 1, _)
+            ))
+        ));
 
-        assert_eq!(c1.available(), Amount::from_scaled(100));
-        assert_eq!(c2.available(), Amount::from_scaled(200));
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(USD), Amount::from_scaled(70));
     }
 
-    //  Async run()
+    #[test]
+    fn partial_resolve_releases_part_of_disputed_portion() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute_part(1, 1, 60)).unwrap();
+        engine.apply(resolve_part(1, 1, 20)).unwrap();
 
-    #[tokio::test]
-    async fn run_processes_all_transactions() {
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(60));
+        assert_eq!(client.held(USD), Amount::from_scaled(40));
+
+        // Cannot resolve more than the disputed portion
+        let result = engine.apply(resolve_part(1, 1, 50));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::AmountExceeded(DepositOperation::Resolve, 1, _, _)
+            ))
+        ));
+
+        // A full resolve releases the rest of the disputed portion
+        engine.apply(resolve(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert_eq!(engine.txs.deposits[&1].state, DepositState::Ok);
+    }
+
+    #[test]
+    fn partial_chargeback_removes_only_disputed_part() {
         let mut engine = Engine::new();
-        let transactions = vec![deposit(1, 1, 100), deposit(2, 2, 200), withdrawal(1, 3, 25)];
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute_part(1, 1, 30)).unwrap();
+        engine.apply(dispute_part(1, 1, 20)).unwrap();
+        engine.apply(chargeback_part(1, 1, 30)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(50));
+        assert_eq!(client.held(USD), Amount::from_scaled(20));
+        assert_eq!(client.total(USD), Amount::from_scaled(70));
+        assert!(client.is_frozen());
+
+        // The record keeps the charged back and disputed portions
+        let record = &engine.txs.deposits[&1];
+        assert_eq!(record.charged_back, Amount::from_scaled(30));
+        assert_eq!(record.disputed, Amount::from_scaled(20));
+        assert_eq!(record.undisputed(), Some(Amount::from_scaled(50)));
+
+        // Charging back the rest of the disputed portion keeps the undisputed part
+        engine.apply(chargeback(1, 1)).unwrap();
+        let record = &engine.txs.deposits[&1];
+        assert_eq!(record.charged_back, Amount::from_scaled(50));
+        assert_eq!(record.undisputed(), Some(Amount::from_scaled(50)));
+        assert_eq!(record.state, DepositState::ChargedBack);
+        assert_eq!(engine.get_client(1).unwrap().unwrap().chargebacks(), 1);
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(50));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+    }
+
+    #[test]
+    fn chargeback_of_whole_deposit_in_parts_retains_record() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute_part(1, 1, 40)).unwrap();
+        engine.apply(dispute_part(1, 1, 60)).unwrap();
+        engine.apply(chargeback_part(1, 1, 40)).unwrap();
+        engine.apply(chargeback_part(1, 1, 60)).unwrap();
+
+        assert_eq!(
+            engine.txs.deposits[&1].charged_back,
+            Amount::from_scaled(100)
+        );
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.total(USD), Amount::from_scaled(0));
+    }
+
+    // Re-presentment tests
+
+    #[test]
+    fn represent_recredits_charged_back_funds() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
+        engine.apply(represent(1, 1)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.total(USD), Amount::from_scaled(100));
+        assert_eq!(client.chargebacks(), 0);
+        // The freeze is kept by default
+        assert!(client.is_frozen());
 
-        engine.run(tokio_stream::iter(transactions)).await;
+        // Back to Ok, the deposit can be disputed again
+        assert_eq!(engine.txs.deposits[&1].state, DepositState::Ok);
+        engine.apply(dispute(1, 1)).unwrap();
+    }
+
+    #[test]
+    fn represent_not_charged_back_fails() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+
+        let result = engine.apply(represent(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InvalidState(DepositOperation::Represent, 1)
+            ))
+        ));
+    }
+
+    #[test]
+    fn represent_unfreezes_once_no_chargeback_remains() {
+        let mut engine = Engine::with_config(EngineConfig {
+            unfreeze_on_represent: true,
+            ..EngineConfig::default()
+        });
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(1, 2, 50)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
+        engine.apply(chargeback_part(1, 2, 20)).unwrap();
+        assert_eq!(engine.get_client(1).unwrap().unwrap().chargebacks(), 2);
+
+        engine.apply(represent(1, 1)).unwrap();
+        assert!(engine.get_client(1).unwrap().unwrap().is_frozen());
+
+        // The second deposit is charged back again, still one chargeback
+        engine.apply(chargeback(1, 2)).unwrap();
+        assert_eq!(engine.get_client(1).unwrap().unwrap().chargebacks(), 1);
+
+        engine.apply(represent(1, 2)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert!(!client.is_frozen());
+        assert_eq!(client.available(USD), Amount::from_scaled(150));
+    }
+
+    #[test]
+    fn represent_keeps_admin_lock() {
+        let config = EngineConfig {
+            unfreeze_on_represent: true,
+            ..EngineConfig::default()
+        };
+
+        // Locked before the chargeback
+        let mut engine = Engine::with_config(config.clone());
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(admin(1, 2, AdminAction::Lock)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
+        engine.apply(represent(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.frozen_by(), Some(FreezeReason::Admin));
+
+        // Locked after the chargeback
+        let mut engine = Engine::with_config(config);
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(chargeback(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.frozen_by(), Some(FreezeReason::Chargeback));
+        engine.apply(admin(1, 2, AdminAction::Lock)).unwrap();
+        engine.apply(represent(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.frozen_by(), Some(FreezeReason::Admin));
+
+        engine.apply(admin(1, 3, AdminAction::Unlock)).unwrap();
+        assert!(!engine.get_client(1).unwrap().unwrap().is_frozen());
+    }
+
+    #[test]
+    fn represent_withdrawal_chargeback_debits_refund() {
+        let mut engine = disputable_withdrawals();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 40)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(chargeback(1, 2)).unwrap();
+        engine.apply(represent(1, 2)).unwrap();
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(60));
+        assert_eq!(client.chargebacks(), 0);
+    }
+
+    // Dispute window tests
+
+    fn with_dispute_window(window: u64) -> Engine {
+        Engine::with_config(EngineConfig {
+            dispute_window: Some(window),
+            ..EngineConfig::default()
+        })
+    }
+
+    #[test]
+    fn dispute_within_window_succeeds() {
+        let mut engine = with_dispute_window(100);
+        engine.apply(at(deposit(1, 1, 100), 1_000)).unwrap();
+        engine.apply(at(dispute(1, 1), 1_100)).unwrap();
+    }
+
+    #[test]
+    fn dispute_outside_window_fails() {
+        let mut engine = with_dispute_window(100);
+        engine.apply(at(deposit(1, 1, 100), 1_000)).unwrap();
+
+        let result = engine.apply(at(dispute(1, 1), 1_101));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::WindowExpired(DepositOperation::Dispute, 1)
+            ))
+        ));
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+    }
+
+    #[test]
+    fn late_dispute_with_old_timestamp_fails() {
+        let mut engine = with_dispute_window(100);
+        engine.apply(at(deposit(1, 1, 100), 1_000)).unwrap();
+        engine.apply(at(deposit(1, 2, 100), 1_200)).unwrap();
+
+        // Within the window of its own timestamp, but the window closed
+        let result = engine.apply(at(dispute(1, 1), 1_050));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::WindowExpired(DepositOperation::Dispute, 1)
+            ))
+        ));
+        assert_eq!(engine.clock, 1_200);
+    }
 
-        let client1 = engine.get_client(1).unwrap();
-        let client2 = engine.get_client(2).unwrap();
+    #[test]
+    fn rejected_transactions_do_not_advance_clock() {
+        let mut engine = with_dispute_window(100);
+        engine.apply(at(deposit(1, 1, 100), 1_000)).unwrap();
+
+        // A rejected row from the far future leaves the window open
+        assert!(engine.apply(at(withdrawal(1, 2, 500), u64::MAX)).is_err());
+        assert!(engine.apply(at(dispute(1, 3), u64::MAX)).is_err());
+        assert_eq!(engine.clock, 1_000);
+        assert!(!engine.last_events().contains(&Event::ClockAdvanced {
+            timestamp: u64::MAX
+        }));
+        engine.apply(at(dispute(1, 1), 1_050)).unwrap();
+    }
 
-        assert_eq!(client1.available(), Amount::from_scaled(75));
-        assert_eq!(client2.available(), Amount::from_scaled(200));
+    #[test]
+    fn untimed_transactions_happen_at_latest_time() {
+        let mut engine = with_dispute_window(100);
+        // Untimed deposit recorded at the latest time seen (1_000)
+        engine.apply(at(deposit(1, 1, 100), 1_000)).unwrap();
+        engine.apply(deposit(1, 2, 100)).unwrap();
+        assert_eq!(engine.txs.deposits[&2].timestamp, 1_000);
+
+        // Clock moves forward, untimed dispute is outside the window
+        engine.apply(at(deposit(1, 3, 100), 1_200)).unwrap();
+        assert!(matches!(
+            engine.apply(dispute(1, 2)),
+            Err(EngineError::DepositOperation(
+                DepositOperationError::WindowExpired(DepositOperation::Dispute, 2)
+            ))
+        ));
+        engine.apply(dispute(1, 3)).unwrap();
     }
 
-    #[tokio::test]
-    async fn run_skips_failed_transactions_and_continues() {
-        let mut engine = Engine::new();
-        let transactions = vec![
-            deposit(1, 1, 100),
-            withdrawal(1, 2, 200), // Should fail with insufficient funds
-            deposit(1, 3, 50),     // Should still process
-        ];
+    #[test]
+    fn evict_expired_keeps_disputed_and_recent_records() {
+        let mut engine = with_dispute_window(100);
+        engine.apply(at(deposit(1, 1, 100), 1_000)).unwrap();
+        engine.apply(at(deposit(1, 2, 100), 1_000)).unwrap();
+        engine.apply(at(dispute(1, 2), 1_050)).unwrap();
+        engine.apply(at(deposit(1, 3, 100), 1_150)).unwrap();
+
+        assert_eq!(engine.evict_expired().unwrap(), 1);
+        assert!(!engine.txs.deposits.contains_key(&1));
+        assert!(engine.txs.deposits.contains_key(&2));
+        assert!(engine.txs.deposits.contains_key(&3));
+
+        // Evicted IDs are still unique, and known to be outside the window
+        assert!(matches!(
+            engine.apply(deposit(1, 1, 100)),
+            Err(EngineError::Deposit(DepositError::DuplicateTxId(1)))
+        ));
+        assert!(matches!(
+            engine.apply(dispute(1, 1)),
+            Err(EngineError::DepositOperation(
+                DepositOperationError::WindowExpired(DepositOperation::Dispute, 1)
+            ))
+        ));
 
-        engine.run(tokio_stream::iter(transactions)).await;
+        // Open disputes can still be settled
+        engine.apply(resolve(1, 2)).unwrap();
+    }
 
-        let client = engine.get_client(1).unwrap();
+    #[test]
+    fn evict_expired_without_window_keeps_everything() {
+        let mut engine = Engine::new();
+        engine.apply(at(deposit(1, 1, 100), 0)).unwrap();
+        engine.apply(at(deposit(1, 2, 100), u64::MAX)).unwrap();
 
-        assert_eq!(client.available(), Amount::from_scaled(150)); // 100 + 50 with withdrawal skipped
+        assert_eq!(engine.evict_expired().unwrap(), 0);
+        engine.apply(dispute(1, 1)).unwrap();
     }
 
-    // Dispute, Resolve, Chargeback - test utils
+    // Withdrawal dispute tests
 
-    fn dispute(client: ClientId, tx: TxId) -> Transaction {
-        Transaction::Dispute { client, tx }
+    fn disputable_withdrawals() -> Engine {
+        Engine::with_config(EngineConfig {
+            dispute_policy: DisputePolicy::DepositsAndWithdrawals,
+            ..EngineConfig::default()
+        })
     }
 
-    fn resolve(client: ClientId, tx: TxId) -> Transaction {
-        Transaction::Resolve { client, tx }
-    }
+    #[test]
+    fn dispute_withdrawal_holds_withdrawn_funds() {
+        let mut engine = disputable_withdrawals();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 40)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
 
-    fn chargeback(client: ClientId, tx: TxId) -> Transaction {
-        Transaction::Chargeback { client, tx }
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(60));
+        assert_eq!(client.held(USD), Amount::from_scaled(40));
+        assert_eq!(client.total(USD), Amount::from_scaled(100));
     }
 
-    // Dispute tests
-
     #[test]
-    fn dispute_deposit_moves_funds_to_held() {
-        let mut engine = Engine::new();
+    fn resolve_withdrawal_dispute_keeps_withdrawal() {
+        let mut engine = disputable_withdrawals();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(withdrawal(1, 2, 40)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(resolve(1, 2)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(0));
-        assert_eq!(client.held(), Amount::from_scaled(100));
-        assert_eq!(client.total(), Amount::from_scaled(100));
-        assert!(!client.is_frozen());
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(60));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+
+        // Can be disputed again
+        engine.apply(dispute(1, 2)).unwrap();
     }
 
     #[test]
-    fn dispute_withdrawal_fails() {
-        let mut engine = Engine::new();
+    fn chargeback_withdrawal_refunds_client() {
+        let mut engine = disputable_withdrawals();
         engine.apply(deposit(1, 1, 100)).unwrap();
         engine.apply(withdrawal(1, 2, 40)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(chargeback(1, 2)).unwrap();
 
-        // Withdrawals can't be disputed - they're not in the deposits map
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(100));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert!(!client.is_frozen());
+
+        // Retained with nothing left to dispute
         let result = engine.apply(dispute(1, 2));
         assert!(matches!(
             result,
             Err(EngineError::DepositOperation(
-                DepositOperationError::TxNotFound(DepositOperation::Dispute, 2)
+                DepositOperationError::InvalidState(DepositOperation::Dispute, 2)
             ))
         ));
-
-        // Balance unchanged
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(60));
-        assert_eq!(client.held(), Amount::from_scaled(0));
     }
 
     #[test]
-    fn dispute_nonexistent_tx_fails() {
-        let mut engine = Engine::new();
+    fn dispute_withdrawal_wrong_client_fails() {
+        let mut engine = disputable_withdrawals();
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 40)).unwrap();
 
-        let result = engine.apply(dispute(1, 999));
+        let result = engine.apply(dispute(2, 2));
         assert!(matches!(
             result,
             Err(EngineError::DepositOperation(
-                DepositOperationError::TxNotFound(DepositOperation::Dispute, 999)
+                DepositOperationError::ClientMismatch(DepositOperation::Dispute, 2, 1, 2)
             ))
         ));
     }
 
     #[test]
-    fn dispute_wrong_client_fails() {
-        let mut engine = Engine::new();
+    fn disputable_withdrawal_ids_are_unique() {
+        let mut engine = disputable_withdrawals();
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(deposit(2, 2, 50)).unwrap();
+        engine.apply(withdrawal(1, 2, 40)).unwrap();
 
-        let result = engine.apply(dispute(2, 1)); // tx 1 belongs to client 1
+        let result = engine.apply(deposit(1, 2, 10));
         assert!(matches!(
             result,
-            Err(EngineError::DepositOperation(
-                DepositOperationError::ClientMismatch(DepositOperation::Dispute, 1, 1, 2)
-            ))
+            Err(EngineError::Deposit(DepositError::DuplicateTxId(2)))
         ));
     }
 
+    // Policy tests
+
+    fn with_negative_available(policy: NegativeAvailablePolicy) -> Engine {
+        Engine::with_config(EngineConfig {
+            negative_available: policy,
+            ..EngineConfig::default()
+        })
+    }
+
     #[test]
-    fn dispute_already_disputed_fails() {
-        let mut engine = Engine::new();
+    fn dispute_capped_to_available_funds() {
+        let mut engine = with_negative_available(NegativeAvailablePolicy::Cap);
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 60)).unwrap();
         engine.apply(dispute(1, 1)).unwrap();
 
+        // Only the 40 available are held, and disputed
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::default());
+        assert_eq!(client.held(USD), Amount::from_scaled(40));
+        assert_eq!(engine.txs.deposits[&1].disputed, Amount::from_scaled(40));
+
+        // Nothing left to hold
         let result = engine.apply(dispute(1, 1));
         assert!(matches!(
             result,
             Err(EngineError::DepositOperation(
-                DepositOperationError::InvalidState(DepositOperation::Dispute, 1)
+                DepositOperationError::InsufficientFunds(DepositOperation::Dispute, 1, _, _)
             ))
         ));
+
+        engine.apply(chargeback(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(USD), Amount::default());
+        assert_eq!(
+            engine.txs.deposits[&1].charged_back,
+            Amount::from_scaled(40)
+        );
     }
 
     #[test]
-    fn dispute_deposit_insufficient_funds_causes_negative_balance() {
-        let mut engine = Engine::new();
+    fn dispute_rejected_on_insufficient_funds() {
+        let mut engine = with_negative_available(NegativeAvailablePolicy::Reject);
         engine.apply(deposit(1, 1, 100)).unwrap();
-        engine.apply(withdrawal(1, 2, 60)).unwrap(); // leaves 40 available
-
-        // Dispute succeeds even with insufficient funds (logs warning)
-        engine.apply(dispute(1, 1)).unwrap(); // needs 100, has 40
+        engine.apply(withdrawal(1, 2, 60)).unwrap();
 
-        // Available is now negative (-60), held is 100
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(-60));
-        assert_eq!(client.held(), Amount::from_scaled(100));
-        assert_eq!(client.total(), Amount::from_scaled(40)); // total unchanged
+        let result = engine.apply(dispute(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::InsufficientFunds(DepositOperation::Dispute, 1, available, requested)
+            )) if available.amount() == Amount::from_scaled(40) && requested.amount() == Amount::from_scaled(100)
+        ));
+        assert_eq!(engine.txs.deposits[&1].state, DepositState::Ok);
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().available(USD),
+            Amount::from_scaled(40)
+        );
+
+        // Covered disputes are unaffected
+        engine.apply(dispute_part(1, 1, 40)).unwrap();
     }
 
-    // Resolve tests
-
     #[test]
-    fn resolve_deposit_returns_funds_to_available() {
-        let mut engine = Engine::new();
+    fn redispute_limit() {
+        let mut engine = Engine::with_config(EngineConfig {
+            max_redisputes: Some(1),
+            ..EngineConfig::default()
+        });
         engine.apply(deposit(1, 1, 100)).unwrap();
         engine.apply(dispute(1, 1)).unwrap();
         engine.apply(resolve(1, 1)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(resolve(1, 1)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(100));
-        assert_eq!(client.held(), Amount::from_scaled(0));
-        assert!(!client.is_frozen());
+        let result = engine.apply(dispute(1, 1));
+        assert!(matches!(
+            result,
+            Err(EngineError::DepositOperation(
+                DepositOperationError::DisputeLimitReached(DepositOperation::Dispute, 1, 2)
+            ))
+        ));
+        assert_eq!(engine.txs.deposits[&1].state, DepositState::Ok);
     }
 
     #[test]
-    fn resolve_not_disputed_fails() {
-        let mut engine = Engine::new();
+    fn redispute_limit_ignores_concurrent_partial_disputes() {
+        let mut engine = Engine::with_config(EngineConfig {
+            max_redisputes: Some(0),
+            ..EngineConfig::default()
+        });
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute_part(1, 1, 30)).unwrap();
+        engine.apply(dispute_part(1, 1, 30)).unwrap();
 
-        let result = engine.apply(resolve(1, 1));
+        // Still open after a partial resolve
+        engine.apply(resolve_part(1, 1, 30)).unwrap();
+        engine.apply(dispute_part(1, 1, 30)).unwrap();
+        engine.apply(resolve(1, 1)).unwrap();
         assert!(matches!(
-            result,
+            engine.apply(dispute_part(1, 1, 30)),
             Err(EngineError::DepositOperation(
-                DepositOperationError::InvalidState(DepositOperation::Resolve, 1)
+                DepositOperationError::DisputeLimitReached(DepositOperation::Dispute, 1, 1)
+            ))
+        ));
+
+        // Rejected resolves are not counted
+        engine.apply(deposit(1, 2, 100)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        assert!(engine.apply(resolve(2, 2)).is_err());
+        engine.apply(resolve(1, 2)).unwrap();
+        assert!(matches!(
+            engine.apply(dispute(1, 2)),
+            Err(EngineError::DepositOperation(
+                DepositOperationError::DisputeLimitReached(DepositOperation::Dispute, 2, 1)
             ))
         ));
     }
 
     #[test]
-    fn resolved_tx_can_be_disputed_again() {
-        let mut engine = Engine::new();
+    fn frozen_account_rejects_dispute_operations() {
+        let mut engine = Engine::with_config(EngineConfig {
+            allow_frozen_disputes: false,
+            ..EngineConfig::default()
+        });
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(1, 2, 50)).unwrap();
+        engine.apply(deposit(1, 3, 50)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
         engine.apply(dispute(1, 1)).unwrap();
-        engine.apply(resolve(1, 1)).unwrap();
-        engine.apply(dispute(1, 1)).unwrap(); // should succeed
+        engine.apply(chargeback(1, 1)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(0));
-        assert_eq!(client.held(), Amount::from_scaled(100));
-    }
+        for tx in [dispute(1, 3), resolve(1, 2), chargeback(1, 2)] {
+            assert!(matches!(
+                engine.apply(tx),
+                Err(EngineError::DepositOperation(
+                    DepositOperationError::AccountFrozen(_, 1)
+                ))
+            ));
+        }
 
-    // Chargeback tests
+        // Re-presentment reverses the chargeback that froze the account
+        engine.apply(represent(1, 1)).unwrap();
+    }
 
     #[test]
-    fn chargeback_deposit_removes_held_and_freezes() {
+    fn frozen_account_accepts_dispute_operations_by_default() {
         let mut engine = Engine::new();
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(1, 2, 50)).unwrap();
         engine.apply(dispute(1, 1)).unwrap();
         engine.apply(chargeback(1, 1)).unwrap();
 
-        let client = engine.get_client(1).unwrap();
-        assert_eq!(client.available(), Amount::from_scaled(0));
-        assert_eq!(client.held(), Amount::from_scaled(0));
-        assert_eq!(client.total(), Amount::from_scaled(0));
-        assert!(client.is_frozen());
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(resolve(1, 2)).unwrap();
     }
 
     #[test]
-    fn chargeback_not_disputed_fails() {
+    fn withdrawal_without_account_creation() {
+        let mut engine = Engine::with_config(EngineConfig {
+            create_accounts_on_withdrawal: false,
+            ..EngineConfig::default()
+        });
+
+        let result = engine.apply(withdrawal(1, 1, 10));
+        assert!(matches!(
+            result,
+            Err(EngineError::Withdrawal(WithdrawalError::ClientNotFound(1)))
+        ));
+        assert!(engine.get_client(1).unwrap().is_none());
+
+        // Known clients are unaffected
+        engine.apply(deposit(1, 2, 100)).unwrap();
+        engine.apply(withdrawal(1, 3, 10)).unwrap();
+    }
+
+    #[test]
+    fn withdrawal_creates_account_by_default() {
         let mut engine = Engine::new();
+        assert!(engine.apply(withdrawal(1, 1, 10)).is_err());
+        assert_eq!(
+            engine.get_client(1).unwrap().unwrap().total(USD),
+            Amount::default()
+        );
+    }
+
+    // Storage tests
+
+    /// Account store failing every lookup once `fail` is set, or only reads (or pair
+    /// lookups) once `fail_reads` (or `fail_pairs`) is.
+    #[derive(Default)]
+    struct FailingAccountStore {
+        inner: MemoryAccountStore,
+        fail: bool,
+        fail_reads: bool,
+        fail_pairs: bool,
+    }
+
+    impl FailingAccountStore {
+        fn check(&self) -> Result<(), StoreError> {
+            if self.fail {
+                return Err(StoreError::new("accounts unavailable"));
+            }
+            Ok(())
+        }
+    }
+
+    impl AccountStore for FailingAccountStore {
+        fn get(&self, client: ClientId) -> Result<Option<&ClientAccount>, StoreError> {
+            self.check()?;
+            if self.fail_reads {
+                return Err(StoreError::new("accounts unreadable"));
+            }
+            self.inner.get(client)
+        }
+
+        fn get_mut(&mut self, client: ClientId) -> Result<Option<&mut ClientAccount>, StoreError> {
+            self.check()?;
+            self.inner.get_mut(client)
+        }
+
+        fn get_or_create(&mut self, client: ClientId) -> Result<&mut ClientAccount, StoreError> {
+            self.check()?;
+            self.inner.get_or_create(client)
+        }
+
+        fn get_pair_mut(
+            &mut self,
+            first: ClientId,
+            second: ClientId,
+        ) -> Result<[Option<&mut ClientAccount>; 2], StoreError> {
+            self.check()?;
+            if self.fail_pairs {
+                return Err(StoreError::new("accounts unavailable"));
+            }
+            self.inner.get_pair_mut(first, second)
+        }
+
+        fn remove(&mut self, client: ClientId) -> Result<(), StoreError> {
+            self.check()?;
+            self.inner.remove(client)
+        }
+
+        fn iter(&self) -> impl Iterator<Item = &ClientAccount> + '_ {
+            self.inner.iter()
+        }
+    }
+
+    #[test]
+    fn authorizations_of_missing_accounts_are_rejected() {
+        let mut engine = Engine::with_stores(
+            EngineConfig::default(),
+            FailingAccountStore::default(),
+            MemoryTxStore::new(),
+        );
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(authorize(1, 2, 30)).unwrap();
+        engine.apply(authorize(1, 3, 30)).unwrap();
+        engine.clients.inner.remove(1).unwrap();
 
-        let result = engine.apply(chargeback(1, 1));
         assert!(matches!(
-            result,
-            Err(EngineError::DepositOperation(
-                DepositOperationError::InvalidState(DepositOperation::Chargeback, 1)
+            engine.apply(capture(1, 2)),
+            Err(EngineError::AuthorizationOperation(
+                AuthorizationOperationError::ClientNotFound(AuthorizationOperation::Capture, 1)
+            ))
+        ));
+        assert!(matches!(
+            engine.apply(void(1, 3)),
+            Err(EngineError::AuthorizationOperation(
+                AuthorizationOperationError::ClientNotFound(AuthorizationOperation::Void, 1)
             ))
         ));
     }
 
+    /// Transaction store failing every insert once `fail` is set.
+    #[derive(Default)]
+    struct FailingTxStore {
+        inner: MemoryTxStore,
+        fail: bool,
+    }
+
+    impl FailingTxStore {
+        fn check(&self) -> Result<(), StoreError> {
+            if self.fail {
+                return Err(StoreError::new("disk full"));
+            }
+            Ok(())
+        }
+    }
+
+    impl TxStore for FailingTxStore {
+        fn contains(&self, tx: TxId) -> Result<bool, StoreError> {
+            self.inner.contains(tx)
+        }
+
+        fn insert_id(&mut self, tx: TxId) -> Result<(), StoreError> {
+            self.check()?;
+            self.inner.insert_id(tx)
+        }
+
+        fn insert_record(
+            &mut self,
+            tx: TxId,
+            kind: RecordKind,
+            record: DepositRecord,
+        ) -> Result<(), StoreError> {
+            self.check()?;
+            self.inner.insert_record(tx, kind, record)
+        }
+
+        fn record_mut(
+            &mut self,
+            tx: TxId,
+        ) -> Result<Option<(RecordKind, &mut DepositRecord)>, StoreError> {
+            self.inner.record_mut(tx)
+        }
+
+        fn insert_authorization(
+            &mut self,
+            tx: TxId,
+            record: AuthorizationRecord,
+        ) -> Result<(), StoreError> {
+            self.check()?;
+            self.inner.insert_authorization(tx, record)
+        }
+
+        fn authorization_mut(
+            &mut self,
+            tx: TxId,
+        ) -> Result<Option<&mut AuthorizationRecord>, StoreError> {
+            self.inner.authorization_mut(tx)
+        }
+
+        fn remove(&mut self, tx: TxId) -> Result<(), StoreError> {
+            self.check()?;
+            self.inner.remove(tx)
+        }
+
+        fn is_evicted(&self, tx: TxId) -> Result<bool, StoreError> {
+            self.inner.is_evicted(tx)
+        }
+
+        fn evict(
+            &mut self,
+            evict: &mut dyn FnMut(&DepositRecord) -> bool,
+        ) -> Result<Vec<TxId>, StoreError> {
+            self.check()?;
+            self.inner.evict(evict)
+        }
+    }
+
     #[test]
-    fn chargedback_tx_cannot_be_disputed() {
-        let mut engine = Engine::new();
+    fn failing_tx_store_leaves_state_unchanged() {
+        let mut engine = Engine::with_stores(
+            EngineConfig::default(),
+            MemoryAccountStore::new(),
+            FailingTxStore::default(),
+        );
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(deposit(2, 2, 100)).unwrap();
+        engine.txs.fail = true;
+
+        for tx in [
+            deposit(1, 3, 50),
+            deposit_asset(1, 4, EUR, 50),
+            withdrawal(1, 5, 50),
+            authorize(1, 6, 50),
+            transfer(1, 2, 7, 50),
+            admin(1, 8, AdminAction::Lock),
+            adjustment(1, 9, -50),
+        ] {
+            assert!(matches!(engine.apply(tx), Err(EngineError::Storage(_))));
+        }
+
+        for id in [1, 2] {
+            let client = engine.get_client(id).unwrap().unwrap();
+            assert_eq!(client.available(USD), Amount::from_scaled(100));
+            assert_eq!(client.reserved(USD), Amount::default());
+            assert!(!client.is_frozen());
+            // No balance left behind for the new asset
+            assert_eq!(client.balances().count(), 1);
+        }
+
+        // Accounts opened by the failed transactions are removed, with their event
+        for tx in [deposit(3, 10, 50), transfer(1, 4, 11, 50)] {
+            assert!(matches!(engine.apply(tx), Err(EngineError::Storage(_))));
+            assert!(engine.last_events().is_empty());
+        }
+        assert!(engine.get_client(3).unwrap().is_none());
+        assert!(engine.get_client(4).unwrap().is_none());
+
+        // Nothing was stored, the IDs can be used once the store recovers
+        engine.txs.fail = false;
+        engine.apply(deposit(1, 3, 50)).unwrap();
+        engine.apply(transfer(1, 2, 7, 50)).unwrap();
+    }
+
+    #[test]
+    fn failing_account_store_rejects_transactions() {
+        let mut engine = Engine::with_stores(
+            EngineConfig::default(),
+            FailingAccountStore::default(),
+            MemoryTxStore::new(),
+        );
         engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.clients.fail = true;
+
+        for tx in [deposit(1, 2, 50), dispute(1, 1), transfer(1, 2, 3, 50)] {
+            let result = engine.apply(tx);
+            assert!(
+                matches!(&result, Err(EngineError::Storage(e)) if e.to_string() == "storage failure: accounts unavailable")
+            );
+        }
+        assert!(engine.get_client(1).is_err());
+
+        engine.clients.fail = false;
+        engine.apply(deposit(1, 2, 50)).unwrap();
         engine.apply(dispute(1, 1)).unwrap();
-        engine.apply(chargeback(1, 1)).unwrap();
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(50));
+        assert_eq!(client.held(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn failing_transfer_removes_the_opened_receiver() {
+        let mut engine = Engine::with_stores(
+            EngineConfig::default(),
+            FailingAccountStore::default(),
+            MemoryTxStore::new(),
+        );
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.clients.fail_pairs = true;
 
-        // Chargedback transactions are evicted, so they appear as "not found"
-        let result = engine.apply(dispute(1, 1));
         assert!(matches!(
-            result,
-            Err(EngineError::DepositOperation(
-                DepositOperationError::TxNotFound(DepositOperation::Dispute, 1)
-            ))
+            engine.apply(transfer(1, 2, 2, 50)),
+            Err(EngineError::Storage(_))
+        ));
+        assert!(engine.get_client(2).unwrap().is_none());
+        assert!(engine.last_events().is_empty());
+    }
+
+    #[test]
+    fn failing_outcome_read_keeps_the_transaction_applied() {
+        let mut engine = Engine::with_stores(
+            EngineConfig::default(),
+            FailingAccountStore::default(),
+            MemoryTxStore::new(),
+        );
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.clients.fail_reads = true;
+
+        // Applied, without the balance it can't read back
+        let outcome = engine.apply(dispute(1, 1)).unwrap().account;
+        assert_eq!(outcome.held_delta, Amount::from_scaled(100));
+        assert_eq!(outcome.balance, None);
+
+        engine.clients.fail_reads = false;
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.held(USD), Amount::from_scaled(100));
+    }
+
+    #[test]
+    fn disk_tx_store_disputes_cold_deposits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let txs = DiskTxStore::create(dir.path(), 2).unwrap();
+        let mut engine =
+            Engine::with_stores(EngineConfig::default(), MemoryAccountStore::new(), txs);
+        for tx in 1..=10 {
+            engine.apply(deposit(1, tx, 10)).unwrap();
+        }
+        assert_eq!(engine.txs.cached(), 2);
+
+        // Deposits evicted from the cache are still unique and disputable
+        assert!(matches!(
+            engine.apply(deposit(1, 1, 10)),
+            Err(EngineError::Deposit(DepositError::DuplicateTxId(1)))
         ));
+        engine.apply(dispute(1, 1)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(resolve(1, 2)).unwrap();
+        for tx in 3..=10 {
+            engine.apply(deposit(2, 10 + tx, 10)).unwrap();
+        }
+        engine.apply(chargeback(1, 1)).unwrap();
+        assert!(engine.apply(resolve(1, 2)).is_err());
+
+        let client = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client.available(USD), Amount::from_scaled(90));
+        assert_eq!(client.held(USD), Amount::from_scaled(0));
+        assert!(client.is_frozen());
     }
 
     // Duplicate transaction ID tests
@@ -801,4 +3770,79 @@ mod tests {
             Err(EngineError::Withdrawal(WithdrawalError::DuplicateTxId(2)))
         ));
     }
+
+    // Metadata tests
+
+    fn retaining_metadata() -> Engine {
+        Engine::with_config(EngineConfig {
+            retain_deposit_metadata: true,
+            ..EngineConfig::default()
+        })
+    }
+
+    #[test]
+    fn deposit_metadata_not_retained_by_default() {
+        let mut engine = Engine::new();
+        engine
+            .apply(with_metadata(deposit(1, 1, 100), &[("merchant", "acme")]))
+            .unwrap();
+        assert_eq!(engine.deposit_metadata(1), None);
+    }
+
+    #[test]
+    fn deposit_metadata_retained() {
+        let mut engine = retaining_metadata();
+        engine
+            .apply(with_metadata(deposit(1, 1, 100), &[("merchant", "acme")]))
+            .unwrap();
+        engine.apply(deposit(1, 2, 100)).unwrap();
+        // Rejected deposits keep nothing
+        let _ = engine.apply(with_metadata(deposit(1, 1, 100), &[("merchant", "other")]));
+
+        assert_eq!(engine.deposit_metadata(1).unwrap()["merchant"], "acme");
+        assert_eq!(engine.deposit_metadata(2), None);
+    }
+
+    #[test]
+    fn operation_metadata_merges_deposit_metadata() {
+        let mut engine = retaining_metadata();
+        engine
+            .apply(with_metadata(
+                deposit(1, 1, 100),
+                &[("merchant", "acme"), ("memo", "order")],
+            ))
+            .unwrap();
+
+        let own = Metadata::from([("memo".to_string(), "fraud".to_string())]);
+        let merged = engine.operation_metadata(1, &own);
+        assert_eq!(merged["merchant"], "acme");
+        assert_eq!(merged["memo"], "fraud");
+        assert!(matches!(
+            engine.operation_metadata(2, &own),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(
+            engine.operation_metadata(1, &Metadata::new()),
+            Cow::Borrowed(retained) if retained["merchant"] == "acme"
+        ));
+    }
+
+    #[test]
+    fn evict_expired_removes_deposit_metadata() {
+        let mut engine = Engine::with_config(EngineConfig {
+            dispute_window: Some(100),
+            retain_deposit_metadata: true,
+            ..EngineConfig::default()
+        });
+        engine
+            .apply(at(
+                with_metadata(deposit(1, 1, 100), &[("merchant", "acme")]),
+                1_000,
+            ))
+            .unwrap();
+        engine.apply(at(deposit(1, 2, 100), 1_200)).unwrap();
+
+        assert_eq!(engine.evict_expired().unwrap(), 1);
+        assert_eq!(engine.deposit_metadata(1), None);
+    }
 }
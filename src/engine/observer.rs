@@ -0,0 +1,212 @@
+//! Observers notified of the transactions applied by the engine.
+
+use super::{AccountOutcome, AccountStore, ApplyOutcome, Engine, EngineError, TxStore};
+use crate::model::Transaction;
+
+/// Callbacks notified of every transaction applied or rejected by an [`Engine`], see
+/// [`Engine::add_observer`].
+///
+/// Observers are given the transaction and its outcome or error, not the engine:
+/// they can keep their own state (metrics, queued webhooks, ...) but can't change
+/// the engine state. Every callback does nothing by default.
+pub trait EngineObserver {
+    /// A transaction was applied.
+    fn on_applied(&mut self, _transaction: &Transaction, _outcome: &ApplyOutcome) {}
+
+    /// A transaction was rejected. Its changes were undone, except the ones kept for
+    /// rejected transactions (see [`Event`](super::Event)), or if the store failed
+    /// while undoing them (the error is then a storage error).
+    fn on_rejected(&mut self, _transaction: &Transaction, _error: &EngineError) {}
+
+    /// A transaction froze an account (a chargeback, or an admin lock), after
+    /// [`on_applied`](Self::on_applied). Called for each account it froze.
+    fn on_frozen(&mut self, _transaction: &Transaction, _account: &AccountOutcome) {}
+
+    /// A transaction made the available balance of an account negative (a dispute of
+    /// spent funds), after [`on_applied`](Self::on_applied). Called for each account
+    /// it made negative.
+    fn on_negative_balance(&mut self, _transaction: &Transaction, _account: &AccountOutcome) {}
+}
+
+impl<A: AccountStore, T: TxStore> Engine<A, T> {
+    /// Notify an observer of the transactions applied from now on.
+    ///
+    /// Observers are notified in the order they were added, once the events of the
+    /// transaction are journaled, or failed to be (see [`Engine::apply`]). The
+    /// transactions of a batch are notified once it is applied, or only the rejected
+    /// one if it is rolled back (all of them if appending to the journal failed, see
+    /// [`Engine::apply_batch`]).
+    pub fn add_observer(&mut self, observer: impl EngineObserver + Send + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Notify the observers of an applied transaction.
+    pub(super) fn notify_applied(&mut self, transaction: &Transaction, outcome: &ApplyOutcome) {
+        for observer in &mut self.observers {
+            observer.on_applied(transaction, outcome);
+            for account in outcome.accounts() {
+                if account.became_frozen {
+                    observer.on_frozen(transaction, account);
+                }
+                if account.went_negative {
+                    observer.on_negative_balance(transaction, account);
+                }
+            }
+        }
+    }
+
+    /// Notify the observers of a rejected transaction.
+    pub(super) fn notify_rejected(&mut self, transaction: &Transaction, error: &EngineError) {
+        for observer in &mut self.observers {
+            observer.on_rejected(transaction, error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::Amount;
+    use crate::engine::Journal;
+    use crate::engine::event::FailingWriter;
+    use crate::engine::test_utils::*;
+
+    /// Records the callbacks it gets, readable through a clone.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Recorder {
+        fn record(&self, callback: &str, transaction: &Transaction) {
+            let entry = format!("{callback} {}", transaction.tx());
+            self.0.lock().unwrap().push(entry);
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl EngineObserver for Recorder {
+        fn on_applied(&mut self, transaction: &Transaction, _outcome: &ApplyOutcome) {
+            self.record("applied", transaction);
+        }
+
+        fn on_rejected(&mut self, transaction: &Transaction, _error: &EngineError) {
+            self.record("rejected", transaction);
+        }
+
+        fn on_frozen(&mut self, transaction: &Transaction, account: &AccountOutcome) {
+            self.record(&format!("frozen {} by", account.client), transaction);
+        }
+
+        fn on_negative_balance(&mut self, transaction: &Transaction, account: &AccountOutcome) {
+            assert!(account.balance.unwrap().available() < Amount::default());
+            self.record(&format!("negative {} by", account.client), transaction);
+        }
+    }
+
+    #[test]
+    fn observers_are_notified_of_every_transaction() {
+        let recorder = Recorder::default();
+        let mut engine = Engine::new();
+        engine.add_observer(recorder.clone());
+
+        for tx in [
+            deposit(1, 1, 100),
+            withdrawal(1, 2, 80),
+            withdrawal(1, 3, 50), // Insufficient funds
+            dispute(1, 1),
+            chargeback(1, 1),
+        ] {
+            let _ = engine.apply(tx);
+        }
+
+        assert_eq!(
+            recorder.take(),
+            [
+                "applied 1",
+                "applied 2",
+                "rejected 3",
+                "applied 1",
+                "negative 1 by 1",
+                "applied 1",
+                "frozen 1 by 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_account_of_the_outcome_is_notified() {
+        let recorder = Recorder::default();
+        let mut engine = Engine::new();
+        engine.add_observer(recorder.clone());
+
+        // Transfers don't freeze accounts, but observers don't rely on it
+        let transfer = transfer(1, 2, 3, 10);
+        let account = |client, became_frozen| AccountOutcome {
+            client,
+            asset: None,
+            available_delta: Amount::default(),
+            held_delta: Amount::default(),
+            balance: None,
+            became_frozen,
+            went_negative: false,
+        };
+        let outcome = ApplyOutcome {
+            account: account(1, false),
+            receiver: Some(account(2, true)),
+        };
+        engine.notify_applied(&transfer, &outcome);
+        assert_eq!(recorder.take(), ["applied 3", "frozen 2 by 3"]);
+    }
+
+    #[test]
+    fn transactions_failing_to_be_journaled_are_notified() {
+        let recorder = Recorder::default();
+        let writer = FailingWriter::default();
+        let mut engine = Engine::new();
+        engine.add_observer(recorder.clone());
+        engine.set_journal(Journal::new(writer.clone()).unwrap());
+
+        // Applied by the engine, even if not journaled
+        writer.fail();
+        assert!(matches!(
+            engine.apply(deposit(1, 1, 100)),
+            Err(EngineError::Journal(_))
+        ));
+        assert!(matches!(
+            engine.apply(withdrawal(2, 2, 50)),
+            Err(EngineError::RejectionNotJournaled { .. })
+        ));
+        assert_eq!(recorder.take(), ["applied 1", "rejected 2"]);
+    }
+
+    #[test]
+    fn batches_are_notified_once_applied() {
+        let first = Recorder::default();
+        let second = Recorder::default();
+        let mut engine = Engine::new();
+        engine.add_observer(first.clone());
+        engine.add_observer(second.clone());
+
+        // Rolled back: only the rejected transaction is notified
+        let batch = [deposit(1, 1, 100), withdrawal(1, 2, 200)];
+        assert!(engine.apply_batch(&batch).is_err());
+        assert_eq!(first.take(), ["rejected 2"]);
+
+        let batch = [deposit(1, 1, 100), withdrawal(1, 2, 50)];
+        engine.apply_batch(&batch).unwrap();
+        assert_eq!(first.take(), ["applied 1", "applied 2"]);
+        assert_eq!(second.take(), ["rejected 2", "applied 1", "applied 2"]);
+
+        // Rolled back as its events can't be journaled: all rejected
+        let writer = FailingWriter::default();
+        engine.set_journal(Journal::new(writer.clone()).unwrap());
+        writer.fail();
+        let batch = [deposit(1, 3, 10), withdrawal(1, 4, 20)];
+        assert!(engine.apply_batch(&batch).is_err());
+        assert_eq!(first.take(), ["rejected 3", "rejected 4"]);
+    }
+}
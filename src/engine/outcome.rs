@@ -0,0 +1,261 @@
+//! Outcome of an applied transaction, derived from the events it emitted.
+
+use std::iter;
+
+use tracing::error;
+
+use super::{AccountStore, Balance, Engine, Event, TxStore};
+use crate::Amount;
+use crate::model::{Asset, ClientId, Transaction};
+
+/// Effect of a transaction on the accounts it changed, returned by
+/// [`Engine::apply`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyOutcome {
+    /// Effect on the account of the client of the transaction (the sender of a
+    /// transfer).
+    pub account: AccountOutcome,
+    /// Effect on the account of the receiver of a transfer.
+    pub receiver: Option<AccountOutcome>,
+}
+
+/// Effect of a transaction on one account, see [`ApplyOutcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountOutcome {
+    pub client: ClientId,
+    /// Asset of the changed balances, `None` if no funds moved (e.g. an account lock).
+    pub asset: Option<Asset>,
+    /// Change of the available balance.
+    pub available_delta: Amount,
+    /// Change of the held balance.
+    pub held_delta: Amount,
+    /// Balances of `asset` after the transaction, `None` if no funds moved or if the
+    /// account store failed to read the account back (logged).
+    pub balance: Option<Balance>,
+    /// Whether the transaction froze the account (a chargeback, or an admin lock).
+    pub became_frozen: bool,
+    /// Whether the available balance went negative (a dispute of spent funds, see
+    /// [`NegativeAvailablePolicy`](super::NegativeAvailablePolicy)).
+    pub went_negative: bool,
+}
+
+impl ApplyOutcome {
+    /// Return the effects on each account, the client of the transaction first.
+    pub fn accounts(&self) -> impl Iterator<Item = &AccountOutcome> {
+        iter::once(&self.account).chain(&self.receiver)
+    }
+}
+
+impl<A: AccountStore, T: TxStore> Engine<A, T> {
+    /// Describe the effect of an applied transaction, from the events it emitted
+    /// (from index `first` of the collected events).
+    ///
+    /// Doesn't fail once the transaction is applied: the balances are left out if the
+    /// accounts can't be read back, or the deltas are out of range.
+    pub(super) fn outcome(&self, transaction: &Transaction, first: usize) -> ApplyOutcome {
+        let receiver = match *transaction {
+            Transaction::Transfer { to, .. } => Some(self.account_outcome(to, first)),
+            _ => None,
+        };
+        ApplyOutcome {
+            account: self.account_outcome(transaction.client(), first),
+            receiver,
+        }
+    }
+
+    /// Describe the effect of the collected events on the account of `client`
+    fn account_outcome(&self, client: ClientId, first: usize) -> AccountOutcome {
+        let mut outcome = AccountOutcome {
+            client,
+            asset: None,
+            available_delta: Amount::default(),
+            held_delta: Amount::default(),
+            balance: None,
+            became_frozen: false,
+            went_negative: false,
+        };
+
+        for event in &self.events[first..] {
+            if let Event::AccountFrozen { client: frozen, .. } = *event {
+                outcome.became_frozen |= frozen == client;
+            } else if let Some((account, asset, available, held)) = balance_change(event)
+                && account == client
+            {
+                outcome.asset = Some(asset);
+                let available = available.and_then(|a| outcome.available_delta.checked_add(a));
+                let held = held.and_then(|h| outcome.held_delta.checked_add(h));
+                let (Some(available), Some(held)) = (available, held) else {
+                    error!(client, "balance deltas out of range");
+                    return outcome;
+                };
+                outcome.available_delta = available;
+                outcome.held_delta = held;
+            }
+        }
+
+        if let Some(asset) = outcome.asset {
+            let balance = match self.clients.get(client) {
+                Ok(account) => {
+                    account.map_or_else(Balance::default, |account| account.balance(asset))
+                }
+                Err(e) => {
+                    error!(client, "failed to read the account back: {e}");
+                    return outcome;
+                }
+            };
+            let available = balance.available();
+            let before = available.checked_sub(outcome.available_delta);
+            outcome.went_negative = available < Amount::default()
+                && before.is_some_and(|before| before >= Amount::default());
+            outcome.balance = Some(balance);
+        }
+        outcome
+    }
+}
+
+/// Return the account, asset, and available and held balance changes of a balance
+/// event. A change is `None` if out of range.
+fn balance_change(event: &Event) -> Option<(ClientId, Asset, Option<Amount>, Option<Amount>)> {
+    let zero = Some(Amount::default());
+    let negated = |amount: Amount| Amount::default().checked_sub(amount);
+    Some(match *event {
+        Event::FundsCredited {
+            client,
+            asset,
+            amount,
+            ..
+        }
+        | Event::ReservationReleased {
+            client,
+            asset,
+            amount,
+            ..
+        } => (client, asset, Some(amount), zero),
+        Event::FundsDebited {
+            client,
+            asset,
+            amount,
+            ..
+        }
+        | Event::FundsReserved {
+            client,
+            asset,
+            amount,
+            ..
+        } => (client, asset, negated(amount), zero),
+        Event::FundsHeld {
+            client,
+            asset,
+            amount,
+            ..
+        } => (client, asset, negated(amount), Some(amount)),
+        Event::FundsReleased {
+            client,
+            asset,
+            amount,
+            ..
+        } => (client, asset, Some(amount), negated(amount)),
+        Event::HeldFundsAdded {
+            client,
+            asset,
+            amount,
+            ..
+        } => (client, asset, zero, Some(amount)),
+        Event::HeldFundsRemoved {
+            client,
+            asset,
+            amount,
+            ..
+        } => (client, asset, zero, negated(amount)),
+        Event::ReservedFundsRemoved { client, asset, .. } => (client, asset, zero, zero),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::model::AdminAction;
+
+    fn amount(scaled: i64) -> Amount {
+        Amount::from_scaled(scaled)
+    }
+
+    #[test]
+    fn deposit_outcome() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        let outcome = engine.apply(deposit(1, 2, 50)).unwrap().account;
+
+        assert_eq!(outcome.client, 1);
+        assert_eq!(outcome.asset, Some(USD));
+        assert_eq!(outcome.available_delta, amount(50));
+        assert_eq!(outcome.held_delta, amount(0));
+        assert_eq!(outcome.balance.unwrap().available(), amount(150));
+        assert!(!outcome.became_frozen);
+        assert!(!outcome.went_negative);
+    }
+
+    #[test]
+    fn dispute_of_spent_funds_goes_negative() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(withdrawal(1, 2, 80)).unwrap();
+        let outcome = engine.apply(dispute(1, 1)).unwrap().account;
+
+        assert_eq!(outcome.available_delta, amount(-100));
+        assert_eq!(outcome.held_delta, amount(100));
+        assert_eq!(outcome.balance.unwrap().available(), amount(-80));
+        assert_eq!(outcome.balance.unwrap().held(), amount(100));
+        assert!(outcome.went_negative);
+
+        // Already negative
+        engine.apply(deposit(1, 3, 10)).unwrap();
+        let outcome = engine.apply(dispute(1, 3)).unwrap().account;
+        assert!(!outcome.went_negative);
+    }
+
+    #[test]
+    fn chargeback_freezes() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        engine.apply(dispute(1, 1)).unwrap();
+        let outcome = engine.apply(chargeback(1, 1)).unwrap().account;
+
+        assert_eq!(outcome.available_delta, amount(0));
+        assert_eq!(outcome.held_delta, amount(-100));
+        assert_eq!(outcome.balance.unwrap().total(), amount(0));
+        assert!(outcome.became_frozen);
+    }
+
+    #[test]
+    fn transfer_outcome_covers_both_accounts() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        let outcome = engine.apply(transfer(1, 2, 2, 30)).unwrap();
+
+        assert_eq!(outcome.account.client, 1);
+        assert_eq!(outcome.account.available_delta, amount(-30));
+        assert_eq!(outcome.account.balance.unwrap().available(), amount(70));
+
+        let receiver = outcome.receiver.unwrap();
+        assert_eq!(receiver.client, 2);
+        assert_eq!(receiver.asset, Some(USD));
+        assert_eq!(receiver.available_delta, amount(30));
+        assert_eq!(receiver.balance.unwrap().available(), amount(30));
+        assert_eq!(outcome.accounts().count(), 2);
+    }
+
+    #[test]
+    fn lock_moves_no_funds() {
+        let mut engine = Engine::new();
+        engine.apply(deposit(1, 1, 100)).unwrap();
+        let outcome = engine.apply(admin(1, 2, AdminAction::Lock)).unwrap();
+
+        assert_eq!(outcome.account.asset, None);
+        assert_eq!(outcome.account.balance, None);
+        assert!(outcome.account.became_frozen);
+        assert_eq!(outcome.receiver, None);
+    }
+}
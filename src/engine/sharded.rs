@@ -0,0 +1,686 @@
+//! Client-sharded engine, applying transactions on parallel workers.
+//!
+//! Clients are partitioned across shards, each an [`Engine`] owning the accounts of
+//! its clients and the records of their transactions, run by a blocking worker.
+//! A dispatcher reads the stream in order and routes each transaction:
+//! - Deposits, withdrawals, authorizations and admin transactions go to the shard of
+//!   their client.
+//! - Disputes, resolves, chargebacks, re-presentments, captures and voids go to the
+//!   shard which saw the referenced ID, or to the shard of their client once the
+//!   dispatcher forgot the ID. Records are stored by the shard of their client, so
+//!   references to other clients' transactions are rejected either way.
+//! - Transfers go to the shard of the sender. Between two shards, both are drained
+//!   and the receiver account joins the sender shard for the transfer.
+//!
+//! Transaction IDs stay globally unique: the dispatcher remembers the shard which
+//! first saw each of the latest IDs. When another shard sees it again, the first one
+//! is drained to check whether it stored the ID, and the ID is then copied to the
+//! other shard, which rejects the transaction as duplicated. IDs not remembered are
+//! new if above the highest ID the dispatcher forgot, otherwise they are looked up
+//! in every shard, once drained.
+//!
+//! Time is shared as well: shards publish the latest time of the transactions they
+//! applied. Transactions without a timestamp, and disputes, are given the time of the
+//! whole stream, once the shards applied the earlier transactions which could move it
+//! forward, so rejected transactions don't move it, as in a single engine.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{self, JoinHandle};
+use tokio_stream::{Stream, StreamExt};
+
+use super::{ClientAccount, Engine, EngineConfig, StoreError, TxStore};
+use crate::model::{ClientId, Timestamp, Transaction, TxId};
+
+/// Operations sent to a shard worker in one batch
+const BATCH_SIZE: usize = 1024;
+
+/// Batches queued per shard before the dispatcher waits for the worker
+const QUEUED_BATCHES: usize = 16;
+
+/// Transaction IDs remembered by the dispatcher
+const CLAIMS: usize = 1 << 20;
+
+const WORKER_STOPPED: &str = "shard worker stopped";
+
+/// An engine partitioning clients across parallel shards.
+///
+/// Applies transactions with the same results as a single [`Engine`], see the
+/// [module documentation](self) for how shards are kept consistent. Transfers between
+/// shards and transaction IDs reused across shards wait for the shards involved,
+/// and are slower than other transactions.
+pub struct ShardedEngine {
+    config: EngineConfig,
+    /// Number of shards, their engines are moved to the workers while running
+    count: usize,
+    shards: Vec<Engine>,
+    /// Shard expected to store each of the latest transaction IDs (the first to see
+    /// it, unless it rejected the transaction), and whether the ID is known to be
+    /// stored
+    claims: BTreeMap<TxId, Claim>,
+    /// Number of IDs remembered, the lowest are forgotten first
+    max_claims: usize,
+    /// Highest ID forgotten, the IDs above it are all remembered once seen
+    forgotten: Option<TxId>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Claim {
+    shard: u32,
+    taken: bool,
+}
+
+enum Operation {
+    Apply(Transaction),
+    /// Store an ID taken by another shard, so transactions reusing it are rejected
+    ReserveId(TxId),
+}
+
+enum Command {
+    Batch(Vec<Operation>),
+    /// Answered once the previous commands are applied
+    Sync(oneshot::Sender<()>),
+}
+
+impl ShardedEngine {
+    /// Create an engine applying the given business policies on `shards` shards (at
+    /// least 1).
+    pub fn new(config: EngineConfig, shards: usize) -> Self {
+        let count = shards.max(1);
+        let shards = (0..count)
+            .map(|_| Engine::with_config(config.clone()))
+            .collect();
+        Self {
+            config,
+            count,
+            shards,
+            claims: BTreeMap::new(),
+            max_claims: CLAIMS,
+            forgotten: None,
+        }
+    }
+
+    /// Run the engine with the given transaction stream, each shard on a blocking
+    /// task.
+    ///
+    /// The shards are only returned to the engine once the stream ends, their state is
+    /// lost if the future is dropped before.
+    pub async fn run(&mut self, mut stream: impl Stream<Item = Transaction> + Unpin) {
+        let mut workers = Workers::spawn(mem::take(&mut self.shards));
+        while let Some(tx) = stream.next().await {
+            self.dispatch(&mut workers, tx).await;
+        }
+        self.shards = workers.join().await;
+    }
+
+    /// Return the engine configuration
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Return the state of client accounts, grouped by shard.
+    pub fn clients(&self) -> impl Iterator<Item = &ClientAccount> + '_ {
+        self.shards.iter().flat_map(Engine::clients)
+    }
+
+    /// Return the state of one client account
+    pub fn get_client(&self, client: ClientId) -> Result<Option<&ClientAccount>, StoreError> {
+        self.shards[self.shard(client)].get_client(client)
+    }
+
+    /// Return the shard owning the accounts of a client
+    fn shard(&self, client: ClientId) -> usize {
+        (client % self.count as u64) as usize
+    }
+
+    /// Route a transaction to the shards, in order of the stream
+    async fn dispatch(&mut self, workers: &mut Workers, mut transaction: Transaction) {
+        // Shards only see their own transactions, those without a timestamp are given
+        // the time of the whole stream, as are disputes, which are checked against it
+        let is_dispute = matches!(transaction, Transaction::Dispute { .. });
+        if is_dispute || transaction.timestamp().is_none() {
+            let clock = workers.clock().await;
+            let timestamp = transaction.timestamp_mut();
+            *timestamp = Some(timestamp.map_or(clock, |time| time.max(clock)));
+        }
+
+        match transaction {
+            Transaction::Deposit { client, tx, .. }
+            | Transaction::Withdrawal { client, tx, .. }
+            | Transaction::Authorize { client, tx, .. }
+            | Transaction::Admin { client, tx, .. } => {
+                let shard = self.shard(client);
+                self.claim(workers, tx, shard).await;
+                workers.push(shard, Operation::Apply(transaction)).await;
+            }
+            Transaction::Transfer { from, to, tx, .. } => {
+                let (shard, receiver) = (self.shard(from), self.shard(to));
+                self.claim(workers, tx, shard).await;
+                if shard == receiver {
+                    workers.push(shard, Operation::Apply(transaction)).await;
+                } else {
+                    workers.transfer(shard, receiver, to, transaction).await;
+                }
+            }
+            Transaction::Capture { client, tx, .. }
+            | Transaction::Void { client, tx, .. }
+            | Transaction::Dispute { client, tx, .. }
+            | Transaction::Resolve { client, tx, .. }
+            | Transaction::Chargeback { client, tx, .. }
+            | Transaction::Represent { client, tx, .. } => {
+                // The shard which last saw the ID holds its record if any, and checks
+                // the record belongs to the client. A forgotten ID is only found by
+                // the shard of the client if the record is the client's.
+                let shard = match self.claims.get(&tx) {
+                    Some(claim) => claim.shard as usize,
+                    None => self.shard(client),
+                };
+                workers.push(shard, Operation::Apply(transaction)).await;
+            }
+        }
+    }
+
+    /// Check the ID of a transaction about to be applied by a shard is still free, or
+    /// copy it to the shard so it rejects the transaction.
+    async fn claim(&mut self, workers: &mut Workers, tx: TxId, shard: usize) {
+        let claim = match self.claims.get(&tx) {
+            Some(claim) => *claim,
+            // Above the forgotten IDs, the ID was never seen
+            None if self.forgotten.is_none_or(|forgotten| tx > forgotten) => {
+                self.remember(tx, shard, false);
+                return;
+            }
+            // Maybe forgotten, or never seen: only stored by the shards if taken
+            None => {
+                let taken = workers.stored_by(tx).await;
+                if let Some(first) = taken
+                    && first != shard
+                {
+                    workers.push(shard, Operation::ReserveId(tx)).await;
+                }
+                self.remember(tx, taken.unwrap_or(shard), taken.is_some());
+                return;
+            }
+        };
+        let first = claim.shard as usize;
+        if first == shard {
+            // The shard checks its own IDs
+            return;
+        }
+
+        // IDs are never removed once stored, but the first shard may have rejected
+        // the transaction which used it
+        let taken = claim.taken || {
+            workers.sync(first).await;
+            matches!(workers.lock(first).txs.contains(tx), Ok(true))
+        };
+        if taken {
+            self.claims.insert(tx, Claim { taken, ..claim });
+            workers.push(shard, Operation::ReserveId(tx)).await;
+        } else {
+            let shard = shard as u32;
+            self.claims.insert(tx, Claim { shard, taken });
+        }
+    }
+
+    /// Remember the shard expected to store an ID, forgetting the lowest ID if full.
+    fn remember(&mut self, tx: TxId, shard: usize, taken: bool) {
+        let shard = shard as u32;
+        self.claims.insert(tx, Claim { shard, taken });
+        if self.claims.len() > self.max_claims
+            && let Some((forgotten, _)) = self.claims.pop_first()
+        {
+            self.forgotten = self.forgotten.max(Some(forgotten));
+        }
+    }
+}
+
+/// Shard engines run by blocking tasks, fed with batches of operations.
+struct Workers {
+    engines: Vec<Arc<Mutex<Engine>>>,
+    senders: Vec<mpsc::Sender<Command>>,
+    handles: Vec<JoinHandle<()>>,
+    /// Operations not sent yet, per shard
+    batches: Vec<Vec<Operation>>,
+    /// Latest time of the transactions applied by the shards
+    clock: Arc<AtomicU64>,
+    /// Latest timestamp routed to each shard since it was last drained, which moves
+    /// the clock forward if the transaction is applied
+    pending: Vec<Timestamp>,
+}
+
+impl Workers {
+    fn spawn(shards: Vec<Engine>) -> Self {
+        let clock = shards.iter().map(|engine| engine.clock).max().unwrap_or(0);
+        let mut workers = Self {
+            engines: Vec::new(),
+            senders: Vec::new(),
+            handles: Vec::new(),
+            batches: Vec::new(),
+            clock: Arc::new(AtomicU64::new(clock)),
+            pending: Vec::new(),
+        };
+        for engine in shards {
+            let engine = Arc::new(Mutex::new(engine));
+            let (sender, receiver) = mpsc::channel(QUEUED_BATCHES);
+            let worker = Arc::clone(&engine);
+            let clock = Arc::clone(&workers.clock);
+            workers.engines.push(engine);
+            workers.senders.push(sender);
+            workers.handles.push(task::spawn_blocking(move || {
+                work(&worker, &clock, receiver)
+            }));
+            workers.batches.push(Vec::with_capacity(BATCH_SIZE));
+            workers.pending.push(0);
+        }
+        workers
+    }
+
+    /// Apply the remaining operations, and return the shard engines.
+    async fn join(mut self) -> Vec<Engine> {
+        for shard in 0..self.engines.len() {
+            self.flush(shard).await;
+        }
+        drop(self.senders);
+        for handle in self.handles {
+            handle.await.expect(WORKER_STOPPED);
+        }
+        self.engines
+            .into_iter()
+            .map(|engine| {
+                let engine = Arc::into_inner(engine).expect(WORKER_STOPPED);
+                engine.into_inner().expect(WORKER_STOPPED)
+            })
+            .collect()
+    }
+
+    async fn push(&mut self, shard: usize, operation: Operation) {
+        if let Operation::Apply(tx) = &operation {
+            let pending = &mut self.pending[shard];
+            *pending = (*pending).max(tx.timestamp().unwrap_or(0));
+        }
+        self.batches[shard].push(operation);
+        if self.batches[shard].len() >= BATCH_SIZE {
+            self.flush(shard).await;
+        }
+    }
+
+    async fn flush(&mut self, shard: usize) {
+        if self.batches[shard].is_empty() {
+            return;
+        }
+        let batch = mem::replace(&mut self.batches[shard], Vec::with_capacity(BATCH_SIZE));
+        self.send(shard, Command::Batch(batch)).await;
+    }
+
+    /// Wait until a shard applied all the operations routed to it.
+    ///
+    /// The shard is idle until new operations are pushed, and can be locked.
+    async fn sync(&mut self, shard: usize) {
+        self.flush(shard).await;
+        let (done, synced) = oneshot::channel();
+        self.send(shard, Command::Sync(done)).await;
+        synced.await.expect(WORKER_STOPPED);
+        self.pending[shard] = 0;
+    }
+
+    /// Return the latest time of the applied transactions, once the shards applied
+    /// the transactions routed to them which could move it forward.
+    async fn clock(&mut self) -> Timestamp {
+        for shard in 0..self.engines.len() {
+            if self.pending[shard] > self.clock.load(Ordering::Acquire) {
+                self.sync(shard).await;
+            }
+        }
+        self.clock.load(Ordering::Acquire)
+    }
+
+    async fn send(&self, shard: usize, command: Command) {
+        if self.senders[shard].send(command).await.is_err() {
+            panic!("{WORKER_STOPPED}");
+        }
+    }
+
+    fn lock(&self, shard: usize) -> MutexGuard<'_, Engine> {
+        self.engines[shard].lock().expect(WORKER_STOPPED)
+    }
+
+    /// Return the first shard storing a transaction ID, once all shards applied the
+    /// operations routed to them.
+    async fn stored_by(&mut self, tx: TxId) -> Option<usize> {
+        for shard in 0..self.engines.len() {
+            self.sync(shard).await;
+        }
+        (0..self.engines.len()).find(|&shard| matches!(self.lock(shard).txs.contains(tx), Ok(true)))
+    }
+
+    /// Apply a transfer between two shards on a blocking task, see [`transfer`].
+    async fn transfer(&mut self, shard: usize, receiver: usize, to: ClientId, tx: Transaction) {
+        self.sync(shard).await;
+        self.sync(receiver).await;
+        let sending = Arc::clone(&self.engines[shard]);
+        let receiving = Arc::clone(&self.engines[receiver]);
+        let clock = Arc::clone(&self.clock);
+        task::spawn_blocking(move || transfer(&sending, &receiving, &clock, to, tx))
+            .await
+            .expect(WORKER_STOPPED);
+    }
+}
+
+/// Apply a transfer on the sender shard, moving the receiver account there for the
+/// transfer.
+fn transfer(
+    sending: &Mutex<Engine>,
+    receiving: &Mutex<Engine>,
+    clock: &AtomicU64,
+    to: ClientId,
+    tx: Transaction,
+) {
+    let mut sending = sending.lock().expect(WORKER_STOPPED);
+    let mut receiving = receiving.lock().expect(WORKER_STOPPED);
+
+    if let Some(account) = receiving.clients.accounts.remove(&to) {
+        sending.clients.accounts.insert(to, account);
+    }
+    // Rejected transfers are logged by the engine
+    let _ = sending.apply(tx);
+    clock.fetch_max(sending.clock, Ordering::AcqRel);
+    if let Some(account) = sending.clients.accounts.remove(&to) {
+        receiving.clients.accounts.insert(to, account);
+    }
+}
+
+/// Apply the commands sent to a shard, until the dispatcher is done, publishing the
+/// time of the applied transactions to `clock`.
+fn work(engine: &Mutex<Engine>, clock: &AtomicU64, mut receiver: mpsc::Receiver<Command>) {
+    while let Some(command) = receiver.blocking_recv() {
+        match command {
+            Command::Batch(operations) => {
+                let mut engine = engine.lock().expect(WORKER_STOPPED);
+                for operation in operations {
+                    // Failures are logged by the engine, as in `Engine::run`
+                    let _ = match operation {
+                        Operation::Apply(tx) => engine.apply(tx).map(drop),
+                        Operation::ReserveId(tx) => engine.txs.insert_id(tx).map_err(Into::into),
+                    };
+                    // Only applied transactions move the engine clock
+                    clock.fetch_max(engine.clock, Ordering::AcqRel);
+                    // Shard engines have no journal to fail
+                    let _ = engine.evict_expired_periodically();
+                }
+            }
+            Command::Sync(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Amount;
+    use crate::engine::DisputePolicy;
+    use crate::engine::test_utils::{USD, at, deposit, dispute, transfer, withdrawal};
+    use crate::model::{AdminAction, Metadata};
+
+    /// Pseudo-random transactions of a few clients, reusing IDs and referencing
+    /// transactions of other clients.
+    fn random_transactions(mut seed: u64, count: u64) -> Vec<Transaction> {
+        let mut next = move |bound: u64| {
+            // xorshift64
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % bound
+        };
+        let metadata = Metadata::default;
+        (1..=count)
+            .map(|id| {
+                let client = next(10) + 1;
+                // Mostly new IDs, sometimes one already used
+                let tx = if next(10) == 0 { next(id) + 1 } else { id };
+                let reference = next(id) + 1;
+                let amount = Amount::from_scaled(next(1_000) as i64 - 50);
+                let timestamp = (next(4) != 0).then(|| id * 100 - next(100));
+                match next(12) {
+                    0..=3 => Transaction::Deposit {
+                        client,
+                        tx,
+                        asset: USD,
+                        amount,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    4 | 5 => Transaction::Withdrawal {
+                        client,
+                        tx,
+                        asset: USD,
+                        amount,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    6 => Transaction::Authorize {
+                        client,
+                        tx,
+                        asset: USD,
+                        amount,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    7 => Transaction::Capture {
+                        client,
+                        tx: reference,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    8 => Transaction::Transfer {
+                        from: client,
+                        to: next(10) + 1,
+                        tx,
+                        asset: USD,
+                        amount,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    9 => Transaction::Admin {
+                        client,
+                        tx,
+                        operator: "ops".to_string(),
+                        action: if next(2) == 0 {
+                            AdminAction::Lock
+                        } else {
+                            AdminAction::Unlock
+                        },
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    10 => Transaction::Dispute {
+                        client,
+                        tx: reference,
+                        amount: None,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                    _ => Transaction::Chargeback {
+                        client,
+                        tx: reference,
+                        amount: None,
+                        timestamp,
+                        metadata: metadata(),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn accounts<'a>(clients: impl Iterator<Item = &'a ClientAccount>) -> Vec<String> {
+        let mut accounts: Vec<_> = clients.map(|account| format!("{account:?}")).collect();
+        accounts.sort();
+        accounts
+    }
+
+    async fn assert_same_as_single_engine(config: EngineConfig, transactions: Vec<Transaction>) {
+        let mut single = Engine::with_config(config.clone());
+        single
+            .run(tokio_stream::iter(transactions.clone()))
+            .await
+            .unwrap();
+
+        for (shards, max_claims) in [(1, CLAIMS), (3, CLAIMS), (4, CLAIMS), (3, 16)] {
+            let mut sharded = ShardedEngine::new(config.clone(), shards);
+            sharded.max_claims = max_claims;
+            sharded.run(tokio_stream::iter(transactions.clone())).await;
+            assert_eq!(accounts(sharded.clients()), accounts(single.clients()));
+            assert!(sharded.claims.len() <= max_claims);
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_single_engine() {
+        for seed in [1, 7, 42] {
+            let transactions = random_transactions(seed, 5_000);
+            assert_same_as_single_engine(EngineConfig::default(), transactions).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_single_engine_with_dispute_window() {
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy::DepositsAndWithdrawals,
+            dispute_window: Some(50_000),
+            max_redisputes: Some(1),
+            ..EngineConfig::default()
+        };
+        let transactions = random_transactions(3, 5_000);
+        assert_same_as_single_engine(config, transactions).await;
+    }
+
+    #[tokio::test]
+    async fn duplicate_ids_are_rejected_across_shards() {
+        let mut engine = ShardedEngine::new(EngineConfig::default(), 2);
+        let transactions = vec![
+            deposit(1, 1, 100),
+            deposit(2, 1, 50),      // Duplicate of a deposit of the other shard
+            transfer(1, 2, 2, 500), // Rejected, its ID is still free
+            deposit(2, 2, 20),
+            deposit(1, 2, 30), // Duplicate again
+        ];
+        engine.run(tokio_stream::iter(transactions)).await;
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        let client2 = engine.get_client(2).unwrap().unwrap();
+        assert_eq!(client1.available(USD), Amount::from_scaled(100));
+        assert_eq!(client2.available(USD), Amount::from_scaled(20));
+    }
+
+    #[tokio::test]
+    async fn forgotten_ids_are_looked_up_in_all_shards() {
+        let mut engine = ShardedEngine::new(EngineConfig::default(), 2);
+        engine.max_claims = 1;
+        let transactions = vec![
+            deposit(1, 1, 100),
+            deposit(1, 3, 10),
+            deposit(2, 1, 50), // Duplicate of a forgotten deposit of the other shard
+            deposit(2, 2, 20), // Forgotten but free
+            dispute(1, 1),     // Routed to the shard of the client
+        ];
+        engine.run(tokio_stream::iter(transactions)).await;
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        let client2 = engine.get_client(2).unwrap().unwrap();
+        assert_eq!(client1.held(USD), Amount::from_scaled(100));
+        assert_eq!(client2.available(USD), Amount::from_scaled(20));
+        assert_eq!(engine.claims.len(), 1);
+        assert_eq!(engine.forgotten, Some(2));
+    }
+
+    #[tokio::test]
+    async fn unordered_ids_are_new_until_forgotten() {
+        let mut engine = ShardedEngine::new(EngineConfig::default(), 2);
+        let transactions = vec![
+            deposit(1, 5, 100),
+            deposit(2, 3, 50), // Below the latest ID, but nothing was forgotten
+            deposit(1, 3, 10), // Duplicate of a remembered deposit of the other shard
+            deposit(2, 4, 20),
+        ];
+        engine.run(tokio_stream::iter(transactions)).await;
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        let client2 = engine.get_client(2).unwrap().unwrap();
+        assert_eq!(client1.available(USD), Amount::from_scaled(100));
+        assert_eq!(client2.available(USD), Amount::from_scaled(70));
+        assert_eq!(engine.forgotten, None);
+        assert_eq!(engine.claims.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn disputes_of_other_clients_are_rejected() {
+        let mut engine = ShardedEngine::new(EngineConfig::default(), 2);
+        let transactions = vec![deposit(1, 1, 100), deposit(2, 2, 50), dispute(2, 1)];
+        engine.run(tokio_stream::iter(transactions)).await;
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        let client2 = engine.get_client(2).unwrap().unwrap();
+        assert_eq!(client1.held(USD), Amount::from_scaled(0));
+        assert_eq!(client2.held(USD), Amount::from_scaled(0));
+    }
+
+    #[tokio::test]
+    async fn transactions_without_timestamp_happen_at_the_stream_time() {
+        let config = EngineConfig {
+            dispute_window: Some(100),
+            ..EngineConfig::default()
+        };
+        let mut engine = ShardedEngine::new(config, 2);
+        let late = at(deposit(2, 2, 50), 1_000);
+        // The dispute happens after the deposit of the other shard, out of the window
+        let transactions = vec![deposit(1, 1, 100), late, dispute(1, 1)];
+        engine.run(tokio_stream::iter(transactions)).await;
+
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client1.held(USD), Amount::from_scaled(0));
+    }
+
+    #[tokio::test]
+    async fn rejected_transactions_do_not_move_the_stream_time() {
+        let config = EngineConfig {
+            dispute_window: Some(100),
+            ..EngineConfig::default()
+        };
+        // The withdrawal is rejected, the dispute is still within the window
+        let transactions = vec![
+            at(deposit(1, 1, 100_000), 1_000),
+            at(withdrawal(1, 2, 5_000_000), 9_999_999),
+            dispute(1, 1),
+        ];
+        assert_same_as_single_engine(config.clone(), transactions.clone()).await;
+
+        let mut engine = ShardedEngine::new(config, 2);
+        engine.run(tokio_stream::iter(transactions)).await;
+        let client1 = engine.get_client(1).unwrap().unwrap();
+        assert_eq!(client1.held(USD), Amount::from_scaled(100_000));
+    }
+
+    #[tokio::test]
+    async fn transfers_move_funds_across_shards() {
+        let mut engine = ShardedEngine::new(EngineConfig::default(), 2);
+        let transactions = vec![
+            deposit(1, 1, 100),
+            transfer(1, 2, 2, 40),
+            transfer(2, 4, 3, 10),
+            deposit(2, 4, 5),
+        ];
+        engine.run(tokio_stream::iter(transactions)).await;
+
+        let balance = |client| engine.get_client(client).unwrap().unwrap().available(USD);
+        assert_eq!(balance(1), Amount::from_scaled(60));
+        assert_eq!(balance(2), Amount::from_scaled(35));
+        assert_eq!(balance(4), Amount::from_scaled(10));
+        assert_eq!(engine.clients().count(), 3);
+    }
+}
@@ -0,0 +1,669 @@
+//! Snapshots of the engine state.
+//!
+//! A snapshot is a versioned binary file holding everything the engine needs to
+//! continue processing where it stopped: client accounts, transaction records with
+//! their dispute states, IDs kept for duplicate checking, retained metadata and
+//! dispute counts, and the engine clock. The configuration is not saved, it is
+//! given again when restoring.
+//!
+//! Layout (integers in little endian, amounts as their scaled `i64` then scale byte,
+//! assets as their 7 byte code, lengths as `u64`):
+//! - header: `TXSNAPSH` magic, `u32` version
+//! - clock
+//! - assets: length, then asset code and `u32` scale, to check amounts are restored
+//!   at the scale they were saved with
+//! - accounts: length, then client ID, freeze reason byte (0 unfrozen, 1 chargeback,
+//!   2 admin lock), `u32` chargebacks and the
+//!   balances (length, then asset, available, held, reserved)
+//! - records: length, then tx ID, kind byte (0 deposit, 1 withdrawal), client,
+//!   asset, amount, disputed, charged back, timestamp, state byte
+//! - authorizations: length, then tx ID, client, asset, amount, state byte
+//! - IDs without record, then evicted IDs: length, then tx IDs
+//! - dispute counts: length, then tx ID and `u32` count
+//! - metadata: length, then tx ID and its entries (length, then key and value as
+//!   length and UTF-8 bytes)
+//!
+//! Every section is sorted by key, so the same state always gives the same snapshot.
+//!
+//! Restoring checks the state is one the engine could have reached: balances in
+//! range, record portions within their amount and matching their state, held
+//! and reserved funds matching the open disputes and pending authorizations,
+//! every transaction ID stored only once across records, authorizations, IDs
+//! without record and evicted IDs, records and authorizations belonging to an
+//! account, chargeback counts matching the charged back deposits, and dispute
+//! counts and metadata only kept for stored records.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+
+use super::codec::{Reader, Writer};
+use super::store::{MemoryAccountStore, MemoryTxStore, RecordKind};
+use super::{Balance, ClientAccount, Engine, EngineConfig, FreezeReason, SnapshotError};
+use crate::Amount;
+use crate::model::{
+    Asset, AuthorizationRecord, AuthorizationState, ClientId, DepositRecord, Metadata,
+};
+
+const MAGIC: &[u8; 8] = b"TXSNAPSH";
+/// Current format version, bumped on any layout change.
+const VERSION: u32 = 3;
+
+impl Engine {
+    /// Write the full engine state to `writer`, see the [module docs](self) for
+    /// the format.
+    ///
+    /// The writer is not buffered here, wrap files in a `BufWriter`.
+    pub fn snapshot(&self, writer: impl Write) -> Result<(), SnapshotError> {
+        let mut w = Writer::new(writer);
+        w.bytes(MAGIC)?;
+        w.u32(VERSION)?;
+        w.u64(self.clock)?;
+
+        let mut accounts: Vec<_> = self.clients.accounts.values().collect();
+        accounts.sort_by_key(|account| account.id());
+        let deposits =
+            (self.txs.deposits.iter()).map(|(tx, record)| (*tx, RecordKind::Deposit, record));
+        let withdrawals =
+            (self.txs.withdrawals.iter()).map(|(tx, record)| (*tx, RecordKind::Withdrawal, record));
+        let mut records: Vec<_> = deposits.chain(withdrawals).collect();
+        records.sort_by_key(|(tx, ..)| *tx);
+
+        // Scales of every asset an amount is saved in
+        let assets: BTreeSet<Asset> = accounts
+            .iter()
+            .flat_map(|account| account.balances().map(|(asset, _)| asset))
+            .chain(records.iter().map(|(_, _, record)| record.asset))
+            .chain(self.txs.authorizations.values().map(|record| record.asset))
+            .collect();
+        w.len(assets.len())?;
+        for asset in assets {
+            w.asset(asset)?;
+            w.u32(self.config.precision_for(asset).scale())?;
+        }
+
+        w.len(accounts.len())?;
+        for account in accounts {
+            w.u64(account.id())?;
+            w.freeze_reason(account.frozen_by())?;
+            w.u32(account.chargebacks())?;
+            w.len(account.balances().count())?;
+            for (asset, balance) in account.balances() {
+                w.asset(asset)?;
+                w.amount(balance.available())?;
+                w.amount(balance.held())?;
+                w.amount(balance.reserved())?;
+            }
+        }
+
+        w.len(records.len())?;
+        for (tx, kind, record) in records {
+            w.u64(tx)?;
+            w.record_kind(kind)?;
+            w.u64(record.client)?;
+            w.asset(record.asset)?;
+            w.amount(record.amount)?;
+            w.amount(record.disputed)?;
+            w.amount(record.charged_back)?;
+            w.u64(record.timestamp)?;
+            w.deposit_state(record.state)?;
+        }
+
+        let authorizations: BTreeMap<_, _> = self.txs.authorizations.iter().collect();
+        w.len(authorizations.len())?;
+        for (tx, record) in authorizations {
+            w.u64(*tx)?;
+            w.u64(record.client)?;
+            w.asset(record.asset)?;
+            w.amount(record.amount)?;
+            w.authorization_state(record.state)?;
+        }
+
+        for ids in [&self.txs.ids, &self.txs.evicted] {
+            let ids: BTreeSet<_> = ids.iter().collect();
+            w.len(ids.len())?;
+            for tx in ids {
+                w.u64(*tx)?;
+            }
+        }
+
+        let dispute_counts: BTreeMap<_, _> = self.dispute_counts.iter().collect();
+        w.len(dispute_counts.len())?;
+        for (tx, count) in dispute_counts {
+            w.u64(*tx)?;
+            w.u32(*count)?;
+        }
+
+        let deposit_metadata: BTreeMap<_, _> = self.deposit_metadata.iter().collect();
+        w.len(deposit_metadata.len())?;
+        for (tx, metadata) in deposit_metadata {
+            w.u64(*tx)?;
+            w.len(metadata.len())?;
+            for (key, value) in metadata {
+                w.str(key)?;
+                w.str(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create an engine from a snapshot written by [`Engine::snapshot`], applying
+    /// the given business policies.
+    ///
+    /// Fails if an asset is configured with another scale than the snapshot was
+    /// written with, amounts are not rescaled.
+    pub fn restore(config: EngineConfig, reader: impl Read) -> Result<Self, SnapshotError> {
+        let mut r = Reader::new(reader);
+        if &r.array::<8>()? != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let mut engine = Engine::with_config(config);
+        engine.clock = r.u64()?;
+
+        for _ in 0..r.len()? {
+            let asset = r.asset()?;
+            let scale = r.u32()?;
+            let configured = engine.config.precision_for(asset).scale();
+            if scale != configured {
+                return Err(SnapshotError::PrecisionMismatch(asset, scale, configured));
+            }
+        }
+
+        let mut accounts = HashMap::new();
+        for _ in 0..r.len()? {
+            let id = r.u64()?;
+            let frozen = r.freeze_reason()?;
+            let chargebacks = r.u32()?;
+            let mut balances = BTreeMap::new();
+            for _ in 0..r.len()? {
+                let asset = r.asset()?;
+                let balance = Balance::from_parts(r.amount()?, r.amount()?, r.amount()?)
+                    .ok_or(SnapshotError::Invalid("balance out of range"))?;
+                balances.insert(asset, balance);
+            }
+            let account = ClientAccount::from_parts(id, balances, frozen, chargebacks);
+            accounts.insert(id, account);
+        }
+        engine.clients = MemoryAccountStore { accounts };
+
+        let mut txs = MemoryTxStore::new();
+        // A transaction ID has at most one of a record, an authorization, an ID
+        // without record or an evicted ID
+        let mut stored = HashSet::new();
+        let mut store_id = |tx| {
+            stored
+                .insert(tx)
+                .then_some(())
+                .ok_or(SnapshotError::Invalid(
+                    "transaction ID stored more than once",
+                ))
+        };
+        for _ in 0..r.len()? {
+            let tx = r.u64()?;
+            let records = match r.record_kind()? {
+                RecordKind::Deposit => &mut txs.deposits,
+                RecordKind::Withdrawal => &mut txs.withdrawals,
+            };
+            let record = DepositRecord {
+                client: r.u64()?,
+                asset: r.asset()?,
+                amount: r.amount()?,
+                disputed: r.amount()?,
+                charged_back: r.amount()?,
+                timestamp: r.u64()?,
+                state: r.deposit_state()?,
+            };
+            check_record(&record)?;
+            if records.insert(tx, record).is_some() {
+                return Err(SnapshotError::Invalid("duplicate record"));
+            }
+            store_id(tx)?;
+        }
+
+        for _ in 0..r.len()? {
+            let tx = r.u64()?;
+            let record = AuthorizationRecord {
+                client: r.u64()?,
+                asset: r.asset()?,
+                amount: r.amount()?,
+                state: r.authorization_state()?,
+            };
+            if txs.authorizations.insert(tx, record).is_some() {
+                return Err(SnapshotError::Invalid("duplicate authorization"));
+            }
+            store_id(tx)?;
+        }
+
+        for ids in [&mut txs.ids, &mut txs.evicted] {
+            for _ in 0..r.len()? {
+                let tx = r.u64()?;
+                store_id(tx)?;
+                ids.insert(tx);
+            }
+        }
+        engine.txs = txs;
+
+        for _ in 0..r.len()? {
+            let tx = r.u64()?;
+            engine.dispute_counts.insert(tx, r.u32()?);
+        }
+
+        for _ in 0..r.len()? {
+            let tx = r.u64()?;
+            let mut metadata = Metadata::new();
+            for _ in 0..r.len()? {
+                metadata.insert(r.string()?, r.string()?);
+            }
+            engine.deposit_metadata.insert(tx, metadata);
+        }
+
+        // Nothing may follow the last section
+        if !r.at_end()? {
+            return Err(SnapshotError::Invalid("trailing data"));
+        }
+        engine.check_balances()?;
+        engine.check_references()?;
+        Ok(engine)
+    }
+
+    /// Check every record and authorization belongs to an account, the chargeback
+    /// count of every account is its number of charged back deposits, and dispute
+    /// counts and metadata are only kept for stored records.
+    ///
+    /// When re-presentments unfreeze accounts, an account frozen by a chargeback
+    /// must also have a charged back deposit left, or nothing would unfreeze it.
+    fn check_references(&self) -> Result<(), SnapshotError> {
+        let accounts = &self.clients.accounts;
+        let (deposits, withdrawals) = (&self.txs.deposits, &self.txs.withdrawals);
+        let clients = (deposits.values().chain(withdrawals.values()))
+            .map(|record| record.client)
+            .chain(self.txs.authorizations.values().map(|record| record.client));
+        for client in clients {
+            if !accounts.contains_key(&client) {
+                return Err(SnapshotError::Invalid(
+                    "record of a client without an account",
+                ));
+            }
+        }
+
+        let mut chargebacks: HashMap<ClientId, u32> = HashMap::new();
+        for record in deposits.values() {
+            if record.charged_back > Amount::default() {
+                let count = chargebacks.entry(record.client).or_default();
+                *count = count.saturating_add(1);
+            }
+        }
+        for account in accounts.values() {
+            let expected = chargebacks.get(&account.id()).copied().unwrap_or(0);
+            if account.chargebacks() != expected {
+                return Err(SnapshotError::Invalid(
+                    "chargeback count doesn't match the charged back deposits",
+                ));
+            }
+            if self.config.unfreeze_on_represent
+                && account.frozen_by() == Some(FreezeReason::Chargeback)
+                && expected == 0
+            {
+                return Err(SnapshotError::Invalid(
+                    "account frozen by a chargeback without charged back deposits",
+                ));
+            }
+        }
+
+        let is_record = |tx| deposits.contains_key(tx) || withdrawals.contains_key(tx);
+        if !self.dispute_counts.keys().all(is_record) {
+            return Err(SnapshotError::Invalid(
+                "dispute count of an unknown transaction",
+            ));
+        }
+        if !self
+            .deposit_metadata
+            .keys()
+            .all(|tx| deposits.contains_key(tx))
+        {
+            return Err(SnapshotError::Invalid("metadata of an unknown deposit"));
+        }
+        Ok(())
+    }
+
+    /// Check the held and reserved funds of every account are the disputed portions
+    /// of its records and the amounts of its pending authorizations.
+    fn check_balances(&self) -> Result<(), SnapshotError> {
+        let out_of_range = || SnapshotError::Invalid("held or reserved funds out of range");
+        let mut expected: HashMap<(ClientId, Asset), (Amount, Amount)> = HashMap::new();
+        for record in self
+            .txs
+            .deposits
+            .values()
+            .chain(self.txs.withdrawals.values())
+        {
+            let (held, _) = expected.entry((record.client, record.asset)).or_default();
+            *held = held.checked_add(record.disputed).ok_or_else(out_of_range)?;
+        }
+        for record in self.txs.authorizations.values() {
+            if record.state == AuthorizationState::Pending {
+                let (_, reserved) = expected.entry((record.client, record.asset)).or_default();
+                *reserved = reserved
+                    .checked_add(record.amount)
+                    .ok_or_else(out_of_range)?;
+            }
+        }
+
+        let mismatch = SnapshotError::Invalid("held or reserved funds don't match the records");
+        for account in self.clients.accounts.values() {
+            for (asset, balance) in account.balances() {
+                let (held, reserved) = expected.remove(&(account.id(), asset)).unwrap_or_default();
+                if balance.held() != held || balance.reserved() != reserved {
+                    return Err(mismatch);
+                }
+            }
+        }
+        // Records of clients without a balance in their asset hold nothing
+        let zero = (Amount::default(), Amount::default());
+        if expected.values().any(|funds| *funds != zero) {
+            return Err(mismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Check the disputed and charged back portions of a record are within its amount,
+/// and its state is the one they give.
+fn check_record(record: &DepositRecord) -> Result<(), SnapshotError> {
+    let zero = Amount::default();
+    let used = record.disputed.checked_add(record.charged_back);
+    if record.disputed < zero
+        || record.charged_back < zero
+        || used.is_none_or(|used| used > record.amount)
+    {
+        return Err(SnapshotError::Invalid("record amounts out of range"));
+    }
+    // The state follows the portions, see `DepositRecord::set_disputed`
+    let mut expected = record.clone();
+    expected.set_disputed(record.disputed);
+    if expected.state != record.state {
+        return Err(SnapshotError::Invalid(
+            "record state doesn't match its amounts",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::test_utils::*;
+    use crate::model::DepositState;
+    use crate::{Amount, Precision, RoundingMode};
+
+    fn config() -> EngineConfig {
+        EngineConfig {
+            retain_deposit_metadata: true,
+            max_redisputes: Some(1),
+            ..EngineConfig::default()
+        }
+    }
+
+    /// An engine with accounts in every state, records, IDs and metadata.
+    fn engine() -> Engine {
+        let mut engine = Engine::with_config(config());
+        engine.apply(recorded(1, 1, 100)).unwrap();
+        engine.apply(recorded_asset(1, 2, EUR, 50)).unwrap();
+        engine.apply(recorded(2, 3, 70)).unwrap();
+        engine.apply(recorded(3, 4, 20)).unwrap();
+        engine.apply(dispute(1, 2)).unwrap();
+        engine.apply(dispute(3, 4)).unwrap();
+        engine.apply(chargeback(3, 4)).unwrap();
+        engine.apply(authorize(2, 5, 30)).unwrap();
+        engine.apply(transfer(2, 1, 6, 10)).unwrap();
+        engine.txs.evicted.insert(7);
+        engine
+    }
+
+    #[test]
+    fn round_trip() {
+        let engine = engine();
+        let bytes = snapshot(&engine);
+        let restored = Engine::restore(config(), bytes.as_slice()).unwrap();
+
+        // Same state gives the same bytes
+        assert_eq!(snapshot(&restored), bytes);
+        assert_eq!(restored.clock, engine.clock);
+        let account = restored.get_client(3).unwrap().unwrap();
+        assert!(account.is_frozen());
+        assert_eq!(account.chargebacks(), 1);
+        assert_eq!(
+            restored.get_client(2).unwrap().unwrap().reserved(USD),
+            Amount::from_scaled(30)
+        );
+        assert_eq!(restored.deposit_metadata(1).unwrap()["ref"], "INV-1");
+    }
+
+    #[test]
+    fn restored_engine_continues_processing() {
+        let mut engine = engine();
+        let mut restored = Engine::restore(config(), snapshot(&engine).as_slice()).unwrap();
+
+        for tx in [
+            // Duplicate of a transfer, and of an evicted record
+            recorded(1, 6, 10),
+            recorded(1, 7, 10),
+            // Limited re-dispute, resolve of an open dispute
+            dispute(3, 4),
+            chargeback(1, 2),
+            recorded(1, 8, 10),
+        ] {
+            assert_eq!(
+                restored.apply(tx.clone()).map_err(|e| e.to_string()),
+                engine.apply(tx).map_err(|e| e.to_string())
+            );
+        }
+        assert_eq!(snapshot(&restored), snapshot(&engine));
+    }
+
+    #[test]
+    fn rejects_invalid_snapshots() {
+        let bytes = snapshot(&engine());
+
+        assert!(matches!(
+            Engine::restore(config(), &b"TXSNAPSX"[..]),
+            Err(SnapshotError::InvalidMagic)
+        ));
+
+        let mut version = bytes.clone();
+        version[8] = 4;
+        assert!(matches!(
+            Engine::restore(config(), version.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(4))
+        ));
+
+        assert!(matches!(
+            Engine::restore(config(), &bytes[..20]),
+            Err(SnapshotError::Io(_))
+        ));
+        assert!(matches!(
+            Engine::restore(config(), &bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Invalid("truncated string"))
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            Engine::restore(config(), trailing.as_slice()),
+            Err(SnapshotError::Invalid("trailing data"))
+        ));
+    }
+
+    #[test]
+    fn rejects_inconsistent_state() {
+        let invalid = |engine: &Engine| match Engine::restore(config(), snapshot(engine).as_slice())
+        {
+            Err(SnapshotError::Invalid(reason)) => reason,
+            _ => panic!("expected an invalid snapshot"),
+        };
+
+        let mut negative = engine();
+        let account = negative.clients.accounts.get_mut(&2).unwrap();
+        account.remove_held(USD, Amount::from_scaled(5)).unwrap();
+        assert_eq!(invalid(&negative), "balance out of range");
+
+        let mut held = engine();
+        let account = held.clients.accounts.get_mut(&1).unwrap();
+        account.hold(EUR, Amount::from_scaled(10)).unwrap();
+        assert_eq!(
+            invalid(&held),
+            "held or reserved funds don't match the records"
+        );
+
+        let mut reserved = engine();
+        let account = reserved.clients.accounts.get_mut(&2).unwrap();
+        account.unreserve(USD, Amount::from_scaled(30)).unwrap();
+        assert_eq!(
+            invalid(&reserved),
+            "held or reserved funds don't match the records"
+        );
+
+        let mut state = engine();
+        state.txs.deposits.get_mut(&2).unwrap().state = DepositState::Ok;
+        assert_eq!(invalid(&state), "record state doesn't match its amounts");
+
+        let mut disputed = engine();
+        let record = disputed.txs.deposits.get_mut(&1).unwrap();
+        record.set_disputed(Amount::from_scaled(record.amount.scaled() + 1));
+        assert_eq!(invalid(&disputed), "record amounts out of range");
+
+        let duplicate = "transaction ID stored more than once";
+        let mut authorized = engine();
+        let authorization = authorized.txs.authorizations[&5].clone();
+        authorized.txs.authorizations.insert(1, authorization);
+        assert_eq!(invalid(&authorized), duplicate);
+
+        let mut evicted = engine();
+        evicted.txs.evicted.insert(1);
+        assert_eq!(invalid(&evicted), duplicate);
+
+        let mut kept = engine();
+        kept.txs.evicted.insert(6);
+        assert_eq!(invalid(&kept), duplicate);
+
+        let mut withdrawn = engine();
+        let record = withdrawn.txs.deposits[&3].clone();
+        withdrawn.txs.withdrawals.insert(3, record);
+        assert_eq!(invalid(&withdrawn), duplicate);
+    }
+
+    #[test]
+    fn rejects_dangling_references() {
+        let invalid = |config: EngineConfig, engine: &Engine| match Engine::restore(
+            config,
+            snapshot(engine).as_slice(),
+        ) {
+            Err(SnapshotError::Invalid(reason)) => reason,
+            _ => panic!("expected an invalid snapshot"),
+        };
+
+        // Accounts of the records and authorizations, without funds in their asset
+        let without_account = "record of a client without an account";
+        let mut deposited = engine();
+        let record = DepositRecord::new(9, USD, Amount::from_scaled(10), 0);
+        deposited.txs.deposits.insert(10, record);
+        assert_eq!(invalid(config(), &deposited), without_account);
+
+        let mut authorized = engine();
+        let mut authorization = AuthorizationRecord::new(9, USD, Amount::from_scaled(10));
+        authorization.state = AuthorizationState::Captured;
+        authorized.txs.authorizations.insert(10, authorization);
+        assert_eq!(invalid(config(), &authorized), without_account);
+
+        let mut uncounted = engine();
+        uncounted
+            .clients
+            .accounts
+            .get_mut(&3)
+            .unwrap()
+            .remove_chargeback();
+        assert_eq!(
+            invalid(config(), &uncounted),
+            "chargeback count doesn't match the charged back deposits"
+        );
+
+        let mut counted = engine();
+        counted
+            .clients
+            .accounts
+            .get_mut(&1)
+            .unwrap()
+            .add_chargeback();
+        assert_eq!(
+            invalid(config(), &counted),
+            "chargeback count doesn't match the charged back deposits"
+        );
+
+        // Only stuck if re-presentments unfreeze accounts
+        let mut frozen = engine();
+        let account = frozen.clients.accounts.get_mut(&1).unwrap();
+        account.freeze(FreezeReason::Chargeback);
+        Engine::restore(config(), snapshot(&frozen).as_slice()).unwrap();
+        let unfreezing = EngineConfig {
+            unfreeze_on_represent: true,
+            ..config()
+        };
+        assert_eq!(
+            invalid(unfreezing, &frozen),
+            "account frozen by a chargeback without charged back deposits"
+        );
+
+        let mut counts = engine();
+        counts.dispute_counts.insert(6, 1);
+        assert_eq!(
+            invalid(config(), &counts),
+            "dispute count of an unknown transaction"
+        );
+
+        let mut metadata = engine();
+        metadata.deposit_metadata.insert(6, Metadata::new());
+        assert_eq!(
+            invalid(config(), &metadata),
+            "metadata of an unknown deposit"
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_authorizations() {
+        // Authorizations 5 and 9, then 9 overwritten with 5 in the snapshot
+        let mut engine = engine();
+        let authorization = engine.txs.authorizations[&5].clone();
+        engine.txs.authorizations.insert(9, authorization);
+        let mut bytes = snapshot(&engine);
+        let id = bytes
+            .windows(16)
+            .position(|w| w[..8] == 9u64.to_le_bytes() && w[8..] == 2u64.to_le_bytes())
+            .unwrap();
+        bytes[id..id + 8].copy_from_slice(&5u64.to_le_bytes());
+
+        assert!(matches!(
+            Engine::restore(config(), bytes.as_slice()),
+            Err(SnapshotError::Invalid("duplicate authorization"))
+        ));
+    }
+
+    #[test]
+    fn rejects_precision_change() {
+        let bytes = snapshot(&engine());
+        let config = EngineConfig {
+            asset_precision: HashMap::from([(
+                EUR,
+                Precision::new(2, RoundingMode::Exact).unwrap(),
+            )]),
+            ..config()
+        };
+
+        assert!(matches!(
+            Engine::restore(config, bytes.as_slice()),
+            Err(SnapshotError::PrecisionMismatch(EUR, 4, 2))
+        ));
+    }
+}
@@ -1,21 +1,88 @@
 //! Client account state.
 
+use std::collections::BTreeMap;
+
+use super::error::BalanceOverflow;
 use crate::Amount;
-use crate::model::ClientId;
+use crate::model::{Asset, ClientId};
+
+/// Available, held and reserved funds of one asset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// Funds available for withdrawal/trading.
+    available: Amount,
+    /// Funds held due to a dispute.
+    held: Amount,
+    /// Funds reserved by pending withdrawal authorizations.
+    reserved: Amount,
+}
 
-/// A client's account with available and held balances.
+impl Balance {
+    /// Returns the available balance.
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    /// Returns the held balance.
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    /// Returns the reserved balance.
+    pub fn reserved(&self) -> Amount {
+        self.reserved
+    }
+
+    /// Total funds (available + held + reserved).
+    pub fn total(&self) -> Amount {
+        self.checked_total()
+            .expect("balances are only built with a representable total")
+    }
+
+    /// Create a balance from its parts, as saved in a snapshot.
+    ///
+    /// Returns `None` if held or reserved funds are negative, or the total is out
+    /// of range.
+    pub(crate) fn from_parts(available: Amount, held: Amount, reserved: Amount) -> Option<Self> {
+        let balance = Self {
+            available,
+            held,
+            reserved,
+        };
+        let zero = Amount::default();
+        (held >= zero && reserved >= zero && balance.checked_total().is_some()).then_some(balance)
+    }
+
+    /// Returns the total if representable.
+    fn checked_total(&self) -> Option<Amount> {
+        self.available
+            .checked_add(self.held)?
+            .checked_add(self.reserved)
+    }
+}
+
+/// Why an account was frozen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreezeReason {
+    /// A deposit was charged back, the freeze can be lifted by re-presenting it.
+    Chargeback,
+    /// An operator locked the account, only an admin unlock lifts the freeze.
+    Admin,
+}
+
+/// A client's account with available, held and reserved balances per asset.
 ///
 /// Accounts can be frozen/locked after a chargeback, preventing further transactions.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ClientAccount {
     /// The client identifier.
     id: ClientId,
-    /// Funds available for withdrawal/trading.
-    available: Amount,
-    /// Funds held due to a dispute.
-    held: Amount,
-    /// Whether the account is frozen (no deposits or withdrawals allowed).
-    frozen: bool,
+    /// Balances per asset, sorted by asset code.
+    balances: BTreeMap<Asset, Balance>,
+    /// Why the account is frozen (no deposits or withdrawals allowed), if it is.
+    frozen: Option<FreezeReason>,
+    /// Number of deposits with a charged back portion, not re-presented.
+    chargebacks: u32,
 }
 
 impl ClientAccount {
@@ -23,9 +90,24 @@ impl ClientAccount {
     pub fn new(id: ClientId) -> Self {
         Self {
             id,
-            available: Amount::default(),
-            held: Amount::default(),
-            frozen: false,
+            balances: BTreeMap::new(),
+            frozen: None,
+            chargebacks: 0,
+        }
+    }
+
+    /// Create an account from its parts, as saved in a snapshot.
+    pub(crate) fn from_parts(
+        id: ClientId,
+        balances: BTreeMap<Asset, Balance>,
+        frozen: Option<FreezeReason>,
+        chargebacks: u32,
+    ) -> Self {
+        Self {
+            id,
+            balances,
+            frozen,
+            chargebacks,
         }
     }
 
@@ -36,63 +118,198 @@ impl ClientAccount {
         self.id
     }
 
-    /// Returns the available balance.
-    pub fn available(&self) -> Amount {
-        self.available
+    /// Returns the balance of an asset (zero if the client never held it).
+    pub fn balance(&self, asset: Asset) -> Balance {
+        self.balances.get(&asset).copied().unwrap_or_default()
     }
 
-    /// Returns the held balance.
-    pub fn held(&self) -> Amount {
-        self.held
+    /// Returns the balances of every asset the client holds, sorted by asset code.
+    pub fn balances(&self) -> impl Iterator<Item = (Asset, &Balance)> + '_ {
+        self.balances
+            .iter()
+            .map(|(asset, balance)| (*asset, balance))
+    }
+
+    /// Returns the available balance of an asset.
+    pub fn available(&self, asset: Asset) -> Amount {
+        self.balance(asset).available()
+    }
+
+    /// Returns the held balance of an asset.
+    pub fn held(&self, asset: Asset) -> Amount {
+        self.balance(asset).held()
+    }
+
+    /// Returns the reserved balance of an asset.
+    pub fn reserved(&self, asset: Asset) -> Amount {
+        self.balance(asset).reserved()
+    }
+
+    /// Total funds of an asset (available + held + reserved).
+    pub fn total(&self, asset: Asset) -> Amount {
+        self.balance(asset).total()
     }
 
     /// Returns whether the account is frozen.
     pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    /// Returns why the account is frozen, if it is.
+    pub fn frozen_by(&self) -> Option<FreezeReason> {
         self.frozen
     }
 
-    /// Total funds (available + held).
-    pub fn total(&self) -> Amount {
-        self.available + self.held
+    /// Returns the number of deposits with a charged back portion, not re-presented.
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
     }
 
     // Mutations
+    //
+    // Balance updates are overflow-checked: on error the account is left unchanged.
 
     /// Credit funds to available balance.
-    pub fn credit(&mut self, amount: Amount) {
-        self.available += amount;
+    pub fn credit(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                available: balance.available.checked_add(amount)?,
+                ..balance
+            })
+        })
     }
 
     /// Debit funds from available balance.
-    pub fn debit(&mut self, amount: Amount) {
-        self.available -= amount;
+    pub fn debit(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                available: balance.available.checked_sub(amount)?,
+                ..balance
+            })
+        })
     }
 
     /// Hold funds: move from available to held.
-    pub fn hold(&mut self, amount: Amount) {
-        self.available -= amount;
-        self.held += amount;
+    pub fn hold(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                available: balance.available.checked_sub(amount)?,
+                held: balance.held.checked_add(amount)?,
+                ..balance
+            })
+        })
     }
 
     /// Release funds: move from held back to available.
-    pub fn release(&mut self, amount: Amount) {
-        self.held -= amount;
-        self.available += amount;
+    pub fn release(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                available: balance.available.checked_add(amount)?,
+                held: balance.held.checked_sub(amount)?,
+                ..balance
+            })
+        })
+    }
+
+    /// Add held funds (for disputed withdrawals).
+    pub fn add_held(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                held: balance.held.checked_add(amount)?,
+                ..balance
+            })
+        })
     }
 
     /// Remove held funds (for chargeback).
-    pub fn remove_held(&mut self, amount: Amount) {
-        self.held -= amount;
+    pub fn remove_held(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                held: balance.held.checked_sub(amount)?,
+                ..balance
+            })
+        })
+    }
+
+    /// Reserve funds: move from available to reserved (for withdrawal authorization).
+    pub fn reserve(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                available: balance.available.checked_sub(amount)?,
+                reserved: balance.reserved.checked_add(amount)?,
+                ..balance
+            })
+        })
+    }
+
+    /// Unreserve funds: move from reserved back to available (for void).
+    pub fn unreserve(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                available: balance.available.checked_add(amount)?,
+                reserved: balance.reserved.checked_sub(amount)?,
+                ..balance
+            })
+        })
+    }
+
+    /// Remove reserved funds (for capture).
+    pub fn remove_reserved(&mut self, asset: Asset, amount: Amount) -> Result<(), BalanceOverflow> {
+        self.update(asset, |balance| {
+            Some(Balance {
+                reserved: balance.reserved.checked_sub(amount)?,
+                ..balance
+            })
+        })
+    }
+
+    /// Returns the balance of an asset as stored, `None` if the asset was never used.
+    pub(crate) fn stored_balance(&self, asset: Asset) -> Option<Balance> {
+        self.balances.get(&asset).copied()
+    }
+
+    /// Restore a balance returned by [`ClientAccount::stored_balance`], undoing the
+    /// updates made since.
+    pub(crate) fn restore_balance(&mut self, asset: Asset, balance: Option<Balance>) {
+        match balance {
+            Some(balance) => self.balances.insert(asset, balance),
+            None => self.balances.remove(&asset),
+        };
+    }
+
+    /// Commit the new balance, only if every update is valid and its total is representable.
+    fn update(
+        &mut self,
+        asset: Asset,
+        f: impl FnOnce(Balance) -> Option<Balance>,
+    ) -> Result<(), BalanceOverflow> {
+        match f(self.balance(asset)) {
+            Some(balance) if balance.checked_total().is_some() => {
+                self.balances.insert(asset, balance);
+                Ok(())
+            }
+            _ => Err(BalanceOverflow(self.id)),
+        }
     }
 
     /// Freeze the account, preventing further transactions.
-    pub fn freeze(&mut self) {
-        self.frozen = true;
+    pub fn freeze(&mut self, reason: FreezeReason) {
+        self.frozen = Some(reason);
     }
 
-    /// Unfreeze the account (for admin).
+    /// Unfreeze the account, whatever froze it.
     pub fn unfreeze(&mut self) {
-        self.frozen = false;
+        self.frozen = None;
+    }
+
+    /// Count a deposit newly charged back.
+    pub fn add_chargeback(&mut self) {
+        self.chargebacks = self.chargebacks.saturating_add(1);
+    }
+
+    /// Uncount a deposit whose chargeback was fully re-presented.
+    pub fn remove_chargeback(&mut self) {
+        self.chargebacks = self.chargebacks.saturating_sub(1);
     }
 }
 
@@ -100,63 +317,157 @@ impl ClientAccount {
 mod tests {
     use super::*;
 
+    const USD: Asset = Asset::new("USD").unwrap();
+    const EUR: Asset = Asset::new("EUR").unwrap();
+
     #[test]
     fn client_account_new() {
         let account = ClientAccount::new(42);
         assert_eq!(account.id(), 42);
-        assert_eq!(account.available(), Amount::default());
-        assert_eq!(account.held(), Amount::default());
+        assert_eq!(account.available(USD), Amount::default());
+        assert_eq!(account.held(USD), Amount::default());
+        assert_eq!(account.balances().count(), 0);
         assert!(!account.is_frozen());
     }
 
     #[test]
     fn client_account_total_sums_available_and_held() {
         let mut account = ClientAccount::new(1);
-        account.credit(Amount::from_scaled(100));
-        account.hold(Amount::from_scaled(50));
+        account.credit(USD, Amount::from_scaled(100)).unwrap();
+        account.hold(USD, Amount::from_scaled(50)).unwrap();
         // available is now 50, held is 50
-        assert_eq!(account.total(), Amount::from_scaled(100));
+        assert_eq!(account.total(USD), Amount::from_scaled(100));
     }
 
     #[test]
     fn credit_and_debit() {
         let mut account = ClientAccount::new(1);
-        account.credit(Amount::from_scaled(100));
-        assert_eq!(account.available(), Amount::from_scaled(100));
-        account.debit(Amount::from_scaled(30));
-        assert_eq!(account.available(), Amount::from_scaled(70));
+        account.credit(USD, Amount::from_scaled(100)).unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(100));
+        account.debit(USD, Amount::from_scaled(30)).unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(70));
     }
 
     #[test]
     fn hold_and_release() {
         let mut account = ClientAccount::new(1);
-        account.credit(Amount::from_scaled(100));
-        account.hold(Amount::from_scaled(40));
-        assert_eq!(account.available(), Amount::from_scaled(60));
-        assert_eq!(account.held(), Amount::from_scaled(40));
+        account.credit(USD, Amount::from_scaled(100)).unwrap();
+        account.hold(USD, Amount::from_scaled(40)).unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(60));
+        assert_eq!(account.held(USD), Amount::from_scaled(40));
 
-        account.release(Amount::from_scaled(40));
-        assert_eq!(account.available(), Amount::from_scaled(100));
-        assert_eq!(account.held(), Amount::from_scaled(0));
+        account.release(USD, Amount::from_scaled(40)).unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(100));
+        assert_eq!(account.held(USD), Amount::from_scaled(0));
     }
 
     #[test]
     fn remove_held() {
         let mut account = ClientAccount::new(1);
-        account.credit(Amount::from_scaled(100));
-        account.hold(Amount::from_scaled(100));
-        account.remove_held(Amount::from_scaled(100));
-        assert_eq!(account.held(), Amount::from_scaled(0));
-        assert_eq!(account.available(), Amount::from_scaled(0));
+        account.credit(USD, Amount::from_scaled(100)).unwrap();
+        account.hold(USD, Amount::from_scaled(100)).unwrap();
+        account.remove_held(USD, Amount::from_scaled(100)).unwrap();
+        assert_eq!(account.held(USD), Amount::from_scaled(0));
+        assert_eq!(account.available(USD), Amount::from_scaled(0));
+    }
+
+    #[test]
+    fn reserve_unreserve_and_remove_reserved() {
+        let mut account = ClientAccount::new(1);
+        account.credit(USD, Amount::from_scaled(100)).unwrap();
+        account.reserve(USD, Amount::from_scaled(60)).unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(40));
+        assert_eq!(account.reserved(USD), Amount::from_scaled(60));
+        assert_eq!(account.total(USD), Amount::from_scaled(100));
+
+        account.unreserve(USD, Amount::from_scaled(20)).unwrap();
+        account
+            .remove_reserved(USD, Amount::from_scaled(40))
+            .unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(60));
+        assert_eq!(account.reserved(USD), Amount::from_scaled(0));
+        assert_eq!(account.total(USD), Amount::from_scaled(60));
+    }
+
+    #[test]
+    fn add_held() {
+        let mut account = ClientAccount::new(1);
+        account.add_held(USD, Amount::from_scaled(40)).unwrap();
+        assert_eq!(account.held(USD), Amount::from_scaled(40));
+        assert_eq!(account.available(USD), Amount::from_scaled(0));
+        assert_eq!(account.total(USD), Amount::from_scaled(40));
+    }
+
+    #[test]
+    fn assets_are_independent() {
+        let mut account = ClientAccount::new(1);
+        account.credit(USD, Amount::from_scaled(100)).unwrap();
+        account.credit(EUR, Amount::from_scaled(30)).unwrap();
+        account.hold(EUR, Amount::from_scaled(10)).unwrap();
+
+        assert_eq!(account.available(USD), Amount::from_scaled(100));
+        assert_eq!(account.held(USD), Amount::from_scaled(0));
+        assert_eq!(account.available(EUR), Amount::from_scaled(20));
+        assert_eq!(account.held(EUR), Amount::from_scaled(10));
+
+        // Sorted by asset code
+        let assets: Vec<_> = account.balances().map(|(asset, _)| asset).collect();
+        assert_eq!(assets, [EUR, USD]);
+    }
+
+    #[test]
+    fn overflow_leaves_account_unchanged() {
+        let mut account = ClientAccount::new(1);
+        account.credit(USD, Amount::from_scaled(i64::MAX)).unwrap();
+
+        assert_eq!(
+            account.credit(USD, Amount::from_scaled(1)),
+            Err(BalanceOverflow(1))
+        );
+        assert_eq!(account.available(USD), Amount::from_scaled(i64::MAX));
+
+        // Moving funds keeps the total, but a negative release would overflow held.
+        account.hold(USD, Amount::from_scaled(i64::MAX)).unwrap();
+        assert_eq!(
+            account.release(USD, Amount::from_scaled(-1)).unwrap_err(),
+            BalanceOverflow(1)
+        );
+        assert_eq!(account.held(USD), Amount::from_scaled(i64::MAX));
+        assert_eq!(account.available(USD), Amount::from_scaled(0));
+
+        // Other assets are not affected by the overflow
+        account.credit(EUR, Amount::from_scaled(1)).unwrap();
+    }
+
+    #[test]
+    fn total_overflow_is_rejected() {
+        let mut account = ClientAccount::new(1);
+        account.credit(USD, Amount::from_scaled(i64::MAX)).unwrap();
+        account.hold(USD, Amount::from_scaled(i64::MAX)).unwrap();
+
+        // available alone could take it, but available + held would not fit
+        assert!(account.credit(USD, Amount::from_scaled(1)).is_err());
+        assert_eq!(account.total(USD), Amount::from_scaled(i64::MAX));
+    }
+
+    #[test]
+    fn balance_from_parts_is_checked() {
+        let amount = Amount::from_scaled;
+        assert!(Balance::from_parts(amount(-5), amount(10), amount(0)).is_some());
+        assert!(Balance::from_parts(amount(5), amount(-1), amount(0)).is_none());
+        assert!(Balance::from_parts(amount(5), amount(0), amount(-1)).is_none());
+        assert!(Balance::from_parts(amount(i64::MAX), amount(1), amount(0)).is_none());
     }
 
     #[test]
     fn freeze_and_unfreeze() {
         let mut account = ClientAccount::new(1);
         assert!(!account.is_frozen());
-        account.freeze();
+        account.freeze(FreezeReason::Chargeback);
         assert!(account.is_frozen());
+        assert_eq!(account.frozen_by(), Some(FreezeReason::Chargeback));
         account.unfreeze();
         assert!(!account.is_frozen());
+        assert_eq!(account.frozen_by(), None);
     }
 }
@@ -0,0 +1,371 @@
+//! Storage backends for client accounts and transaction records.
+//!
+//! The engine is generic over an [`AccountStore`] and a [`TxStore`], the in-memory
+//! [`MemoryAccountStore`] and [`MemoryTxStore`] are used by default. Records are
+//! updated in place through the references returned by a store: a backend keeping
+//! them elsewhere than in memory must write them back itself (e.g. when they leave
+//! a cache).
+//!
+//! Every operation can fail with a [`StoreError`], which rejects the transaction
+//! being applied. The engine looks records up before changing any state, and undoes
+//! its in-memory changes if storing a new record fails, so a failing store leaves
+//! the engine state as it was.
+
+use std::collections::{HashMap, HashSet};
+
+use super::StoreError;
+use super::state::ClientAccount;
+use crate::model::{AuthorizationRecord, ClientId, DepositRecord, TxId};
+
+/// Storage of client accounts.
+pub trait AccountStore {
+    /// Return an account, if it exists.
+    fn get(&self, client: ClientId) -> Result<Option<&ClientAccount>, StoreError>;
+
+    /// Return an account to update, if it exists.
+    fn get_mut(&mut self, client: ClientId) -> Result<Option<&mut ClientAccount>, StoreError>;
+
+    /// Return an account to update, creating an empty one if it doesn't exist.
+    fn get_or_create(&mut self, client: ClientId) -> Result<&mut ClientAccount, StoreError>;
+
+    /// Return two distinct accounts to update, if they exist.
+    ///
+    /// # Panics
+    ///
+    /// May panic if both IDs are the same.
+    fn get_pair_mut(
+        &mut self,
+        first: ClientId,
+        second: ClientId,
+    ) -> Result<[Option<&mut ClientAccount>; 2], StoreError>;
+
+    /// Remove an account, if it exists (e.g. to roll back its creation).
+    fn remove(&mut self, client: ClientId) -> Result<(), StoreError>;
+
+    /// Return all accounts, in no particular order.
+    fn iter(&self) -> impl Iterator<Item = &ClientAccount> + '_;
+}
+
+/// Kind of a disputable transaction record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Deposit,
+    /// Only stored when withdrawals are disputable.
+    Withdrawal,
+}
+
+/// Storage of transaction records and IDs.
+///
+/// Every stored transaction ID is unique across deposits, withdrawals, withdrawal
+/// authorizations and the transactions stored by ID only.
+pub trait TxStore {
+    /// Whether a transaction with this ID was stored, including evicted records.
+    fn contains(&self, tx: TxId) -> Result<bool, StoreError>;
+
+    /// Store the ID of a transaction that has no record (e.g. transfers), for
+    /// duplicate checking only.
+    fn insert_id(&mut self, tx: TxId) -> Result<(), StoreError>;
+
+    /// Store the record of a disputable transaction.
+    fn insert_record(
+        &mut self,
+        tx: TxId,
+        kind: RecordKind,
+        record: DepositRecord,
+    ) -> Result<(), StoreError>;
+
+    /// Return a disputable transaction record to update, and its kind.
+    fn record_mut(
+        &mut self,
+        tx: TxId,
+    ) -> Result<Option<(RecordKind, &mut DepositRecord)>, StoreError>;
+
+    /// Store a withdrawal authorization.
+    fn insert_authorization(
+        &mut self,
+        tx: TxId,
+        record: AuthorizationRecord,
+    ) -> Result<(), StoreError>;
+
+    /// Return a withdrawal authorization to update.
+    fn authorization_mut(
+        &mut self,
+        tx: TxId,
+    ) -> Result<Option<&mut AuthorizationRecord>, StoreError>;
+
+    /// Remove a transaction ID with its record or authorization, if stored (e.g. to
+    /// roll back a batch, see [`Engine::apply_batch`](super::Engine::apply_batch)).
+    fn remove(&mut self, tx: TxId) -> Result<(), StoreError>;
+
+    /// Whether the record of this transaction was evicted.
+    fn is_evicted(&self, tx: TxId) -> Result<bool, StoreError>;
+
+    /// Evict the disputable transaction records for which `evict` returns `true`,
+    /// keeping their IDs for duplicate checking. Returns the evicted IDs.
+    fn evict(
+        &mut self,
+        evict: &mut dyn FnMut(&DepositRecord) -> bool,
+    ) -> Result<Vec<TxId>, StoreError>;
+}
+
+/// In-memory [`AccountStore`], the default.
+#[derive(Debug, Default)]
+pub struct MemoryAccountStore {
+    pub(super) accounts: HashMap<ClientId, ClientAccount>,
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn get(&self, client: ClientId) -> Result<Option<&ClientAccount>, StoreError> {
+        Ok(self.accounts.get(&client))
+    }
+
+    fn get_mut(&mut self, client: ClientId) -> Result<Option<&mut ClientAccount>, StoreError> {
+        Ok(self.accounts.get_mut(&client))
+    }
+
+    fn get_or_create(&mut self, client: ClientId) -> Result<&mut ClientAccount, StoreError> {
+        Ok(self
+            .accounts
+            .entry(client)
+            .or_insert_with(|| ClientAccount::new(client)))
+    }
+
+    fn get_pair_mut(
+        &mut self,
+        first: ClientId,
+        second: ClientId,
+    ) -> Result<[Option<&mut ClientAccount>; 2], StoreError> {
+        Ok(self.accounts.get_disjoint_mut([&first, &second]))
+    }
+
+    fn remove(&mut self, client: ClientId) -> Result<(), StoreError> {
+        self.accounts.remove(&client);
+        Ok(())
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &ClientAccount> + '_ {
+        self.accounts.values()
+    }
+}
+
+/// In-memory [`TxStore`], the default.
+#[derive(Debug, Default)]
+pub struct MemoryTxStore {
+    /// Deposit records for dispute tracking (chargedback deposits are retained)
+    pub(super) deposits: HashMap<TxId, DepositRecord>,
+    /// Withdrawal records, only stored when withdrawals are disputable
+    pub(super) withdrawals: HashMap<TxId, DepositRecord>,
+    /// Withdrawal authorizations, kept in their final state
+    pub(super) authorizations: HashMap<TxId, AuthorizationRecord>,
+    /// IDs of transactions without record, for duplicate checking only
+    pub(super) ids: HashSet<TxId>,
+    /// IDs of evicted records, for duplicate checking only
+    pub(super) evicted: HashSet<TxId>,
+}
+
+impl MemoryTxStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TxStore for MemoryTxStore {
+    fn contains(&self, tx: TxId) -> Result<bool, StoreError> {
+        Ok(self.deposits.contains_key(&tx)
+            || self.withdrawals.contains_key(&tx)
+            || self.authorizations.contains_key(&tx)
+            || self.ids.contains(&tx)
+            || self.evicted.contains(&tx))
+    }
+
+    fn insert_id(&mut self, tx: TxId) -> Result<(), StoreError> {
+        self.ids.insert(tx);
+        Ok(())
+    }
+
+    fn insert_record(
+        &mut self,
+        tx: TxId,
+        kind: RecordKind,
+        record: DepositRecord,
+    ) -> Result<(), StoreError> {
+        match kind {
+            RecordKind::Deposit => self.deposits.insert(tx, record),
+            RecordKind::Withdrawal => self.withdrawals.insert(tx, record),
+        };
+        Ok(())
+    }
+
+    fn record_mut(
+        &mut self,
+        tx: TxId,
+    ) -> Result<Option<(RecordKind, &mut DepositRecord)>, StoreError> {
+        Ok(match self.deposits.get_mut(&tx) {
+            Some(record) => Some((RecordKind::Deposit, record)),
+            None => self
+                .withdrawals
+                .get_mut(&tx)
+                .map(|record| (RecordKind::Withdrawal, record)),
+        })
+    }
+
+    fn insert_authorization(
+        &mut self,
+        tx: TxId,
+        record: AuthorizationRecord,
+    ) -> Result<(), StoreError> {
+        self.authorizations.insert(tx, record);
+        Ok(())
+    }
+
+    fn authorization_mut(
+        &mut self,
+        tx: TxId,
+    ) -> Result<Option<&mut AuthorizationRecord>, StoreError> {
+        Ok(self.authorizations.get_mut(&tx))
+    }
+
+    fn remove(&mut self, tx: TxId) -> Result<(), StoreError> {
+        self.deposits.remove(&tx);
+        self.withdrawals.remove(&tx);
+        self.authorizations.remove(&tx);
+        self.ids.remove(&tx);
+        self.evicted.remove(&tx);
+        Ok(())
+    }
+
+    fn is_evicted(&self, tx: TxId) -> Result<bool, StoreError> {
+        Ok(self.evicted.contains(&tx))
+    }
+
+    fn evict(
+        &mut self,
+        evict: &mut dyn FnMut(&DepositRecord) -> bool,
+    ) -> Result<Vec<TxId>, StoreError> {
+        let mut evicted = Vec::new();
+        for records in [&mut self.deposits, &mut self.withdrawals] {
+            records.retain(|tx, record| {
+                let keep = !evict(record);
+                if !keep {
+                    evicted.push(*tx);
+                }
+                keep
+            });
+        }
+        self.evicted.extend(&evicted);
+        Ok(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Asset;
+    use crate::{Amount, model::AuthorizationState};
+
+    const USD: Asset = Asset::new("USD").unwrap();
+
+    fn record(client: ClientId) -> DepositRecord {
+        DepositRecord::new(client, USD, Amount::from_scaled(100), 0)
+    }
+
+    #[test]
+    fn account_store_creates_accounts_once() {
+        let mut store = MemoryAccountStore::new();
+        assert!(store.get(1).unwrap().is_none());
+
+        store
+            .get_or_create(1)
+            .unwrap()
+            .credit(USD, Amount::from_scaled(10))
+            .unwrap();
+        let account = store.get_or_create(1).unwrap();
+        assert_eq!(account.available(USD), Amount::from_scaled(10));
+        assert_eq!(store.iter().count(), 1);
+    }
+
+    #[test]
+    fn account_store_pair() {
+        let mut store = MemoryAccountStore::new();
+        store.get_or_create(1).unwrap();
+
+        let [first, second] = store.get_pair_mut(1, 2).unwrap();
+        assert_eq!(first.unwrap().id(), 1);
+        assert!(second.is_none());
+    }
+
+    #[test]
+    fn tx_store_ids_are_shared() {
+        let mut store = MemoryTxStore::new();
+        store.insert_id(1).unwrap();
+        store
+            .insert_record(2, RecordKind::Deposit, record(1))
+            .unwrap();
+        store
+            .insert_record(3, RecordKind::Withdrawal, record(1))
+            .unwrap();
+        store
+            .insert_authorization(4, AuthorizationRecord::new(1, USD, Amount::from_scaled(5)))
+            .unwrap();
+
+        for tx in 1..=4 {
+            assert!(store.contains(tx).unwrap());
+        }
+        assert!(!store.contains(5).unwrap());
+
+        assert!(store.record_mut(1).unwrap().is_none());
+        assert_eq!(store.record_mut(2).unwrap().unwrap().0, RecordKind::Deposit);
+        assert_eq!(
+            store.record_mut(3).unwrap().unwrap().0,
+            RecordKind::Withdrawal
+        );
+        assert_eq!(
+            store.authorization_mut(4).unwrap().unwrap().state,
+            AuthorizationState::Pending
+        );
+    }
+
+    #[test]
+    fn tx_store_remove_forgets_ids() {
+        let mut store = MemoryTxStore::new();
+        store.insert_id(1).unwrap();
+        store
+            .insert_record(2, RecordKind::Deposit, record(1))
+            .unwrap();
+        store
+            .insert_authorization(3, AuthorizationRecord::new(1, USD, Amount::from_scaled(5)))
+            .unwrap();
+
+        for tx in 1..=3 {
+            store.remove(tx).unwrap();
+            assert!(!store.contains(tx).unwrap());
+        }
+        // Removing an unknown ID does nothing
+        store.remove(4).unwrap();
+    }
+
+    #[test]
+    fn tx_store_evict_keeps_ids() {
+        let mut store = MemoryTxStore::new();
+        store
+            .insert_record(1, RecordKind::Deposit, record(1))
+            .unwrap();
+        store
+            .insert_record(2, RecordKind::Deposit, record(2))
+            .unwrap();
+
+        let evicted = store.evict(&mut |record| record.client == 1).unwrap();
+        assert_eq!(evicted, [1]);
+        assert!(store.record_mut(1).unwrap().is_none());
+        assert!(store.record_mut(2).unwrap().is_some());
+        assert!(store.contains(1).unwrap());
+        assert!(store.is_evicted(1).unwrap());
+        assert!(!store.is_evicted(2).unwrap());
+    }
+}
@@ -0,0 +1,94 @@
+//! Interning of external string identifiers into compact numeric IDs.
+//!
+//! Upstream systems may reference clients and transactions with UUIDs or other
+//! strings. The engine only works with numeric [`ClientId`](crate::ClientId) and
+//! [`TxId`](crate::TxId), so external IDs are mapped to sequential numbers on first
+//! sight, and mapped back when reporting.
+
+use std::collections::HashMap;
+
+/// Bidirectional map between external string IDs and sequential numeric IDs.
+///
+/// IDs are assigned from 1 in order of first appearance, so they stay compact.
+#[derive(Debug, Default, Clone)]
+pub struct IdInterner {
+    ids: HashMap<Box<str>, u64>,
+    external: Vec<Box<str>>,
+}
+
+impl IdInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the ID of an external identifier, assigning the next one if unseen.
+    pub fn intern(&mut self, external: &str) -> u64 {
+        if let Some(&id) = self.ids.get(external) {
+            return id;
+        }
+        self.external.push(external.into());
+        let id = self.external.len() as u64;
+        self.ids.insert(external.into(), id);
+        id
+    }
+
+    /// Returns the ID of an already interned external identifier.
+    pub fn get(&self, external: &str) -> Option<u64> {
+        self.ids.get(external).copied()
+    }
+
+    /// Returns the external identifier an ID was assigned to.
+    pub fn resolve(&self, id: u64) -> Option<&str> {
+        let index = usize::try_from(id.checked_sub(1)?).ok()?;
+        self.external.get(index).map(|external| &**external)
+    }
+
+    /// Number of interned identifiers.
+    pub fn len(&self) -> usize {
+        self.external.len()
+    }
+
+    /// Whether no identifier was interned yet.
+    pub fn is_empty(&self) -> bool {
+        self.external.is_empty()
+    }
+}
+
+/// Interners for both client and transaction external IDs.
+#[derive(Debug, Default, Clone)]
+pub struct ExternalIds {
+    pub clients: IdInterner,
+    pub txs: IdInterner,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intern_assigns_sequential_ids() {
+        let mut interner = IdInterner::new();
+        assert_eq!(interner.intern("a1b2"), 1);
+        assert_eq!(interner.intern("c3d4"), 2);
+        assert_eq!(interner.intern("a1b2"), 1);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn get_and_resolve() {
+        let mut interner = IdInterner::new();
+        let id = interner.intern("550e8400-e29b-41d4-a716-446655440000");
+
+        assert_eq!(
+            interner.get("550e8400-e29b-41d4-a716-446655440000"),
+            Some(id)
+        );
+        assert_eq!(interner.get("unknown"), None);
+        assert_eq!(
+            interner.resolve(id),
+            Some("550e8400-e29b-41d4-a716-446655440000")
+        );
+        assert_eq!(interner.resolve(0), None);
+        assert_eq!(interner.resolve(id + 1), None);
+    }
+}
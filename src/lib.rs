@@ -3,21 +3,27 @@
 //! # Usage
 //!
 //! ```no_run
-//! use txs_eng::{Engine, Transaction, Amount};
+//! use txs_eng::{Amount, Asset, Engine, Metadata, Transaction};
 //!
 //! let mut engine = Engine::new();
 //! engine.apply(Transaction::Deposit {
 //!     client: 1,
 //!     tx: 1,
-//!     amount: Amount::from_float(100.0),
+//!     asset: "USD".parse::<Asset>().unwrap(),
+//!     amount: "100.0".parse::<Amount>().unwrap(),
+//!     timestamp: None,
+//!     metadata: Metadata::default(),
 //! });
 //! ```
 
 pub mod amount;
 pub mod csv;
 pub mod engine;
+pub mod intern;
 pub mod model;
+pub mod rate;
 
-pub use amount::Amount;
-pub use engine::Engine;
-pub use model::{ClientId, Transaction, TxId};
+pub use amount::{Amount, Decimal, ParseAmountError, Precision, RoundingMode};
+pub use engine::{DisputePolicy, Engine, EngineConfig, NegativeAvailablePolicy};
+pub use model::{Asset, ClientId, Metadata, Timestamp, Transaction, TxId};
+pub use rate::{BasisPoints, Rate};
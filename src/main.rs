@@ -1,10 +1,28 @@
-use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::{env, process};
 
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
 use tokio_stream::wrappers::ReceiverStream;
-use tracing::{error, warn};
+use tracing::{error, field, warn};
 use tracing_subscriber::EnvFilter;
-use txs_eng::Engine;
-use txs_eng::csv::{read_transactions, write_accounts};
+use txs_eng::csv::{RowError, read_transactions, read_transactions_interned, write_accounts};
+use txs_eng::engine::{
+    AccountStore, DiskTxStore, Journal, JournalError, MemoryAccountStore, ShardedEngine,
+    SnapshotError, TxStore,
+};
+use txs_eng::intern::ExternalIds;
+use txs_eng::{Engine, EngineConfig, Transaction};
+
+const USAGE: &str = "usage: txs-eng [--intern-ids] [--config <config.toml>] \
+                     [--disk-store <dir> [--cache-records <n>]] \
+                     [--load-snapshot <file>] [--save-snapshot <file>] \
+                     [--replay <journal>] [--journal <journal>] [--shards <n>] \
+                     <transactions.csv>";
+
+/// Records kept in memory by the disk store, unless set with `--cache-records`
+const DEFAULT_CACHE_RECORDS: usize = 1 << 20;
 
 #[tokio::main]
 async fn main() {
@@ -13,42 +31,235 @@ async fn main() {
         .with_writer(std::io::stderr)
         .init();
 
-    let path = env::args()
-        .nth(1)
-        .expect("usage: txs-eng <transactions.csv>");
+    // `--intern-ids`: client and tx columns are opaque strings (e.g. UUIDs)
+    // `--config`: business policies, see `EngineConfig`
+    // `--disk-store`: keep transaction records on disk, see `DiskTxStore`
+    // `--load-snapshot`, `--save-snapshot`: continue from the state of a previous run
+    // `--replay`, `--journal`: rebuild the state from a journal, append the events to one
+    // `--shards`: apply transactions on parallel shards of clients, see `ShardedEngine`
+    let mut intern_ids = false;
+    let mut config_path = None;
+    let mut disk_store = None;
+    let mut cache_records = DEFAULT_CACHE_RECORDS;
+    let mut load_snapshot = None;
+    let mut save_snapshot = None;
+    let mut replay = None;
+    let mut journal = None;
+    let mut shards = None;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--intern-ids" => intern_ids = true,
+            "--config" => config_path = Some(args.next().expect(USAGE)),
+            "--disk-store" => disk_store = Some(args.next().expect(USAGE)),
+            "--cache-records" => {
+                cache_records = args.next().and_then(|n| n.parse().ok()).expect(USAGE)
+            }
+            "--load-snapshot" => load_snapshot = Some(args.next().expect(USAGE)),
+            "--save-snapshot" => save_snapshot = Some(args.next().expect(USAGE)),
+            "--replay" => replay = Some(args.next().expect(USAGE)),
+            "--journal" => journal = Some(args.next().expect(USAGE)),
+            "--shards" => shards = Some(args.next().and_then(|n| n.parse().ok()).expect(USAGE)),
+            _ if path.is_none() => path = Some(arg),
+            _ => panic!("{USAGE}"),
+        }
+    }
+    let path = path.expect(USAGE);
+
+    let config = match config_path.map(EngineConfig::load).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            error!("failed to load config: {e}");
+            process::exit(1);
+        }
+    };
 
     if !path.ends_with(".csv") {
         warn!(path, "input file seems to not be a csv file");
     }
 
-    let mut engine = Engine::new();
-    let (tx_sender, tx_receiver) = tokio::sync::mpsc::channel(16);
+    let snapshots = load_snapshot.is_some() || save_snapshot.is_some();
+    if snapshots && (disk_store.is_some() || intern_ids) {
+        // Records on disk aren't saved, and interned IDs differ between runs
+        error!("snapshots can't be used with --disk-store or --intern-ids");
+        process::exit(1);
+    }
+    if replay.is_some() && intern_ids {
+        error!("a journal can't be replayed with --intern-ids");
+        process::exit(1);
+    }
 
-    tokio::spawn(async move {
-        let transactions = match read_transactions(&path) {
-            Ok(iter) => iter,
-            Err(e) => {
-                error!("failed to open transactions file: {e}");
-                return;
-            }
-        };
-
-        for result in transactions {
-            match result {
-                Ok(tx) => {
-                    if tx_sender.send(tx).await.is_err() {
-                        // Receiver dropped, stop sending
-                        break;
+    if let Some(shards) = shards {
+        if disk_store.is_some() || snapshots || replay.is_some() || journal.is_some() {
+            // Shard engines keep their own in-memory state, without events
+            error!("--shards can't be used with --disk-store, snapshots or journals");
+            process::exit(1);
+        }
+        process_sharded(ShardedEngine::new(config, shards), path, intern_ids).await;
+        return;
+    }
+
+    match disk_store {
+        Some(dir) => {
+            let txs = match DiskTxStore::create(&dir, cache_records) {
+                Ok(txs) => txs,
+                Err(e) => {
+                    error!("failed to create disk store: {e}");
+                    process::exit(1);
+                }
+            };
+            let mut engine = Engine::with_stores(config, MemoryAccountStore::new(), txs);
+            open_journal(&mut engine, replay, journal);
+            process(engine, path, intern_ids).await;
+        }
+        None => {
+            let mut engine = match load_snapshot {
+                Some(snapshot) => match File::open(&snapshot)
+                    .map_err(SnapshotError::from)
+                    .and_then(|file| Engine::restore(config, BufReader::new(file)))
+                {
+                    Ok(engine) => engine,
+                    Err(e) => {
+                        error!(snapshot, "failed to load snapshot: {e}");
+                        process::exit(1);
                     }
+                },
+                None => Engine::with_config(config),
+            };
+            open_journal(&mut engine, replay, journal);
+            let engine = process(engine, path, intern_ids).await;
+
+            if let Some(snapshot) = save_snapshot {
+                let result = File::create(&snapshot)
+                    .map_err(SnapshotError::from)
+                    .and_then(|file| {
+                        let mut writer = BufWriter::new(file);
+                        engine.snapshot(&mut writer)?;
+                        Ok(writer.flush()?)
+                    });
+                if let Err(e) = result {
+                    error!(snapshot, "failed to save snapshot: {e}");
+                    process::exit(1);
                 }
+            }
+        }
+    }
+}
+
+/// Replay a journal, then set the journal new events are appended to, if requested.
+fn open_journal<A: AccountStore, T: TxStore>(
+    engine: &mut Engine<A, T>,
+    replay: Option<String>,
+    journal: Option<String>,
+) {
+    if let Some(replay) = replay {
+        let result = File::open(&replay)
+            .map_err(JournalError::from)
+            .and_then(|file| engine.replay(BufReader::new(file)));
+        if let Err(e) = result {
+            error!(journal = replay, "failed to replay journal: {e}");
+            process::exit(1);
+        }
+    }
+    if let Some(journal) = journal {
+        match Journal::open(&journal) {
+            Ok(opened) => engine.set_journal(opened),
+            Err(e) => {
+                error!(journal, "failed to open journal: {e}");
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Apply the transactions of a file, and write the resulting accounts to stdout.
+///
+/// Exits without output if appending to the journal fails, as the accounts would
+/// only reflect part of the file.
+async fn process<A: AccountStore, T: TxStore>(
+    mut engine: Engine<A, T>,
+    path: String,
+    intern_ids: bool,
+) -> Engine<A, T> {
+    let (transactions, reader) = spawn_reader(path, intern_ids, engine.config().clone());
+    if engine.run(transactions).await.is_err() {
+        // Already logged by the engine
+        process::exit(1);
+    }
+    let ids = reader.await.expect("reader task panicked");
+
+    write_accounts(
+        engine.clients(),
+        engine.config(),
+        ids.as_ref().map(|ids| &ids.clients),
+    );
+    engine
+}
+
+/// Apply the transactions of a file on a sharded engine, and write the resulting
+/// accounts to stdout.
+async fn process_sharded(mut engine: ShardedEngine, path: String, intern_ids: bool) {
+    let (transactions, reader) = spawn_reader(path, intern_ids, engine.config().clone());
+    engine.run(transactions).await;
+    let ids = reader.await.expect("reader task panicked");
+
+    write_accounts(
+        engine.clients(),
+        engine.config(),
+        ids.as_ref().map(|ids| &ids.clients),
+    );
+}
+
+/// Read the transactions of a file on another task, returning their stream, and the
+/// interned IDs once read.
+fn spawn_reader(
+    path: String,
+    intern_ids: bool,
+    config: EngineConfig,
+) -> (ReceiverStream<Transaction>, JoinHandle<Option<ExternalIds>>) {
+    let (tx_sender, tx_receiver) = tokio::sync::mpsc::channel(16);
+
+    let reader = tokio::spawn(async move {
+        if intern_ids {
+            let mut transactions = match read_transactions_interned(&path, &config) {
+                Ok(iter) => iter,
                 Err(e) => {
-                    warn!("{e}");
+                    error!("failed to open transactions file: {e}");
+                    return None;
                 }
+            };
+            forward(transactions.by_ref(), tx_sender).await;
+            Some(transactions.into_ids())
+        } else {
+            match read_transactions(&path, &config) {
+                Ok(transactions) => forward(transactions, tx_sender).await,
+                Err(e) => error!("failed to open transactions file: {e}"),
             }
+            None
         }
     });
 
-    engine.run(ReceiverStream::new(tx_receiver)).await;
+    (ReceiverStream::new(tx_receiver), reader)
+}
 
-    write_accounts(engine.clients());
+/// Send parsed transactions to the engine, logging invalid rows with their metadata.
+async fn forward(
+    transactions: impl Iterator<Item = Result<Transaction, RowError>>,
+    tx_sender: Sender<Transaction>,
+) {
+    for result in transactions {
+        match result {
+            Ok(tx) => {
+                if tx_sender.send(tx).await.is_err() {
+                    // Receiver dropped, stop sending
+                    break;
+                }
+            }
+            Err(e) => {
+                let metadata = (!e.metadata.is_empty()).then(|| field::debug(&e.metadata));
+                warn!(metadata, "{}", e.error);
+            }
+        }
+    }
 }
@@ -1,67 +1,474 @@
 //! Core domain types for the transaction engine.
 
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
 use crate::Amount;
+use crate::amount::Decimal;
 
 /// Client identifier.
-pub type ClientId = u16;
+///
+/// External string identifiers can be mapped to client IDs with
+/// [`IdInterner`](crate::intern::IdInterner).
+pub type ClientId = u64;
 
 /// Transaction identifier.
-pub type TxId = u32;
+///
+/// External string identifiers can be mapped to transaction IDs with
+/// [`IdInterner`](crate::intern::IdInterner).
+pub type TxId = u64;
+
+/// Asset code, such as `USD`, `EUR` or `USDC`.
+///
+/// Codes are 1 to 7 ASCII alphanumeric characters (or `.`, `-`, `_`), stored inline
+/// so the type is `Copy` and stays small in deposit records. Codes are case-sensitive.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Asset([u8; Asset::MAX_LEN]);
+
+impl Asset {
+    /// Maximum length of an asset code.
+    pub const MAX_LEN: usize = 7;
+
+    /// Create an asset from its code, returns `None` if the code is invalid.
+    pub const fn new(code: &str) -> Option<Self> {
+        let bytes = code.as_bytes();
+        if bytes.is_empty() || bytes.len() > Self::MAX_LEN {
+            return None;
+        }
+        let mut inline = [0; Self::MAX_LEN];
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            if !(b.is_ascii_alphanumeric() || b == b'.' || b == b'-' || b == b'_') {
+                return None;
+            }
+            inline[i] = b;
+            i += 1;
+        }
+        Some(Self(inline))
+    }
+
+    /// Returns the code as stored, padded with zero bytes.
+    pub(crate) fn to_bytes(self) -> [u8; Self::MAX_LEN] {
+        self.0
+    }
+
+    /// Create an asset from bytes returned by [`Asset::to_bytes`], returns `None` if
+    /// they are not a valid code.
+    pub(crate) fn from_bytes(bytes: [u8; Self::MAX_LEN]) -> Option<Self> {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(Self::MAX_LEN);
+        let asset = Self::new(std::str::from_utf8(&bytes[..len]).ok()?)?;
+        (asset.0 == bytes).then_some(asset)
+    }
+
+    /// Returns the asset code.
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(Self::MAX_LEN);
+        // Only ASCII bytes are ever stored
+        std::str::from_utf8(&self.0[..len]).expect("asset code is ascii")
+    }
+}
+
+/// Error returned when parsing an invalid [`Asset`] code.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid asset code '{0}'")]
+pub struct ParseAssetError(pub String);
+
+impl FromStr for Asset {
+    type Err = ParseAssetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s).ok_or_else(|| ParseAssetError(s.to_string()))
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Serialized as its code.
+impl Serialize for Asset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Asset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Debug for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Asset({})", self.as_str())
+    }
+}
+
+/// Time of a transaction, in seconds since the Unix epoch.
+pub type Timestamp = u64;
+
+/// Pass-through fields of a transaction (e.g. merchant ID, reference, memo), by name.
+///
+/// Not interpreted by the engine, only carried into logs.
+pub type Metadata = BTreeMap<String, String>;
 
 /// A transaction representing the possible inputs of the engine.
+///
+/// Every transaction has an optional timestamp, transactions without one are
+/// considered to happen at the latest time seen by the engine, and metadata.
 #[derive(Debug, Clone)]
 pub enum Transaction {
     /// Credit funds to a client's available balance.
     Deposit {
         client: ClientId,
         tx: TxId,
+        asset: Asset,
         amount: Amount,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
     },
     /// Debit funds from a client's available balance.
     Withdrawal {
         client: ClientId,
         tx: TxId,
+        asset: Asset,
         amount: Amount,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+    /// Reserve funds for a withdrawal: moves funds from available to reserved.
+    Authorize {
+        client: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+    /// Finalize an authorized withdrawal; removes reserved funds.
+    Capture {
+        client: ClientId,
+        tx: TxId,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+    /// Cancel an authorized withdrawal; moves reserved funds back to available.
+    Void {
+        client: ClientId,
+        tx: TxId,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+    /// Move funds from one client's available balance to another's, atomically.
+    Transfer {
+        from: ClientId,
+        to: ClientId,
+        tx: TxId,
+        asset: Asset,
+        amount: Amount,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+    /// Operator action on a client account, applied regardless of the client.
+    Admin {
+        client: ClientId,
+        tx: TxId,
+        operator: OperatorId,
+        action: AdminAction,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
     },
     /// Claim a deposit was erroneous; moves corresponding funds from available to held.
-    Dispute { client: ClientId, tx: TxId },
+    ///
+    /// Disputes the given part of the deposit, or all of its undisputed remainder. The
+    /// amount is in the asset of the deposit, scaled at its precision by the engine.
+    Dispute {
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
     /// Release disputed funds back to available.
-    Resolve { client: ClientId, tx: TxId },
+    ///
+    /// Resolves the given part of the disputed portion, or all of it.
+    Resolve {
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
     /// Reverse a disputed deposit; removes held funds and freezes account.
-    Chargeback { client: ClientId, tx: TxId },
+    ///
+    /// Charges back the given part of the disputed portion, or all of it.
+    Chargeback {
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+    /// Reverse a chargeback after a successful re-presentment; re-credits the funds.
+    ///
+    /// Re-presents the given part of the charged back portion, or all of it.
+    Represent {
+        client: ClientId,
+        tx: TxId,
+        amount: Option<Decimal>,
+        timestamp: Option<Timestamp>,
+        metadata: Metadata,
+    },
+}
+
+/// Identifier of the operator applying an admin transaction.
+pub type OperatorId = String;
+
+/// Action of an admin transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminAction {
+    /// Freeze the account.
+    Lock,
+    /// Unfreeze the account, e.g. once a chargeback was reviewed.
+    Unlock,
+    /// Credit (positive amount) or debit (negative amount) the available balance.
+    Adjustment {
+        asset: Asset,
+        amount: Amount,
+        /// Reason code of the adjustment, for audit.
+        reason: String,
+    },
 }
 
-/// State of a deposit for dispute tracking.
+impl AdminAction {
+    /// Return the action name, as in the CSV `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminAction::Lock => "lock",
+            AdminAction::Unlock => "unlock",
+            AdminAction::Adjustment { .. } => "adjustment",
+        }
+    }
+}
+
+impl Transaction {
+    /// Return the pass-through metadata of the transaction.
+    pub fn metadata(&self) -> &Metadata {
+        match self {
+            Transaction::Deposit { metadata, .. }
+            | Transaction::Withdrawal { metadata, .. }
+            | Transaction::Authorize { metadata, .. }
+            | Transaction::Capture { metadata, .. }
+            | Transaction::Void { metadata, .. }
+            | Transaction::Transfer { metadata, .. }
+            | Transaction::Admin { metadata, .. }
+            | Transaction::Dispute { metadata, .. }
+            | Transaction::Resolve { metadata, .. }
+            | Transaction::Chargeback { metadata, .. }
+            | Transaction::Represent { metadata, .. } => metadata,
+        }
+    }
+
+    /// Return the client of the transaction (the sender of a transfer).
+    pub fn client(&self) -> ClientId {
+        match self {
+            Transaction::Transfer { from, .. } => *from,
+            Transaction::Deposit { client, .. }
+            | Transaction::Withdrawal { client, .. }
+            | Transaction::Authorize { client, .. }
+            | Transaction::Capture { client, .. }
+            | Transaction::Void { client, .. }
+            | Transaction::Admin { client, .. }
+            | Transaction::Dispute { client, .. }
+            | Transaction::Resolve { client, .. }
+            | Transaction::Chargeback { client, .. }
+            | Transaction::Represent { client, .. } => *client,
+        }
+    }
+
+    /// Return the transaction ID, or the ID of the transaction referred to (disputes,
+    /// captures, ...).
+    pub fn tx(&self) -> TxId {
+        match self {
+            Transaction::Deposit { tx, .. }
+            | Transaction::Withdrawal { tx, .. }
+            | Transaction::Authorize { tx, .. }
+            | Transaction::Capture { tx, .. }
+            | Transaction::Void { tx, .. }
+            | Transaction::Transfer { tx, .. }
+            | Transaction::Admin { tx, .. }
+            | Transaction::Dispute { tx, .. }
+            | Transaction::Resolve { tx, .. }
+            | Transaction::Chargeback { tx, .. }
+            | Transaction::Represent { tx, .. } => *tx,
+        }
+    }
+
+    /// Return the time of the transaction, if known.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        match self {
+            Transaction::Deposit { timestamp, .. }
+            | Transaction::Withdrawal { timestamp, .. }
+            | Transaction::Authorize { timestamp, .. }
+            | Transaction::Capture { timestamp, .. }
+            | Transaction::Void { timestamp, .. }
+            | Transaction::Transfer { timestamp, .. }
+            | Transaction::Admin { timestamp, .. }
+            | Transaction::Dispute { timestamp, .. }
+            | Transaction::Resolve { timestamp, .. }
+            | Transaction::Chargeback { timestamp, .. }
+            | Transaction::Represent { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// Return the time of the transaction, to set it when unknown.
+    pub(crate) fn timestamp_mut(&mut self) -> &mut Option<Timestamp> {
+        match self {
+            Transaction::Deposit { timestamp, .. }
+            | Transaction::Withdrawal { timestamp, .. }
+            | Transaction::Authorize { timestamp, .. }
+            | Transaction::Capture { timestamp, .. }
+            | Transaction::Void { timestamp, .. }
+            | Transaction::Transfer { timestamp, .. }
+            | Transaction::Admin { timestamp, .. }
+            | Transaction::Dispute { timestamp, .. }
+            | Transaction::Resolve { timestamp, .. }
+            | Transaction::Chargeback { timestamp, .. }
+            | Transaction::Represent { timestamp, .. } => timestamp,
+        }
+    }
+}
+
+/// State of a deposit (or disputable withdrawal) for dispute tracking.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum DepositState {
     /// Deposit is valid and can be disputed.
     #[default]
     Ok,
-    /// Deposit is currently under dispute.
+    /// Deposit is currently under dispute, in full or in part.
     Disputed,
-    // Chargeback is a final state
+    /// Deposit was charged back, in full or in part, and is not under dispute.
+    ///
+    /// The record is retained so the chargeback can be reversed by a re-presentment.
+    ChargedBack,
 }
 
 /// Record of a deposit for dispute tracking.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DepositRecord {
     /// The client who made the deposit.
     pub client: ClientId,
+    /// The deposited asset, disputes hold funds in this asset.
+    pub asset: Asset,
     /// The deposited amount.
     pub amount: Amount,
+    /// The portion of `amount` under dispute, the sum of concurrent partial disputes.
+    pub disputed: Amount,
+    /// The portion of `amount` charged back and not re-presented.
+    pub charged_back: Amount,
+    /// Time of the deposit, to bound the dispute window.
+    pub timestamp: Timestamp,
     /// Current dispute state.
     pub state: DepositState,
 }
 
+/// Record of a withdrawal for dispute tracking, when withdrawals are disputable.
+///
+/// Same layout as a deposit record, the transaction kind is given by where it is stored.
+pub type WithdrawalRecord = DepositRecord;
+
 impl DepositRecord {
     /// Create a new deposit record in the `Ok` state.
-    pub fn new(client: ClientId, amount: Amount) -> Self {
+    pub fn new(client: ClientId, asset: Asset, amount: Amount, timestamp: Timestamp) -> Self {
         Self {
             client,
+            asset,
             amount,
+            disputed: Amount::default(),
+            charged_back: Amount::default(),
+            timestamp,
             state: DepositState::Ok,
         }
     }
+
+    /// Return the amount that can still be disputed, `None` if the disputed and
+    /// charged back portions put it out of range.
+    pub fn undisputed(&self) -> Option<Amount> {
+        self.amount
+            .checked_sub(self.disputed)?
+            .checked_sub(self.charged_back)
+    }
+
+    /// Update the disputed portion, and the state accordingly.
+    pub fn set_disputed(&mut self, disputed: Amount) {
+        self.disputed = disputed;
+        self.update_state();
+    }
+
+    /// Update the charged back portion, and the state accordingly.
+    pub fn set_charged_back(&mut self, charged_back: Amount) {
+        self.charged_back = charged_back;
+        self.update_state();
+    }
+
+    /// An open dispute takes precedence over a past chargeback.
+    fn update_state(&mut self) {
+        let zero = Amount::default();
+        self.state = if self.disputed > zero {
+            DepositState::Disputed
+        } else if self.charged_back > zero {
+            DepositState::ChargedBack
+        } else {
+            DepositState::Ok
+        };
+    }
+}
+
+/// State of a withdrawal authorization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuthorizationState {
+    /// Funds are reserved, waiting for capture or void.
+    #[default]
+    Pending,
+    /// Withdrawal was finalized (final state).
+    Captured,
+    /// Withdrawal was cancelled (final state).
+    Voided,
+}
+
+/// Record of a withdrawal authorization, referenced by its capture or void.
+///
+/// Records are kept in their final state to reject a second capture or void.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationRecord {
+    /// The client who authorized the withdrawal.
+    pub client: ClientId,
+    /// The reserved asset.
+    pub asset: Asset,
+    /// The reserved amount.
+    pub amount: Amount,
+    /// Current authorization state.
+    pub state: AuthorizationState,
+}
+
+impl AuthorizationRecord {
+    /// Create a new authorization record in the `Pending` state.
+    pub fn new(client: ClientId, asset: Asset, amount: Amount) -> Self {
+        Self {
+            client,
+            asset,
+            amount,
+            state: AuthorizationState::Pending,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -71,11 +478,81 @@ mod tests {
     #[test]
     fn deposit_record_size() {
         // DepositRecord layout:
-        // - amount: 8 bytes
-        // - client: 2 bytes
+        // - amount: 16 bytes (value, scale, padding)
+        // - disputed: 16 bytes
+        // - charged_back: 16 bytes
+        // - client: 8 bytes
+        // - timestamp: 8 bytes
+        // - asset: 7 bytes
         // - state: 1 byte
-        // - padding: 5 bytes
-        assert_eq!(std::mem::size_of::<DepositRecord>(), 16);
+        assert_eq!(std::mem::size_of::<Amount>(), 16);
+        assert_eq!(std::mem::size_of::<DepositRecord>(), 72);
+    }
+
+    #[test]
+    fn deposit_record_disputed_portion() {
+        let mut record =
+            DepositRecord::new(1, Asset::new("USD").unwrap(), Amount::from_scaled(100), 0);
+        record.set_disputed(Amount::from_scaled(30));
+        assert_eq!(record.state, DepositState::Disputed);
+        assert_eq!(record.undisputed(), Some(Amount::from_scaled(70)));
+
+        record.set_disputed(Amount::default());
+        assert_eq!(record.state, DepositState::Ok);
+        assert_eq!(record.undisputed(), Some(Amount::from_scaled(100)));
+    }
+
+    #[test]
+    fn deposit_record_charged_back_portion() {
+        let mut record =
+            DepositRecord::new(1, Asset::new("USD").unwrap(), Amount::from_scaled(100), 0);
+        record.set_disputed(Amount::from_scaled(30));
+        record.set_charged_back(Amount::from_scaled(50));
+        assert_eq!(record.state, DepositState::Disputed);
+        assert_eq!(record.undisputed(), Some(Amount::from_scaled(20)));
+
+        record.set_disputed(Amount::default());
+        assert_eq!(record.state, DepositState::ChargedBack);
+
+        record.set_charged_back(Amount::default());
+        assert_eq!(record.state, DepositState::Ok);
+    }
+
+    #[test]
+    fn deposit_record_undisputed_out_of_range() {
+        let mut record = DepositRecord::new(
+            1,
+            Asset::new("USD").unwrap(),
+            Amount::from_scaled(i64::MIN),
+            0,
+        );
+        record.set_disputed(Amount::from_scaled(1));
+        assert_eq!(record.undisputed(), None);
+    }
+
+    #[test]
+    fn asset_parse_and_display() {
+        let usdc: Asset = "USDC".parse().unwrap();
+        assert_eq!(usdc.as_str(), "USDC");
+        assert_eq!(usdc.to_string(), "USDC");
+        assert_eq!(Asset::new("stETH").unwrap().as_str(), "stETH");
+        assert_eq!(Asset::new("USDC.e").unwrap().as_str(), "USDC.e");
+        assert_eq!(Asset::new("ABCDEFG").unwrap().as_str(), "ABCDEFG");
+    }
+
+    #[test]
+    fn asset_rejects_invalid_codes() {
+        for code in ["", "ABCDEFGH", "US D", "US,D", "€UR"] {
+            assert_eq!(
+                code.parse::<Asset>(),
+                Err(ParseAssetError(code.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn asset_is_case_sensitive() {
+        assert_ne!(Asset::new("usd"), Asset::new("USD"));
     }
 
     #[test]
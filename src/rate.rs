@@ -0,0 +1,240 @@
+//! Fixed-point rates for multiplying amounts (fees, interest, FX conversions).
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::Amount;
+use crate::amount::{ParseAmountError, Precision, RoundingMode, div_round};
+
+/// Fixed-point rate with 9 decimal places, stored as a scaled integer.
+///
+/// `Rate` is a plain multiplier: `1.5` scales an amount by 150%, `0.0125` takes 1.25%
+/// of it. Rates can be negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Rate(i64);
+
+impl Rate {
+    const PRECISION: Precision = Precision::new(9, RoundingMode::Exact).unwrap();
+    const SCALE: i64 = 1_000_000_000;
+
+    /// The identity rate (100%).
+    pub const ONE: Rate = Rate(Self::SCALE);
+
+    /// Create a Rate from a pre-scaled integer value (9 decimal places).
+    pub fn from_scaled(value: i64) -> Self {
+        Rate(value)
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ParseAmountError;
+
+    /// Parse a decimal string exactly, with at most 9 decimal places.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s, Self::PRECISION).map(|amount| Rate(amount.scaled()))
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Amount::from_scaled_at(self.0, Self::PRECISION).fmt(f)
+    }
+}
+
+/// A rate expressed in basis points: `1 bp = 0.01%`, so `10_000 bp = 100%`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct BasisPoints(pub i64);
+
+impl TryFrom<BasisPoints> for Rate {
+    type Error = ArithmeticError;
+
+    /// Convert basis points to a rate, returns [`ArithmeticError::Overflow`] if out
+    /// of the rate range.
+    fn try_from(bps: BasisPoints) -> Result<Self, Self::Error> {
+        bps.0
+            .checked_mul(Rate::SCALE / 10_000)
+            .map(Rate)
+            .ok_or(ArithmeticError::Overflow)
+    }
+}
+
+impl fmt::Display for BasisPoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}bp", self.0)
+    }
+}
+
+/// Error returned by fixed-point [`Amount`] multiplication and division.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ArithmeticError {
+    #[error("arithmetic overflow")]
+    Overflow,
+    #[error("result is not exact and rounding is not allowed")]
+    Inexact,
+    #[error("division by zero")]
+    DivisionByZero,
+}
+
+impl Amount {
+    /// Multiply by a rate, rounding the result to the amount's scale with `rounding`.
+    ///
+    /// Returns [`ArithmeticError::Inexact`] if the product needs rounding and
+    /// `rounding` is [`RoundingMode::Exact`].
+    pub fn mul_rate(self, rate: Rate, rounding: RoundingMode) -> Result<Amount, ArithmeticError> {
+        // i64 * i64 always fits in i128
+        let product = i128::from(self.scaled()) * i128::from(rate.0);
+        let value = div_round(product, i128::from(Rate::SCALE), rounding)
+            .ok_or(ArithmeticError::Inexact)?;
+        i64::try_from(value)
+            .map(|value| self.with_scaled(value))
+            .map_err(|_| ArithmeticError::Overflow)
+    }
+
+    /// Split into `n` parts that always sum exactly to the original amount.
+    ///
+    /// Parts differ by at most one unit: the remainder is distributed one unit at a
+    /// time to the first parts. The parts are computed lazily, so any `n` is
+    /// accepted without allocating.
+    pub fn split(self, n: usize) -> Result<impl ExactSizeIterator<Item = Amount>, ArithmeticError> {
+        if n == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        let value = i128::from(self.scaled());
+        let (base, remainder) = (value / n as i128, value % n as i128);
+        // `remainder` has the sign of `value`, spread it as +/-1 units
+        Ok((0..n).map(move |i| {
+            let extra = if (i as i128) < remainder.abs() {
+                remainder.signum()
+            } else {
+                0
+            };
+            // |base + extra| <= |value|, so it fits back in an i64
+            self.with_scaled((base + extra) as i64)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amount(value: i64) -> Amount {
+        Amount::from_scaled(value)
+    }
+
+    #[test]
+    fn parse_and_display() {
+        assert_eq!("0.0125".parse(), Ok(Rate(12_500_000)));
+        assert_eq!("1".parse(), Ok(Rate::ONE));
+        assert_eq!("-0.5".parse(), Ok(Rate(-500_000_000)));
+        assert!("0.0000000001".parse::<Rate>().is_err());
+        assert_eq!(Rate(12_500_000).to_string(), "0.0125");
+    }
+
+    #[test]
+    fn basis_points_to_rate() {
+        assert_eq!(Rate::try_from(BasisPoints(10_000)), Ok(Rate::ONE));
+        assert_eq!(
+            Rate::try_from(BasisPoints(125)),
+            Ok("0.0125".parse().unwrap())
+        );
+        assert_eq!(
+            Rate::try_from(BasisPoints(-125)),
+            Ok(Rate::from_scaled(-12_500_000))
+        );
+        assert_eq!(
+            Rate::try_from(BasisPoints(i64::MAX)),
+            Err(ArithmeticError::Overflow)
+        );
+        assert_eq!(
+            Rate::try_from(BasisPoints(i64::MIN / 10)),
+            Err(ArithmeticError::Overflow)
+        );
+    }
+
+    #[test]
+    fn mul_rate_computes_fee() {
+        // 1.25% of 100.0000
+        let rate = Rate::try_from(BasisPoints(125)).unwrap();
+        let fee = amount(1_000_000).mul_rate(rate, RoundingMode::Exact);
+        assert_eq!(fee, Ok(amount(12_500)));
+    }
+
+    #[test]
+    fn mul_rate_rounding_modes() {
+        // 0.0005 * 50% = 0.00025, between two units
+        let value = amount(5);
+        let half = Rate::try_from(BasisPoints(5_000)).unwrap();
+        assert_eq!(
+            value.mul_rate(half, RoundingMode::HalfAwayFromZero),
+            Ok(amount(3))
+        );
+        assert_eq!(value.mul_rate(half, RoundingMode::HalfEven), Ok(amount(2)));
+        assert_eq!(
+            value.mul_rate(half, RoundingMode::TowardZero),
+            Ok(amount(2))
+        );
+        assert_eq!(
+            value.mul_rate(half, RoundingMode::Exact),
+            Err(ArithmeticError::Inexact)
+        );
+        assert_eq!(
+            amount(-5).mul_rate(half, RoundingMode::HalfAwayFromZero),
+            Ok(amount(-3))
+        );
+    }
+
+    #[test]
+    fn mul_rate_overflow() {
+        let double = Rate::from_scaled(2 * Rate::SCALE);
+        assert_eq!(
+            amount(i64::MAX).mul_rate(double, RoundingMode::Exact),
+            Err(ArithmeticError::Overflow)
+        );
+        // Large intermediate products don't overflow
+        assert_eq!(
+            amount(i64::MAX).mul_rate(Rate::ONE, RoundingMode::Exact),
+            Ok(amount(i64::MAX))
+        );
+    }
+
+    fn split(value: i64, n: usize) -> Result<Vec<Amount>, ArithmeticError> {
+        amount(value).split(n).map(Iterator::collect)
+    }
+
+    #[test]
+    fn split_distributes_remainder() {
+        assert_eq!(split(100, 3), Ok(vec![amount(34), amount(33), amount(33)]));
+        assert_eq!(
+            split(-100, 3),
+            Ok(vec![amount(-34), amount(-33), amount(-33)])
+        );
+        assert_eq!(
+            split(2, 4),
+            Ok(vec![amount(1), amount(1), amount(0), amount(0)])
+        );
+        assert_eq!(split(7, 0), Err(ArithmeticError::DivisionByZero));
+    }
+
+    #[test]
+    fn split_in_many_parts_is_lazy() {
+        let mut parts = amount(-2).split(usize::MAX).unwrap();
+        assert_eq!(parts.len(), usize::MAX);
+        assert_eq!(parts.next(), Some(amount(-1)));
+        assert_eq!(parts.nth(1_000_000), Some(amount(0)));
+    }
+
+    #[test]
+    fn split_parts_sum_to_original() {
+        for value in [0, 1, -1, 999_999, i64::MAX, i64::MIN] {
+            for n in [1, 2, 3, 7, 10] {
+                let parts = split(value, n).unwrap();
+                assert_eq!(parts.len(), n);
+                let sum: i128 = parts.iter().map(|p| i128::from(p.scaled())).sum();
+                assert_eq!(sum, i128::from(value));
+            }
+        }
+    }
+}
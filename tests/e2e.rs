@@ -1,10 +1,20 @@
 use std::process::Command;
 
 fn run(fixture: &str) -> (String, String, bool) {
+    run_with_args(fixture, &[])
+}
+
+fn run_with_args(fixture: &str, args: &[&str]) -> (String, String, bool) {
+    run_with_log(fixture, args, "warn")
+}
+
+fn run_with_log(fixture: &str, args: &[&str], log: &str) -> (String, String, bool) {
     let path = format!("tests/fixtures/{fixture}");
     let output = Command::new(env!("CARGO_BIN_EXE_txs-eng"))
+        .args(args)
         .arg(&path)
-        .env("RUST_LOG", "warn")
+        .env("RUST_LOG", log)
+        .env("NO_COLOR", "1")
         .output()
         .expect("failed to run binary");
 
@@ -21,11 +31,14 @@ fn valid_transactions() {
     assert!(stderr.is_empty());
 
     let mut lines: Vec<&str> = stdout.lines().collect();
-    assert_eq!(lines[0], "client,available,held,total,locked");
+    assert_eq!(
+        lines[0],
+        "client,asset,available,held,reserved,total,locked"
+    );
     lines.remove(0);
     lines.sort();
-    assert_eq!(lines[0], "1,75,0,75,false");
-    assert_eq!(lines[1], "2,50,0,50,false");
+    assert_eq!(lines[0], "1,USD,75,0,0,75,false");
+    assert_eq!(lines[1], "2,USD,50,0,0,50,false");
 }
 
 #[test]
@@ -37,6 +50,261 @@ fn errors_warn_but_do_not_block() {
     assert!(stderr.contains("missing amount"));
 
     let lines: Vec<&str> = stdout.lines().collect();
-    assert_eq!(lines[0], "client,available,held,total,locked");
-    assert_eq!(lines[1], "1,75,0,75,false");
+    assert_eq!(
+        lines[0],
+        "client,asset,available,held,reserved,total,locked"
+    );
+    assert_eq!(lines[1], "1,USD,75,0,0,75,false");
+}
+
+#[test]
+fn multi_asset_balances() {
+    let (stdout, stderr, success) = run("multi_asset.csv");
+
+    assert!(success);
+    assert!(stderr.is_empty());
+
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines[0],
+        "client,asset,available,held,reserved,total,locked"
+    );
+    lines.remove(0);
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "1,EUR,0,20,0,20,false",
+            "1,USD,75,0,0,75,false",
+            "2,USD,10,0,0,10,false",
+            "2,USDC,0.5,0,0,0.5,false",
+        ]
+    );
+}
+
+#[test]
+fn external_ids_are_interned() {
+    let (stdout, stderr, success) = run_with_args("external_ids.csv", &["--intern-ids"]);
+
+    assert!(success);
+    assert!(stderr.is_empty());
+
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.remove(0);
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "3f2c9a1e-0b7d-4c2e-9f3a-1d2e3f4a5b6c,USD,75,0,0,75,false",
+            "acct-bob,USD,0,50,0,50,false",
+        ]
+    );
+}
+
+#[test]
+fn transfers_move_funds_between_clients() {
+    let (stdout, _, success) = run("transfers.csv");
+
+    assert!(success);
+
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.remove(0);
+    lines.sort();
+    assert_eq!(lines, ["1,USD,60,0,0,60,false", "2,USD,25,0,0,25,false"]);
+}
+
+#[test]
+fn transfers_log_the_receiver() {
+    let (_, stderr, success) = run_with_log("transfers.csv", &[], "txs_eng=info");
+
+    assert!(success);
+    assert!(stderr.contains("transfer applied client=1 to=2 tx=2"));
+    assert!(stderr.contains("transfer skipped client=2 to=3 tx=3"));
+}
+
+#[test]
+fn admin_transactions_are_audited() {
+    let (stdout, stderr, success) = run("admin.csv");
+
+    assert!(success);
+    // Rejected admin transactions are logged apart from client ones
+    assert!(stderr.contains("txs_eng::admin"));
+    assert!(stderr.contains("ops-jane"));
+    assert!(stderr.contains("admin lock skipped"));
+    assert!(stderr.contains("admin transaction by operator ops-jane failed: client 2 not found"));
+
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "client,asset,available,held,reserved,total,locked",
+            "1,USD,45,0,0,45,false"
+        ]
+    );
+}
+
+#[test]
+fn metadata_columns_follow_rejections() {
+    let (stdout, stderr, success) = run_with_log("metadata.csv", &[], "txs_eng=info");
+
+    assert!(success);
+    // Extra columns are attached to the logged results, not parsed as errors
+    assert!(stderr.contains("withdrawal skipped"));
+    assert!(stderr.contains("PAYOUT-7"));
+    assert!(stderr.contains("INV-1"));
+    // Also to the rows which can't be parsed
+    assert!(stderr.contains("INV-9"));
+
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "client,asset,available,held,reserved,total,locked",
+            "1,USD,60,0,0,60,false"
+        ]
+    );
+}
+
+#[test]
+fn config_file_sets_policies() {
+    let (stdout, _, success) = run_with_args(
+        "policies.csv",
+        &["--config", "tests/fixtures/policies.toml"],
+    );
+
+    assert!(success);
+    // Uncovered dispute and re-dispute rejected, no account for the unknown client
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "client,asset,available,held,reserved,total,locked",
+            "1,USD,50,0,0,50,false"
+        ]
+    );
+}
+
+#[test]
+fn invalid_config_file_fails() {
+    let (stdout, stderr, success) =
+        run_with_args("policies.csv", &["--config", "tests/fixtures/policies.csv"]);
+
+    assert!(!success);
+    assert!(stdout.is_empty());
+    assert!(stderr.contains("failed to load config"));
+}
+
+#[test]
+fn two_phase_withdrawals_report_reserved_funds() {
+    let (stdout, stderr, success) = run("two_phase.csv");
+
+    assert!(success);
+    assert!(stderr.is_empty());
+
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[1], "1,USD,60,0,10,70,false");
+}
+
+#[test]
+fn disk_store_matches_memory_store() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let store = dir.path().to_str().unwrap();
+
+    for fixture in ["multi_asset.csv", "two_phase.csv", "transfers.csv"] {
+        let (expected, _, _) = run(fixture);
+        // A single cached record, every dispute or capture reads from disk
+        let (stdout, stderr, success) =
+            run_with_args(fixture, &["--disk-store", store, "--cache-records", "1"]);
+
+        assert!(success);
+        assert!(stderr.is_empty());
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        let mut expected: Vec<&str> = expected.lines().collect();
+        lines.sort();
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
+    assert!(dir.path().join("records.bin").exists());
+}
+
+#[test]
+fn sharded_engine_matches_single_engine() {
+    for fixture in [
+        "multi_asset.csv",
+        "two_phase.csv",
+        "transfers.csv",
+        "admin.csv",
+        "day1.csv",
+    ] {
+        let (expected, _, _) = run(fixture);
+        let (stdout, _, success) = run_with_args(fixture, &["--shards", "3"]);
+
+        assert!(success);
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        let mut expected: Vec<&str> = expected.lines().collect();
+        lines.sort();
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
+}
+
+#[test]
+fn snapshots_continue_previous_runs() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let snapshot = dir.path().join("state.bin");
+    let snapshot = snapshot.to_str().unwrap();
+
+    let (_, stderr, success) = run_with_args("day1.csv", &["--save-snapshot", snapshot]);
+    assert!(success);
+    assert!(stderr.is_empty());
+
+    // Disputes a deposit of the first day, and reuses one of its tx IDs
+    let (stdout, _, success) = run_with_args(
+        "day2.csv",
+        &["--load-snapshot", snapshot, "--save-snapshot", snapshot],
+    );
+    assert!(success);
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    lines.remove(0);
+    lines.sort();
+    assert_eq!(lines, ["1,USD,20,0,0,20,true", "2,USD,45,0,0,45,false"]);
+
+    let (stdout, _, success) = run_with_args("valid.csv", &["--load-snapshot", snapshot]);
+    assert!(success);
+    assert!(stdout.contains("1,USD,20,0,0,20,true"));
+}
+
+#[test]
+fn invalid_snapshot_fails() {
+    let (stdout, stderr, success) = run_with_args(
+        "valid.csv",
+        &["--load-snapshot", "tests/fixtures/valid.csv"],
+    );
+
+    assert!(!success);
+    assert!(stdout.is_empty());
+    assert!(stderr.contains("failed to load snapshot"));
+}
+
+#[test]
+fn journal_replay_rebuilds_state() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let journal = dir.path().join("journal.bin");
+    let journal = journal.to_str().unwrap();
+
+    let (_, stderr, success) = run_with_args("day1.csv", &["--journal", journal]);
+    assert!(success);
+    assert!(stderr.is_empty());
+
+    // Replaying the first day's journal, then appending the second day's events
+    let (expected, _, _) = run_with_args("day2.csv", &["--replay", journal, "--journal", journal]);
+    let (stdout, _, success) = run_with_args("with_errors.csv", &["--replay", journal]);
+    assert!(success);
+    let mut lines: Vec<&str> = stdout.lines().collect();
+    let mut expected: Vec<&str> = expected.lines().collect();
+    lines.sort();
+    expected.sort();
+    // Only duplicate IDs in the last file
+    assert_eq!(lines, expected);
+    assert!(lines.contains(&"1,USD,20,0,0,20,true"));
 }